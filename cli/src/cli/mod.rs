@@ -11,6 +11,7 @@ use clap::{Args, Parser, Subcommand, ValueEnum};
 
 use crate::project_template;
 use inquire::Text;
+use server_lib::persistence::migrations;
 
 /// A fictional versioning CLI
 #[derive(Debug, Parser)] // requires `derive` feature
@@ -31,6 +32,12 @@ enum Commands {
         #[command(subcommand)]
         command: ClusterCommands,
     },
+
+    /// Database schema migrations
+    Migrate {
+        #[command(subcommand)]
+        command: MigrateCommands,
+    },
 }
 
 #[derive(Debug, Subcommand)]
@@ -41,6 +48,23 @@ enum ClusterCommands {
     }
 }
 
+#[derive(Debug, Subcommand)]
+enum MigrateCommands {
+    /// Lists migrations and whether they have been applied
+    Status {
+        /// Connection URL for task storage, `postgres://...` or `sqlite://...`
+        #[arg(long)]
+        database_url: String,
+    },
+
+    /// Applies pending migrations
+    Up {
+        /// Connection URL for task storage, `postgres://...` or `sqlite://...`
+        #[arg(long)]
+        database_url: String,
+    },
+}
+
 pub async fn start() {
     let args = Cli::parse();
 
@@ -52,6 +76,10 @@ pub async fn start() {
                 Ok(())
             }
         }
+        Commands::Migrate { command } => match command {
+            MigrateCommands::Status { database_url } => migration_status(&database_url).await,
+            MigrateCommands::Up { database_url } => migrate_up(&database_url).await,
+        },
     };
 
     if let Err(e) = result {
@@ -94,6 +122,34 @@ async fn init_project() -> Result<()> {
     Ok(())
 }
 
+async fn migration_status(database_url: &str) -> Result<()> {
+    for migration in migrations::database_status(database_url).await? {
+        let state = if migration.applied_at.is_some() {
+            "applied"
+        } else {
+            "pending"
+        };
+
+        println!("{:>4}  {:<8} {}", migration.version, state, migration.description);
+    }
+
+    Ok(())
+}
+
+async fn migrate_up(database_url: &str) -> Result<()> {
+    let applied = migrations::migrate_database(database_url).await?;
+
+    if applied.is_empty() {
+        println!("Database is up to date");
+    }
+
+    for version in applied {
+        println!("Applied migration {}", version);
+    }
+
+    Ok(())
+}
+
 async fn deploy_project() -> Result<()> {
     Ok(())
 }
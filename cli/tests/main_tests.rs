@@ -30,3 +30,23 @@ fn initialized_the_project_with_new_dir() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn migrates_a_sqlite_database() -> Result<()> {
+    let temp_dir = assert_fs::TempDir::new()?;
+    let database_url = format!("sqlite://{}", temp_dir.child("tasks.db").path().display());
+
+    Command::cargo_bin("cli")?
+        .args(["migrate", "up", "--database-url", &database_url])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Applied migration 1"));
+
+    Command::cargo_bin("cli")?
+        .args(["migrate", "status", "--database-url", &database_url])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("applied"));
+
+    Ok(())
+}
@@ -38,4 +38,21 @@ export function task<P extends Record<string, TSchema>>(
   });
 }
 
+export interface TaskDefinitionRegistration {
+  definitionId: string;
+  name: string;
+  description: string;
+  parameters: string;
+}
+
+/** The registered tasks as `RegisterTaskDefinition` requests, parameters as JSON Schema. */
+export function taskDefinitions(): TaskDefinitionRegistration[] {
+  return [...taskRegistry.values()].map(({ config }) => ({
+    definitionId: config.id,
+    name: config.name,
+    description: config.description,
+    parameters: JSON.stringify(Type.Object(config.parameters)),
+  }));
+}
+
 export { Type };
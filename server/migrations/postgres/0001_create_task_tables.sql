@@ -0,0 +1,34 @@
+-- `IF NOT EXISTS` lets databases created before versioned migrations adopt this baseline.
+CREATE TABLE IF NOT EXISTS svppl_task (
+    seq_id BIGSERIAL NOT NULL,
+    queue_id TEXT NOT NULL,
+    partition_id SMALLINT NOT NULL,
+    payload BYTEA NOT NULL,
+    status SMALLINT NOT NULL,
+    scheduled_at BIGINT NOT NULL DEFAULT 0,
+    deadline_at BIGINT,
+    timeout_ms BIGINT,
+    content_type TEXT,
+    lease_token TEXT,
+    leased_at BIGINT,
+    lease_expires_at BIGINT,
+    last_error TEXT,
+    attempts INT NOT NULL DEFAULT 0,
+    max_attempts INT NOT NULL,
+    PRIMARY KEY (queue_id, partition_id, seq_id)
+);
+
+CREATE TABLE IF NOT EXISTS svppl_retry_policy (
+    queue_id TEXT NOT NULL PRIMARY KEY,
+    max_attempts INT NOT NULL,
+    initial_backoff_ms BIGINT NOT NULL,
+    max_backoff_ms BIGINT NOT NULL,
+    backoff_multiplier DOUBLE PRECISION NOT NULL,
+    jitter DOUBLE PRECISION NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS svppl_idx_task_status ON svppl_task(status);
+
+-- Covers the due-task lookup in `lease_tasks`.
+CREATE INDEX IF NOT EXISTS svppl_idx_task_scheduled_at
+ON svppl_task(queue_id, partition_id, status, scheduled_at, seq_id);
@@ -0,0 +1,5 @@
+ALTER TABLE svppl_task ADD COLUMN priority SMALLINT NOT NULL DEFAULT 0;
+
+-- Covers the claim in `lease_tasks`, which takes the highest priority due tasks first.
+CREATE INDEX svppl_idx_task_priority
+ON svppl_task(queue_id, partition_id, status, priority DESC, scheduled_at, seq_id);
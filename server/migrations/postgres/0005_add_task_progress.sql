@@ -0,0 +1,2 @@
+ALTER TABLE svppl_task ADD COLUMN progress_percent REAL;
+ALTER TABLE svppl_task ADD COLUMN progress_detail TEXT;
@@ -0,0 +1,16 @@
+ALTER TABLE svppl_task ADD COLUMN parent_failure_policy SMALLINT NOT NULL DEFAULT 0;
+
+-- A task waits on each of its parents. Parents found through an idempotency key may sit in
+-- another partition of the queue.
+CREATE TABLE svppl_task_dependency (
+    queue_id TEXT NOT NULL,
+    partition_id SMALLINT NOT NULL,
+    seq_id BIGINT NOT NULL,
+    parent_partition_id SMALLINT NOT NULL,
+    parent_seq_id BIGINT NOT NULL,
+    PRIMARY KEY (queue_id, partition_id, seq_id, parent_partition_id, parent_seq_id)
+);
+
+-- Covers walking a graph from parents to the tasks depending on them.
+CREATE INDEX svppl_idx_task_dependency_parent
+ON svppl_task_dependency(queue_id, parent_partition_id, parent_seq_id);
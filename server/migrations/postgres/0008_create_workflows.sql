@@ -0,0 +1,23 @@
+CREATE TABLE svppl_workflow (
+    workflow_id TEXT NOT NULL PRIMARY KEY,
+    workflow_type TEXT NOT NULL,
+    queue_id TEXT NOT NULL,
+    partition_id SMALLINT NOT NULL,
+    status SMALLINT NOT NULL,
+    result BYTEA,
+    error TEXT,
+    last_event_id BIGINT NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+-- Append only, `event_id` counts up from 1 per workflow.
+CREATE TABLE svppl_workflow_event (
+    workflow_id TEXT NOT NULL,
+    event_id BIGINT NOT NULL,
+    kind SMALLINT NOT NULL,
+    name TEXT,
+    payload BYTEA,
+    error TEXT,
+    recorded_at BIGINT NOT NULL,
+    PRIMARY KEY (workflow_id, event_id)
+);
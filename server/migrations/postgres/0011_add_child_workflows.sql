@@ -0,0 +1,2 @@
+-- Set on child workflows, whose parent is told how they finish.
+ALTER TABLE svppl_workflow ADD COLUMN parent_workflow_id TEXT;
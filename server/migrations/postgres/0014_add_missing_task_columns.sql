@@ -0,0 +1,11 @@
+-- Databases created before versioned migrations kept their own task table through migration
+-- 1, which may lack columns added since. Tables migration 1 created already have them all.
+ALTER TABLE svppl_task ADD COLUMN IF NOT EXISTS timeout_ms BIGINT;
+ALTER TABLE svppl_task ADD COLUMN IF NOT EXISTS content_type TEXT;
+ALTER TABLE svppl_task ADD COLUMN IF NOT EXISTS lease_token TEXT;
+ALTER TABLE svppl_task ADD COLUMN IF NOT EXISTS leased_at BIGINT;
+ALTER TABLE svppl_task ADD COLUMN IF NOT EXISTS lease_expires_at BIGINT;
+ALTER TABLE svppl_task ADD COLUMN IF NOT EXISTS last_error TEXT;
+ALTER TABLE svppl_task ADD COLUMN IF NOT EXISTS attempts INT NOT NULL DEFAULT 0;
+-- Existing tasks get the default retry policy's attempts, enqueues always set it.
+ALTER TABLE svppl_task ADD COLUMN IF NOT EXISTS max_attempts INT NOT NULL DEFAULT 5;
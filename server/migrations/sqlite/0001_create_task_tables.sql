@@ -0,0 +1,32 @@
+-- `IF NOT EXISTS` lets databases created before versioned migrations adopt this baseline.
+
+-- `seq_id` is the rowid, so it is unique across every queue rather than per partition.
+CREATE TABLE IF NOT EXISTS svppl_task (
+    seq_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    queue_id TEXT NOT NULL,
+    partition_id INTEGER NOT NULL,
+    payload BLOB NOT NULL,
+    status INTEGER NOT NULL,
+    scheduled_at INTEGER NOT NULL DEFAULT 0,
+    deadline_at INTEGER,
+    timeout_ms INTEGER,
+    content_type TEXT,
+    lease_token TEXT,
+    leased_at INTEGER,
+    lease_expires_at INTEGER,
+    last_error TEXT,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS svppl_idx_task_scheduled_at
+ON svppl_task(queue_id, partition_id, status, scheduled_at, seq_id);
+
+CREATE TABLE IF NOT EXISTS svppl_retry_policy (
+    queue_id TEXT NOT NULL PRIMARY KEY,
+    max_attempts INTEGER NOT NULL,
+    initial_backoff_ms INTEGER NOT NULL,
+    max_backoff_ms INTEGER NOT NULL,
+    backoff_multiplier REAL NOT NULL,
+    jitter REAL NOT NULL
+);
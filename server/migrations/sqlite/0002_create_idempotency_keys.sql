@@ -0,0 +1,12 @@
+-- Keys are unique per queue, across partitions, and point at the task first enqueued with them.
+CREATE TABLE svppl_idempotency_key (
+    queue_id TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    partition_id INTEGER NOT NULL,
+    seq_id INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    PRIMARY KEY (queue_id, idempotency_key)
+);
+
+CREATE INDEX svppl_idx_idempotency_key_expires_at
+ON svppl_idempotency_key(queue_id, expires_at);
@@ -0,0 +1 @@
+ALTER TABLE svppl_task ADD COLUMN result BLOB;
@@ -0,0 +1,14 @@
+CREATE TABLE svppl_schedule (
+    schedule_id TEXT NOT NULL PRIMARY KEY,
+    cron TEXT NOT NULL,
+    timezone TEXT NOT NULL,
+    queue_id TEXT NOT NULL,
+    partition_id INTEGER NOT NULL,
+    payload BLOB NOT NULL,
+    content_type TEXT,
+    misfire_policy INTEGER NOT NULL,
+    next_fire_at INTEGER NOT NULL
+);
+
+-- Covers the due lookup every node polls with.
+CREATE INDEX svppl_idx_schedule_next_fire_at ON svppl_schedule(next_fire_at);
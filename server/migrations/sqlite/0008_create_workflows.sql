@@ -0,0 +1,23 @@
+CREATE TABLE svppl_workflow (
+    workflow_id TEXT NOT NULL PRIMARY KEY,
+    workflow_type TEXT NOT NULL,
+    queue_id TEXT NOT NULL,
+    partition_id INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    result BLOB,
+    error TEXT,
+    last_event_id INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+-- Append only, `event_id` counts up from 1 per workflow.
+CREATE TABLE svppl_workflow_event (
+    workflow_id TEXT NOT NULL,
+    event_id INTEGER NOT NULL,
+    kind INTEGER NOT NULL,
+    name TEXT,
+    payload BLOB,
+    error TEXT,
+    recorded_at INTEGER NOT NULL,
+    PRIMARY KEY (workflow_id, event_id)
+);
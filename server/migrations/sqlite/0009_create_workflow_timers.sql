@@ -0,0 +1,13 @@
+ALTER TABLE svppl_workflow_event ADD COLUMN fire_at INTEGER;
+
+-- A row per timer that has yet to fire, `timer_id` is the event that set it.
+CREATE TABLE svppl_workflow_timer (
+    workflow_id TEXT NOT NULL,
+    timer_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    fire_at INTEGER NOT NULL,
+    PRIMARY KEY (workflow_id, timer_id)
+);
+
+-- Covers the due lookup every node polls with.
+CREATE INDEX svppl_idx_workflow_timer_fire_at ON svppl_workflow_timer(fire_at);
@@ -0,0 +1,2 @@
+-- The signal a workflow is blocked on, set by the replay that waits for it.
+ALTER TABLE svppl_workflow ADD COLUMN awaiting_signal TEXT;
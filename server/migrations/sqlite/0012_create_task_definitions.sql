@@ -0,0 +1,10 @@
+-- Append only, a row per version of every task definition.
+CREATE TABLE svppl_task_definition (
+    definition_id TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    parameters TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (definition_id, version)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE svppl_task ADD COLUMN definition_id TEXT;
+ALTER TABLE svppl_task ADD COLUMN definition_version INTEGER;
@@ -0,0 +1,6 @@
+-- The priority index from migration 3 covers every task lookup by queue partition and status,
+-- these only cost writes. `svppl_idx_task_due` replaced `svppl_idx_task_scheduled_at` in
+-- some databases.
+DROP INDEX IF EXISTS svppl_idx_task_status;
+DROP INDEX IF EXISTS svppl_idx_task_scheduled_at;
+DROP INDEX IF EXISTS svppl_idx_task_due;
@@ -1,20 +1,29 @@
 use crate::{
     cluster_monitor::{ClusterMonitorConfig, ClusterMonitorHandle},
     partition_resolver::{self, PartitionResolverHandle},
+    persistence::{
+        common::TaskQueue,
+        postgres::{self, PersistencePostgres},
+        sqlite::{self, PersistenceSqlite},
+    },
     resolve_addr,
     rpc::server::RpcServerHandle,
+    scheduler::{self, SchedulerHandle},
 };
 use anyhow::Context;
+use std::{sync::Arc, time::Duration};
 use tracing::info;
 
 pub struct AppHandle {
     rpc_handle: RpcServerHandle,
     cluster_monitor_handle: ClusterMonitorHandle,
     partition_resolver_handle: PartitionResolverHandle,
+    scheduler_handle: SchedulerHandle,
 }
 
 impl AppHandle {
     pub async fn shutdown(self) -> anyhow::Result<()> {
+        self.scheduler_handle.shutdown().await?;
         self.rpc_handle.shutdown().await?;
         self.cluster_monitor_handle.shutdown().await?;
         self.partition_resolver_handle.shutdown().await?;
@@ -26,6 +35,53 @@ impl AppHandle {
 pub async fn start(opts: crate::opts::Opts) -> anyhow::Result<AppHandle> {
     info!(opts = ?opts, "app_start");
 
+    // The backend is picked from the database URL's scheme.
+    if sqlite::is_sqlite_url(&opts.database_url) {
+        let pool = sqlite::create_connection_pool(&opts.database_url)
+            .await
+            .context("failed to connect to database")?;
+
+        let mut task_queue = PersistenceSqlite::new(pool)
+            .with_idempotency_retention(Duration::from_secs(opts.idempotency_retention_secs));
+
+        if let Some(aging_ms) = opts.priority_aging_ms {
+            task_queue = task_queue.with_priority_aging(Duration::from_millis(aging_ms));
+        }
+
+        task_queue
+            .migrate()
+            .await
+            .context("failed to migrate database")?;
+
+        start_with_task_queue(opts, Arc::new(task_queue)).await
+    } else {
+        let pool = postgres::create_connection_pool(&opts.database_url)
+            .await
+            .context("failed to connect to database")?;
+
+        let mut task_queue = PersistencePostgres::new(pool)
+            .with_idempotency_retention(Duration::from_secs(opts.idempotency_retention_secs));
+
+        if let Some(aging_ms) = opts.priority_aging_ms {
+            task_queue = task_queue.with_priority_aging(Duration::from_millis(aging_ms));
+        }
+
+        task_queue
+            .migrate()
+            .await
+            .context("failed to migrate database")?;
+
+        start_with_task_queue(opts, Arc::new(task_queue)).await
+    }
+}
+
+async fn start_with_task_queue<Q>(
+    opts: crate::opts::Opts,
+    task_queue: Arc<Q>,
+) -> anyhow::Result<AppHandle>
+where
+    Q: TaskQueue + Send + Sync + 'static,
+{
     let gossip_public_addr =
         resolve_addr::resolve_socket_addr(&opts.hostname, opts.gossip_port).await?;
 
@@ -51,9 +107,15 @@ pub async fn start(opts: crate::opts::Opts) -> anyhow::Result<AppHandle> {
     let partition_resolver_handle =
         partition_resolver::start(cluster_monitor_handle.cluster_monitor()).await;
 
+    let scheduler_handle = scheduler::start(
+        task_queue.clone(),
+        partition_resolver_handle.partition_resolver(),
+    );
+
     let rpc_handle = crate::rpc::server::start(
         opts.grpc_listen_addr,
         partition_resolver_handle.partition_resolver(),
+        task_queue,
     )
     .await;
 
@@ -61,6 +123,7 @@ pub async fn start(opts: crate::opts::Opts) -> anyhow::Result<AppHandle> {
         rpc_handle,
         cluster_monitor_handle,
         partition_resolver_handle,
+        scheduler_handle,
     };
 
     Ok(app_handle)
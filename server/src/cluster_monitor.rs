@@ -48,6 +48,11 @@ impl ClusterNode {
 
         let grpc_endpoint = grpc_endpoint_str.parse::<SocketAddr>()?;
 
+        Self::new(chitchat_id, grpc_endpoint)
+    }
+
+    /// A node serving gRPC on `grpc_endpoint`. The channel connects on first use.
+    pub fn new(chitchat_id: &ChitchatId, grpc_endpoint: SocketAddr) -> Result<Self> {
         let grpc_channel = Channel::from_shared(grpc_endpoint.to_string())
             .map_err(|e| anyhow::anyhow!("failed to create channel: {}", e))?
             .connect_lazy();
@@ -257,7 +262,7 @@ impl ClusterMonitor {
         rx
     }
 
-    pub async fn self_id(&mut self) -> ClusterNodeId {
+    pub async fn self_id(&self) -> ClusterNodeId {
         let locked = self.chitchat.lock().await;
         ClusterNodeId(locked.self_chitchat_id().node_id.clone())
     }
@@ -7,3 +7,6 @@ pub mod partition_resolver;
 pub mod persistence;
 pub mod resolve_addr;
 pub mod rpc;
+pub mod scheduler;
+pub mod task_definition;
+pub mod workflow;
@@ -28,4 +28,17 @@ pub struct Opts {
     /// A comma separated list of seed node hostnames
     #[arg(long, value_parser, value_delimiter = ',', num_args = 1..)]
     pub seeds: Vec<String>,
+
+    /// Connection URL for task storage, `postgres://...` or `sqlite://...`
+    #[arg(long)]
+    pub database_url: String,
+
+    /// How long idempotency keys are remembered, in seconds
+    #[arg(long, default_value = "86400")]
+    pub idempotency_retention_secs: u64,
+
+    /// Lifts waiting tasks one priority level per this many milliseconds, so low priority
+    /// tasks are not starved
+    #[arg(long)]
+    pub priority_aging_ms: Option<u64>,
 }
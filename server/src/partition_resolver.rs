@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
@@ -12,8 +12,9 @@ use tokio_stream::StreamExt;
 #[derive(Clone)]
 pub struct PartitionResolver {
     conhash: Arc<RwLock<ConsistentHash<ClusterNodeId>>>,
+    channels: Arc<RwLock<BTreeMap<ClusterNodeId, Channel>>>,
     replica_count: usize,
-    cluster_monitor: ClusterMonitor,
+    self_id: ClusterNodeId,
 }
 
 impl Node for ClusterNodeId {
@@ -23,13 +24,15 @@ impl Node for ClusterNodeId {
 }
 
 impl PartitionResolver {
-    pub fn new(cluster_monitor: &ClusterMonitor, _replica_count: usize, _seed: (u64, u64)) -> Self {
+    /// A resolver for the node `self_id`, with an empty ring until [`Self::sync`] adds nodes.
+    pub fn new(self_id: ClusterNodeId, _replica_count: usize, _seed: (u64, u64)) -> Self {
         Self {
-            cluster_monitor: cluster_monitor.clone(),
+            self_id,
             conhash: Arc::new(RwLock::new(ConsistentHash::<
                 ClusterNodeId,
                 DefaultBytesHasher,
             >::with_seed((0, 0)))),
+            channels: Arc::new(RwLock::new(BTreeMap::new())),
             replica_count: 10,
         }
     }
@@ -37,17 +40,21 @@ impl PartitionResolver {
     pub async fn sync(&mut self, cs: &ClusterStateChangeset) {
         for node in cs {
             let mut conhash_guard = self.conhash.write().await;
+            let mut channels_guard = self.channels.write().await;
 
+            // This node is on the ring too, so every node agrees on who owns a key.
             match node {
-                ClusterStateChange::Added(node)
-                    if node.node_id() != self.cluster_monitor.self_id().await =>
-                {
+                ClusterStateChange::Added(node) => {
                     conhash_guard.add(&node.node_id(), self.replica_count);
+                    channels_guard.insert(node.node_id(), node.grpc_channel());
                 }
                 ClusterStateChange::Removed(node) => {
                     conhash_guard.remove(&node.node_id());
+                    channels_guard.remove(&node.node_id());
+                }
+                ClusterStateChange::Updated(node) => {
+                    channels_guard.insert(node.node_id(), node.grpc_channel());
                 }
-                _ => {}
             }
         }
     }
@@ -57,17 +64,26 @@ impl PartitionResolver {
         conhash_guard.get(key).map(|value| value.clone())
     }
 
+    /// Whether this node owns `key`. Nothing is local until this node has joined the ring.
+    pub async fn is_local(&self, key: &[u8]) -> bool {
+        self.resolve_node_id(key).await.as_ref() == Some(&self.self_id)
+    }
+
+    /// A channel to the node owning `key`, `None` if it is owned by this node or nobody.
     pub async fn resolve(&self, key: &[u8]) -> Option<Channel> {
         let node_id = self.resolve_node_id(key).await?;
-        let node = self.cluster_monitor.get_node_channel(&node_id).await;
 
-        match node {
-            Err(err) => {
-                tracing::error!(err=?err, "node_id_missing_channel");
-                None
-            }
-            Ok(channel) => Some(channel),
+        if node_id == self.self_id {
+            return None;
         }
+
+        let channel = self.channels.read().await.get(&node_id).cloned();
+
+        if channel.is_none() {
+            tracing::error!(node_id = %node_id, "node_id_missing_channel");
+        }
+
+        channel
     }
 }
 
@@ -91,7 +107,7 @@ impl PartitionResolverHandle {
 }
 
 pub async fn start(cluster_monitor: ClusterMonitor) -> PartitionResolverHandle {
-    let partition_resolver = PartitionResolver::new(&cluster_monitor, 50, (0, 0));
+    let partition_resolver = PartitionResolver::new(cluster_monitor.self_id().await, 50, (0, 0));
     let mut ws = cluster_monitor.watch().await;
     let mut pr = partition_resolver.clone();
 
@@ -1,3 +1,14 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use anyhow::Result;
 use async_trait::async_trait;
 
@@ -16,16 +27,1177 @@ impl TaskId {
             queue_id: queue_id.to_string(),
         }
     }
+
+    pub fn queue_id(&self) -> &str {
+        &self.queue_id
+    }
+
+    pub fn partition_id(&self) -> i16 {
+        self.partition_id
+    }
+
+    pub fn seq_id(&self) -> i64 {
+        self.seq_id
+    }
+}
+
+/// Formats as `queue_id:partition_id:seq_id`, which is what gets handed out to clients.
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.queue_id, self.partition_id, self.seq_id)
+    }
+}
+
+impl FromStr for TaskId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // The queue id may itself contain `:`, so split from the right.
+        let mut parts = s.rsplitn(3, ':');
+
+        let seq_id = parts.next().and_then(|part| part.parse::<i64>().ok());
+        let partition_id = parts.next().and_then(|part| part.parse::<i16>().ok());
+        let queue_id = parts.next().filter(|part| !part.is_empty());
+
+        match (queue_id, partition_id, seq_id) {
+            (Some(queue_id), Some(partition_id), Some(seq_id)) => {
+                Ok(TaskId::from_parts(queue_id, partition_id, seq_id))
+            }
+            _ => Err(anyhow::anyhow!("invalid task id: {}", s)),
+        }
+    }
 }
 
 pub type TaskPayload = Vec<u8>;
 
+/// Recorded against tasks failed because their deadline passed.
+pub const DEADLINE_EXCEEDED_REASON: &str = "deadline exceeded";
+
+/// Recorded against tasks whose lease lapsed without being settled.
+pub const LEASE_EXPIRED_REASON: &str = "lease expired";
+
+/// Recorded against tasks cancelled with [`TaskQueue::cancel_task`].
+pub const CANCELLED_REASON: &str = "cancelled";
+
+/// Recorded against blocked tasks failed or cancelled because a task they depend on failed.
+pub const DEPENDENCY_FAILED_REASON: &str = "dependency failed";
+
+/// How long idempotency keys are remembered unless a backend is configured otherwise.
+pub const DEFAULT_IDEMPOTENCY_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often a queue partition is swept for expired tasks unless a backend is configured
+/// otherwise.
+pub const DEFAULT_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where a task is in its lifecycle. Stored as the `SMALLINT` discriminant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(i16)]
+pub enum TaskStatus {
+    /// Ready to be leased.
+    Pending = 0,
+    /// Waiting for its `scheduled_at` before it can be leased.
+    Scheduled = 1,
+    /// Claimed by a worker under a lease.
+    Leased = 2,
+    Succeeded = 3,
+    Failed = 4,
+    Cancelled = 5,
+    /// Gave up on, kept aside for inspection.
+    DeadLettered = 6,
+    /// Waiting for the tasks it depends on.
+    Blocked = 7,
+}
+
+impl TaskStatus {
+    pub const ALL: [TaskStatus; 8] = [
+        TaskStatus::Pending,
+        TaskStatus::Scheduled,
+        TaskStatus::Leased,
+        TaskStatus::Succeeded,
+        TaskStatus::Failed,
+        TaskStatus::Cancelled,
+        TaskStatus::DeadLettered,
+        TaskStatus::Blocked,
+    ];
+
+    pub fn as_i16(self) -> i16 {
+        self as i16
+    }
+
+    pub fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            TaskStatus::Succeeded | TaskStatus::Failed | TaskStatus::Cancelled
+        )
+    }
+
+    /// Whether a task may move from `self` to `next`.
+    pub fn can_transition_to(self, next: TaskStatus) -> bool {
+        use TaskStatus::*;
+
+        matches!(
+            (self, next),
+            (Pending, Scheduled | Leased | Failed | Cancelled)
+                | (Scheduled, Pending | Leased | Failed | Cancelled)
+                | (
+                    Leased,
+                    Pending | Scheduled | Succeeded | Failed | Cancelled | DeadLettered
+                )
+                | (DeadLettered, Pending)
+                | (Blocked, Pending | Scheduled | Failed | Cancelled)
+        )
+    }
+
+    /// Whether a parent in this status has let down the tasks depending on it, having
+    /// finished without succeeding or been given up on.
+    pub fn fails_dependents(self) -> bool {
+        matches!(
+            self,
+            TaskStatus::Failed | TaskStatus::Cancelled | TaskStatus::DeadLettered
+        )
+    }
+
+    /// Every status that may move to `self`, for backends that enforce transitions in a
+    /// single conditional update.
+    pub fn sources(self) -> Vec<TaskStatus> {
+        TaskStatus::ALL
+            .into_iter()
+            .filter(|status| status.can_transition_to(self))
+            .collect()
+    }
+
+    /// Fails unless the task may move from `self` to `next`.
+    pub fn check_transition(self, next: TaskStatus) -> Result<()> {
+        if self.can_transition_to(next) {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "illegal task status transition: {:?} -> {:?}",
+                self,
+                next
+            ))
+        }
+    }
+}
+
+impl TryFrom<i16> for TaskStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i16) -> Result<Self> {
+        TaskStatus::ALL
+            .into_iter()
+            .find(|status| status.as_i16() == value)
+            .ok_or_else(|| anyhow::anyhow!("unknown task status: {}", value))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TaskData {
     pub task_id: TaskId,
+    pub status: TaskStatus,
     pub payload: TaskPayload,
+    pub content_type: Option<String>,
     pub scheduled_at: i64,
     pub deadline_at: Option<i64>,
+    pub timeout_ms: Option<i64>,
+    /// Why the task last failed, expired or had its lease revoked.
+    pub last_error: Option<String>,
+    /// How many times the task has been leased.
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub priority: i16,
+    /// What the task returned when it succeeded.
+    pub result: Option<TaskPayload>,
+    /// The last progress reported by a heartbeat during the current attempt.
+    pub progress: Option<TaskProgress>,
+    /// The task definition the payload was checked against when the task was scheduled.
+    pub definition_id: Option<String>,
+    pub definition_version: Option<i32>,
+}
+
+impl TaskData {
+    /// The workflow a workflow task replays, `None` for any other task.
+    pub fn workflow_id(&self) -> Option<&str> {
+        if self.content_type.as_deref() != Some(WORKFLOW_TASK_CONTENT_TYPE) {
+            return None;
+        }
+
+        std::str::from_utf8(&self.payload).ok()
+    }
+}
+
+/// How far a running task has got, as reported by the worker holding its lease.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskProgress {
+    /// Between `0` and `100`.
+    pub percent: f32,
+    pub detail: Option<String>,
+}
+
+impl TaskProgress {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=100.0).contains(&self.percent) {
+            return Err(anyhow::anyhow!(
+                "percent must be between 0 and 100: {}",
+                self.percent
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// A task claimed by [`TaskQueue::lease_tasks`].
+///
+/// The lease is only valid while `lease_token` matches the one stored with the task; once
+/// `lease_expires_at` passes the task can be leased again under a new token.
+#[derive(Debug, Clone)]
+pub struct LeasedTask {
+    pub task: TaskData,
+    pub lease_token: String,
+    pub lease_expires_at: i64,
+}
+
+/// What became of a lease, as seen by the worker holding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaseState {
+    /// The task is still leased under the token.
+    Held,
+    /// The task was cancelled while leased, the worker should stop.
+    Cancelled,
+    /// The lease was settled, expired or revoked.
+    Lost,
+}
+
+/// A task to be written by [`TaskQueue::enqueue_tasks`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NewTask<'a> {
+    pub payload: &'a [u8],
+    pub content_type: Option<&'a str>,
+    /// When the task becomes due, `0` means as soon as possible.
+    pub scheduled_at: i64,
+    /// After this time the task fails instead of being leased.
+    pub deadline_at: Option<i64>,
+    /// How long a single run may hold its lease before it is revoked.
+    pub timeout_ms: Option<i64>,
+    /// Overrides the queue's [`RetryPolicy::max_attempts`].
+    pub max_attempts: Option<i32>,
+    /// While the key is retained, enqueueing it again on the same queue returns the original
+    /// task's id instead of writing a new task.
+    pub idempotency_key: Option<&'a str>,
+    /// Due tasks with a higher priority are leased first, `0` by default.
+    pub priority: i16,
+    /// Indexes of earlier tasks in the same batch that have to succeed before this one runs.
+    pub depends_on: &'a [usize],
+    pub on_parent_failure: ParentFailurePolicy,
+    /// The task definition the payload was checked against, kept with the task.
+    pub definition_id: Option<&'a str>,
+    pub definition_version: Option<i32>,
+}
+
+impl<'a> NewTask<'a> {
+    pub fn new(payload: &'a [u8]) -> Self {
+        Self {
+            payload,
+            ..Default::default()
+        }
+    }
+
+    /// The time the task becomes due, with an unset `scheduled_at` resolved to `now`.
+    pub fn due_at(&self, now: i64) -> i64 {
+        if self.scheduled_at > 0 {
+            self.scheduled_at
+        } else {
+            now
+        }
+    }
+
+    /// A task asking a worker to replay `workflow_id` against its history.
+    pub fn workflow_task(workflow_id: &'a str) -> Self {
+        Self {
+            content_type: Some(WORKFLOW_TASK_CONTENT_TYPE),
+            ..Self::new(workflow_id.as_bytes())
+        }
+    }
+
+    /// The status the task is written with. Tasks with parents start out blocked, delayed tasks
+    /// scheduled.
+    pub fn initial_status(&self, now: i64) -> TaskStatus {
+        if self.depends_on.is_empty() {
+            waiting_status(self.due_at(now), now)
+        } else {
+            TaskStatus::Blocked
+        }
+    }
+}
+
+/// What a blocked task does once a task it depends on fails, is cancelled or is
+/// dead-lettered. Redriving the parent afterwards does not undo it. Stored as the `SMALLINT`
+/// discriminant.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(i16)]
+pub enum ParentFailurePolicy {
+    /// Fails as well, passing the failure on to the tasks depending on it.
+    #[default]
+    Fail = 0,
+    /// Is cancelled, which its own dependents treat as a failure.
+    Cancel = 1,
+    /// Runs anyway once every parent has finished.
+    Run = 2,
+}
+
+impl ParentFailurePolicy {
+    pub fn as_i16(self) -> i16 {
+        self as i16
+    }
+
+    /// The status a blocked task moves to as soon as one of its parents fails, `None` if it
+    /// waits for the rest instead.
+    pub fn failed_status(self) -> Option<TaskStatus> {
+        match self {
+            ParentFailurePolicy::Fail => Some(TaskStatus::Failed),
+            ParentFailurePolicy::Cancel => Some(TaskStatus::Cancelled),
+            ParentFailurePolicy::Run => None,
+        }
+    }
+}
+
+impl TryFrom<i16> for ParentFailurePolicy {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i16) -> Result<Self> {
+        match value {
+            0 => Ok(ParentFailurePolicy::Fail),
+            1 => Ok(ParentFailurePolicy::Cancel),
+            2 => Ok(ParentFailurePolicy::Run),
+            _ => Err(anyhow::anyhow!("unknown parent failure policy: {}", value)),
+        }
+    }
+}
+
+/// An edge of a task graph, `task_id` waits on `parent_id`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TaskDependency {
+    pub task_id: TaskId,
+    pub parent_id: TaskId,
+}
+
+/// The tasks connected to one another through their dependencies.
+#[derive(Debug, Clone)]
+pub struct TaskGraph {
+    /// In the order they were enqueued.
+    pub tasks: Vec<TaskData>,
+    pub dependencies: Vec<TaskDependency>,
+}
+
+/// Fails unless every task only depends on tasks before it in the batch, which keeps graphs
+/// free of cycles.
+pub fn check_dependencies(tasks: &[NewTask<'_>]) -> Result<()> {
+    for (index, task) in tasks.iter().enumerate() {
+        if let Some(parent) = task.depends_on.iter().find(|parent| **parent >= index) {
+            return Err(anyhow::anyhow!(
+                "task {} can only depend on tasks before it: {}",
+                index,
+                parent
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails if a task overrides its queue's attempts with fewer than one, as
+/// [`RetryPolicy::validate`] does for the queue.
+pub fn check_max_attempts(tasks: &[NewTask<'_>]) -> Result<()> {
+    for (index, task) in tasks.iter().enumerate() {
+        if let Some(max_attempts) = task.max_attempts.filter(|max_attempts| *max_attempts < 1) {
+            return Err(anyhow::anyhow!(
+                "task {}: max_attempts must be at least 1: {}",
+                index,
+                max_attempts
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The edges to write for an enqueued batch, given an id per task in the batch and the ids of
+/// the rows actually written. Tasks that resolved to an existing task through their
+/// idempotency key keep that task's edges, but can still be depended on.
+pub fn batch_dependencies(
+    tasks: &[NewTask<'_>],
+    task_ids: &[TaskId],
+    written: &[TaskId],
+) -> Vec<TaskDependency> {
+    let mut written: HashSet<&TaskId> = written.iter().collect();
+    let mut dependencies = Vec::new();
+
+    for (task, task_id) in tasks.iter().zip(task_ids) {
+        // Removing the id skips later repeats of the same key in the batch.
+        if task.depends_on.is_empty() || !written.remove(task_id) {
+            continue;
+        }
+
+        let mut parent_ids: Vec<&TaskId> = task.depends_on.iter().map(|i| &task_ids[*i]).collect();
+        parent_ids.sort_by_key(|parent_id| (parent_id.partition_id(), parent_id.seq_id()));
+        parent_ids.dedup();
+
+        dependencies.extend(parent_ids.into_iter().map(|parent_id| TaskDependency {
+            task_id: task_id.clone(),
+            parent_id: parent_id.clone(),
+        }));
+    }
+
+    dependencies
+}
+
+/// The priority a due task is leased by. With a starvation guard, every `aging_ms` a task has
+/// been due lifts it one level, so low priority work still makes progress.
+pub fn effective_priority(
+    priority: i16,
+    scheduled_at: i64,
+    now: i64,
+    aging_ms: Option<i64>,
+) -> i64 {
+    match aging_ms {
+        Some(aging_ms) => i64::from(priority) + (now - scheduled_at).max(0) / aging_ms,
+        None => i64::from(priority),
+    }
+}
+
+/// The status of a task waiting to run, depending on whether it is due.
+pub fn waiting_status(due_at: i64, now: i64) -> TaskStatus {
+    if due_at > now {
+        TaskStatus::Scheduled
+    } else {
+        TaskStatus::Pending
+    }
+}
+
+/// Spaces out the expiry sweeps made by lease calls, so the workers polling a partition do
+/// not each sweep it.
+pub struct ExpirySweeps {
+    interval_ms: i64,
+    swept_at: Mutex<HashMap<(String, i16), i64>>,
+}
+
+impl ExpirySweeps {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval_ms: interval.as_millis() as i64,
+            swept_at: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the partition is due a sweep at `now`. A due partition counts as swept.
+    pub fn claim(&self, queue_id: &str, partition_id: i16, now: i64) -> bool {
+        let mut swept_at = self.swept_at.lock().unwrap();
+        let key = (queue_id.to_string(), partition_id);
+
+        if swept_at
+            .get(&key)
+            .is_some_and(|swept_at| now - swept_at < self.interval_ms)
+        {
+            return false;
+        }
+
+        swept_at.insert(key, now);
+        true
+    }
+}
+
+/// An enqueue batch with repeated idempotency keys folded together. Later tasks reusing a key
+/// from earlier in the batch are not written, they share the earlier task's id.
+pub struct IdempotentBatch<'a> {
+    tasks: Vec<NewTask<'a>>,
+    /// For each task in the original batch, the index of the earlier task it repeats.
+    duplicate_of: Vec<Option<usize>>,
+}
+
+impl<'a> IdempotentBatch<'a> {
+    pub fn new(tasks: Vec<NewTask<'a>>) -> Self {
+        let mut first_by_key = HashMap::new();
+        let mut duplicate_of = Vec::with_capacity(tasks.len());
+        let mut unique = Vec::with_capacity(tasks.len());
+
+        for (index, task) in tasks.into_iter().enumerate() {
+            let first = match task.idempotency_key {
+                Some(key) => *first_by_key.entry(key).or_insert(index),
+                None => index,
+            };
+
+            if first == index {
+                duplicate_of.push(None);
+                unique.push(task);
+            } else {
+                duplicate_of.push(Some(first));
+            }
+        }
+
+        Self {
+            tasks: unique,
+            duplicate_of,
+        }
+    }
+
+    /// The tasks to write.
+    pub fn tasks(&self) -> &[NewTask<'a>] {
+        &self.tasks
+    }
+
+    /// Expands the ids of the written tasks, in order, to one id per task in the batch.
+    pub fn task_ids(&self, written: Vec<TaskId>) -> Vec<TaskId> {
+        let mut written = written.into_iter();
+        let mut task_ids: Vec<TaskId> = Vec::with_capacity(self.duplicate_of.len());
+
+        for duplicate_of in &self.duplicate_of {
+            let task_id = match duplicate_of {
+                Some(first) => task_ids[*first].clone(),
+                None => written.next().expect("an id for every written task"),
+            };
+
+            task_ids.push(task_id);
+        }
+
+        task_ids
+    }
+}
+
+/// How a queue retries tasks that fail.
+///
+/// A failed attempt puts the task back as scheduled after an exponential backoff, once
+/// `max_attempts` have been made it is dead-lettered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: i32,
+    pub initial_backoff_ms: i64,
+    pub max_backoff_ms: i64,
+    pub backoff_multiplier: f64,
+    /// Fraction of each delay that is randomised, `0.0` for none and `1.0` for full jitter.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 1_000,
+            max_backoff_ms: 300_000,
+            backoff_multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retrying a task that has made `attempts` attempts. `jitter_sample`
+    /// is a random number in `[0, 1)`, so the delay shrinks by up to `jitter` of itself.
+    pub fn backoff_ms(&self, attempts: i32, jitter_sample: f64) -> i64 {
+        let exponent = attempts.saturating_sub(1).max(0);
+        let delay = (self.initial_backoff_ms as f64 * self.backoff_multiplier.powi(exponent))
+            .min(self.max_backoff_ms as f64);
+
+        (delay * (1.0 - self.jitter * jitter_sample)) as i64
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.max_attempts < 1 {
+            return Err(anyhow::anyhow!(
+                "max_attempts must be at least 1: {}",
+                self.max_attempts
+            ));
+        }
+
+        if self.initial_backoff_ms < 0 || self.max_backoff_ms < self.initial_backoff_ms {
+            return Err(anyhow::anyhow!(
+                "invalid backoff range: {}ms..{}ms",
+                self.initial_backoff_ms,
+                self.max_backoff_ms
+            ));
+        }
+
+        if self.backoff_multiplier.is_nan() || self.backoff_multiplier < 1.0 {
+            return Err(anyhow::anyhow!(
+                "backoff_multiplier must be at least 1: {}",
+                self.backoff_multiplier
+            ));
+        }
+
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(anyhow::anyhow!(
+                "jitter must be between 0 and 1: {}",
+                self.jitter
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// What a schedule does about runs that came due while it was not being fired, e.g. while no
+/// node was up. Stored as the `SMALLINT` discriminant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i16)]
+pub enum MisfirePolicy {
+    /// Enqueues every missed run, oldest first.
+    CatchUp = 0,
+    /// Enqueues only the most recent missed run.
+    Skip = 1,
+}
+
+impl MisfirePolicy {
+    pub fn as_i16(self) -> i16 {
+        self as i16
+    }
+}
+
+impl TryFrom<i16> for MisfirePolicy {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i16) -> Result<Self> {
+        match value {
+            0 => Ok(MisfirePolicy::CatchUp),
+            1 => Ok(MisfirePolicy::Skip),
+            _ => Err(anyhow::anyhow!("unknown misfire policy: {}", value)),
+        }
+    }
+}
+
+/// A recurring task, enqueued every time its cron expression fires.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub schedule_id: String,
+    /// A cron expression, with or without a leading seconds field.
+    pub cron: String,
+    /// The IANA time zone `cron` is evaluated in, e.g. `Europe/London`.
+    pub timezone: String,
+    pub queue_id: String,
+    pub partition_id: i16,
+    pub payload: TaskPayload,
+    pub content_type: Option<String>,
+    pub misfire_policy: MisfirePolicy,
+    /// When the schedule next fires, in unix millis.
+    pub next_fire_at: i64,
+}
+
+/// The content type of the tasks that replay workflows. Their payload is the workflow id.
+pub const WORKFLOW_TASK_CONTENT_TYPE: &str = "application/vnd.svppl.workflow-task";
+
+/// Where a workflow is in its life. Stored as the `SMALLINT` discriminant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i16)]
+pub enum WorkflowStatus {
+    Running = 0,
+    Completed = 1,
+    Failed = 2,
+}
+
+impl WorkflowStatus {
+    pub fn as_i16(self) -> i16 {
+        self as i16
+    }
+}
+
+impl TryFrom<i16> for WorkflowStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i16) -> Result<Self> {
+        match value {
+            0 => Ok(WorkflowStatus::Running),
+            1 => Ok(WorkflowStatus::Completed),
+            2 => Ok(WorkflowStatus::Failed),
+            _ => Err(anyhow::anyhow!("unknown workflow status: {}", value)),
+        }
+    }
+}
+
+/// What an entry in a workflow's history records. Stored as the `SMALLINT` discriminant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i16)]
+pub enum WorkflowEventKind {
+    /// The first event, named after the workflow type and holding its input.
+    WorkflowStarted = 0,
+    /// A step returned the payload.
+    StepCompleted = 1,
+    /// A step failed with the error.
+    StepFailed = 2,
+    /// The workflow returned the payload.
+    WorkflowCompleted = 3,
+    /// The workflow failed with the error.
+    WorkflowFailed = 4,
+    /// The workflow went to sleep until `fire_at`.
+    TimerStarted = 5,
+    /// The timer of the same name fired.
+    TimerFired = 6,
+    /// The workflow blocked until a signal of the same name is received.
+    SignalAwaited = 7,
+    /// A signal of the same name was sent to the workflow with the payload.
+    SignalReceived = 8,
+    /// The workflow started a child workflow, of the type the event is named after and with
+    /// the payload as its input.
+    ChildWorkflowStarted = 9,
+    /// The child workflow the event is named after returned the payload.
+    ChildWorkflowCompleted = 10,
+    /// The child workflow the event is named after failed with the error.
+    ChildWorkflowFailed = 11,
+    /// The compensation for the step of the same name ran while the workflow was failing.
+    CompensationCompleted = 12,
+    /// The compensation for the step of the same name failed with the error.
+    CompensationFailed = 13,
+}
+
+impl WorkflowEventKind {
+    pub fn as_i16(self) -> i16 {
+        self as i16
+    }
+
+    /// The status a workflow finishes with once this event is recorded, `None` if it keeps
+    /// running.
+    pub fn finished_status(self) -> Option<WorkflowStatus> {
+        match self {
+            WorkflowEventKind::WorkflowCompleted => Some(WorkflowStatus::Completed),
+            WorkflowEventKind::WorkflowFailed => Some(WorkflowStatus::Failed),
+            _ => None,
+        }
+    }
+
+    /// Whether workers may append the event, the rest are only recorded by the server.
+    pub fn is_appendable(self) -> bool {
+        !matches!(
+            self,
+            WorkflowEventKind::WorkflowStarted
+                | WorkflowEventKind::TimerFired
+                | WorkflowEventKind::SignalReceived
+                | WorkflowEventKind::ChildWorkflowCompleted
+                | WorkflowEventKind::ChildWorkflowFailed
+        )
+    }
+
+    /// Whether the event gives a replay something new to go on.
+    pub fn wakes_workflow(self) -> bool {
+        matches!(
+            self,
+            WorkflowEventKind::StepCompleted
+                | WorkflowEventKind::StepFailed
+                | WorkflowEventKind::TimerFired
+                | WorkflowEventKind::SignalReceived
+                | WorkflowEventKind::ChildWorkflowStarted
+                | WorkflowEventKind::ChildWorkflowCompleted
+                | WorkflowEventKind::ChildWorkflowFailed
+                | WorkflowEventKind::CompensationCompleted
+                | WorkflowEventKind::CompensationFailed
+        )
+    }
+}
+
+impl TryFrom<i16> for WorkflowEventKind {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i16) -> Result<Self> {
+        match value {
+            0 => Ok(WorkflowEventKind::WorkflowStarted),
+            1 => Ok(WorkflowEventKind::StepCompleted),
+            2 => Ok(WorkflowEventKind::StepFailed),
+            3 => Ok(WorkflowEventKind::WorkflowCompleted),
+            4 => Ok(WorkflowEventKind::WorkflowFailed),
+            5 => Ok(WorkflowEventKind::TimerStarted),
+            6 => Ok(WorkflowEventKind::TimerFired),
+            7 => Ok(WorkflowEventKind::SignalAwaited),
+            8 => Ok(WorkflowEventKind::SignalReceived),
+            9 => Ok(WorkflowEventKind::ChildWorkflowStarted),
+            10 => Ok(WorkflowEventKind::ChildWorkflowCompleted),
+            11 => Ok(WorkflowEventKind::ChildWorkflowFailed),
+            12 => Ok(WorkflowEventKind::CompensationCompleted),
+            13 => Ok(WorkflowEventKind::CompensationFailed),
+            _ => Err(anyhow::anyhow!("unknown workflow event kind: {}", value)),
+        }
+    }
+}
+
+/// A durable workflow. Its progress lives in its history of [`WorkflowEvent`]s, which
+/// workers replay the workflow function against every time the history grows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workflow {
+    pub workflow_id: String,
+    /// Names the workflow function workers replay.
+    pub workflow_type: String,
+    /// Where the workflow's tasks are enqueued.
+    pub queue_id: String,
+    pub partition_id: i16,
+    pub status: WorkflowStatus,
+    /// What the workflow returned when it completed.
+    pub result: Option<TaskPayload>,
+    /// Why the workflow failed.
+    pub error: Option<String>,
+    /// The id of the latest event in the workflow's history.
+    pub last_event_id: i64,
+    /// The signal the workflow is blocked on, if any.
+    pub awaiting_signal: Option<String>,
+    /// The workflow that started this one as its child, which is told how it finished.
+    pub parent_workflow_id: Option<String>,
+    pub created_at: i64,
+}
+
+/// A workflow to be created by [`TaskQueue::start_workflow`].
+#[derive(Debug, Clone, Copy)]
+pub struct NewWorkflow<'a> {
+    pub workflow_id: &'a str,
+    pub workflow_type: &'a str,
+    pub queue_id: &'a str,
+    pub partition_id: i16,
+    pub input: &'a [u8],
+}
+
+/// An entry in a workflow's history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkflowEvent {
+    /// The event's position in the history, counting from `1`.
+    pub event_id: i64,
+    pub kind: WorkflowEventKind,
+    /// The step, timer, signal, child workflow or workflow type the event is about.
+    pub name: Option<String>,
+    pub payload: Option<TaskPayload>,
+    pub error: Option<String>,
+    /// When a timer fires, in unix millis.
+    pub fire_at: Option<i64>,
+    pub recorded_at: i64,
+}
+
+/// An event to be appended by [`TaskQueue::append_workflow_events`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewWorkflowEvent {
+    pub kind: WorkflowEventKind,
+    pub name: Option<String>,
+    pub payload: Option<TaskPayload>,
+    pub error: Option<String>,
+    pub fire_at: Option<i64>,
+}
+
+impl NewWorkflowEvent {
+    pub fn step_completed(name: &str, result: TaskPayload) -> Self {
+        Self {
+            kind: WorkflowEventKind::StepCompleted,
+            name: Some(name.to_string()),
+            payload: Some(result),
+            error: None,
+            fire_at: None,
+        }
+    }
+
+    pub fn step_failed(name: &str, error: &str) -> Self {
+        Self {
+            kind: WorkflowEventKind::StepFailed,
+            name: Some(name.to_string()),
+            payload: None,
+            error: Some(error.to_string()),
+            fire_at: None,
+        }
+    }
+
+    pub fn timer_started(name: &str, fire_at: i64) -> Self {
+        Self {
+            kind: WorkflowEventKind::TimerStarted,
+            name: Some(name.to_string()),
+            payload: None,
+            error: None,
+            fire_at: Some(fire_at),
+        }
+    }
+
+    pub fn signal_awaited(name: &str) -> Self {
+        Self {
+            kind: WorkflowEventKind::SignalAwaited,
+            name: Some(name.to_string()),
+            payload: None,
+            error: None,
+            fire_at: None,
+        }
+    }
+
+    pub fn child_workflow_started(workflow_type: &str, input: TaskPayload) -> Self {
+        Self {
+            kind: WorkflowEventKind::ChildWorkflowStarted,
+            name: Some(workflow_type.to_string()),
+            payload: Some(input),
+            error: None,
+            fire_at: None,
+        }
+    }
+
+    pub fn compensation_completed(name: &str) -> Self {
+        Self {
+            kind: WorkflowEventKind::CompensationCompleted,
+            name: Some(name.to_string()),
+            payload: None,
+            error: None,
+            fire_at: None,
+        }
+    }
+
+    pub fn compensation_failed(name: &str, error: &str) -> Self {
+        Self {
+            kind: WorkflowEventKind::CompensationFailed,
+            name: Some(name.to_string()),
+            payload: None,
+            error: Some(error.to_string()),
+            fire_at: None,
+        }
+    }
+
+    pub fn workflow_completed(result: TaskPayload) -> Self {
+        Self {
+            kind: WorkflowEventKind::WorkflowCompleted,
+            name: None,
+            payload: Some(result),
+            error: None,
+            fire_at: None,
+        }
+    }
+
+    pub fn workflow_failed(error: &str) -> Self {
+        Self {
+            kind: WorkflowEventKind::WorkflowFailed,
+            name: None,
+            payload: None,
+            error: Some(error.to_string()),
+            fire_at: None,
+        }
+    }
+}
+
+/// Checks events before they are appended: only appendable kinds, and nothing after the event
+/// that finishes the workflow.
+pub fn check_workflow_events(events: &[NewWorkflowEvent]) -> Result<()> {
+    if events.is_empty() {
+        return Err(anyhow::anyhow!("no events to append"));
+    }
+
+    for (index, event) in events.iter().enumerate() {
+        if !event.kind.is_appendable() {
+            return Err(anyhow::anyhow!(
+                "{:?} events can not be appended",
+                event.kind
+            ));
+        }
+
+        if event.kind == WorkflowEventKind::TimerStarted
+            && (event.name.is_none() || event.fire_at.is_none())
+        {
+            return Err(anyhow::anyhow!("timers need a name and a fire_at"));
+        }
+
+        if event.kind == WorkflowEventKind::SignalAwaited && event.name.is_none() {
+            return Err(anyhow::anyhow!("awaited signals need a name"));
+        }
+
+        if event.kind == WorkflowEventKind::ChildWorkflowStarted && event.name.is_none() {
+            return Err(anyhow::anyhow!("child workflows need a workflow type"));
+        }
+
+        if event.kind.finished_status().is_some() && index + 1 < events.len() {
+            return Err(anyhow::anyhow!(
+                "{:?} has to be the last event appended",
+                event.kind
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// How appending `events` leaves a workflow: its status, result and error.
+pub fn workflow_outcome(
+    events: &[NewWorkflowEvent],
+) -> (WorkflowStatus, Option<&[u8]>, Option<&str>) {
+    let finished = events
+        .last()
+        .and_then(|event| Some((event.kind.finished_status()?, event)));
+
+    match finished {
+        Some((status, event)) => (status, event.payload.as_deref(), event.error.as_deref()),
+        None => (WorkflowStatus::Running, None, None),
+    }
+}
+
+/// Whether appending `events` leaves the workflow with something to replay, rather than
+/// finished or asleep.
+pub fn replays_workflow(events: &[NewWorkflowEvent]) -> bool {
+    let (status, _, _) = workflow_outcome(events);
+
+    status == WorkflowStatus::Running && events.iter().any(|event| event.kind.wakes_workflow())
+}
+
+/// The signal appending `events` leaves the workflow blocked on, if any.
+pub fn awaited_signal(events: &[NewWorkflowEvent]) -> Option<&str> {
+    events
+        .iter()
+        .rev()
+        .find(|event| event.kind == WorkflowEventKind::SignalAwaited)
+        .and_then(|event| event.name.as_deref())
+}
+
+/// The id of the child workflow started by the [`WorkflowEventKind::ChildWorkflowStarted`]
+/// event `event_id` in the history of `workflow_id`.
+pub fn child_workflow_id(workflow_id: &str, event_id: i64) -> String {
+    format!("{}/{}", workflow_id, event_id)
+}
+
+/// The child workflows started by appending `events` to a history that ends at
+/// `last_event_id`, as their ids with the events that start them.
+pub fn child_workflows<'a>(
+    workflow_id: &str,
+    last_event_id: i64,
+    events: &'a [NewWorkflowEvent],
+) -> Vec<(String, &'a NewWorkflowEvent)> {
+    events
+        .iter()
+        .zip(last_event_id + 1..)
+        .filter(|(event, _)| event.kind == WorkflowEventKind::ChildWorkflowStarted)
+        .map(|(event, event_id)| (child_workflow_id(workflow_id, event_id), event))
+        .collect()
+}
+
+/// The event that tells its parent that appending `events` finished the child workflow
+/// `workflow_id`, `None` while the child keeps running.
+pub fn child_workflow_finished(
+    workflow_id: &str,
+    events: &[NewWorkflowEvent],
+) -> Option<NewWorkflowEvent> {
+    let (status, result, error) = workflow_outcome(events);
+
+    let kind = match status {
+        WorkflowStatus::Running => return None,
+        WorkflowStatus::Completed => WorkflowEventKind::ChildWorkflowCompleted,
+        WorkflowStatus::Failed => WorkflowEventKind::ChildWorkflowFailed,
+    };
+
+    Some(NewWorkflowEvent {
+        kind,
+        name: Some(workflow_id.to_string()),
+        payload: result.map(<[u8]>::to_vec),
+        error: error.map(str::to_string),
+        fire_at: None,
+    })
+}
+
+/// The timers set by appending `events` to a history that ends at `last_event_id`.
+pub fn workflow_timers(
+    workflow_id: &str,
+    last_event_id: i64,
+    events: &[NewWorkflowEvent],
+) -> Vec<WorkflowTimer> {
+    events
+        .iter()
+        .zip(last_event_id + 1..)
+        .filter(|(event, _)| event.kind == WorkflowEventKind::TimerStarted)
+        .filter_map(|(event, event_id)| {
+            Some(WorkflowTimer {
+                workflow_id: workflow_id.to_string(),
+                timer_id: event_id,
+                name: event.name.clone()?,
+                fire_at: event.fire_at?,
+            })
+        })
+        .collect()
+}
+
+/// A timer a workflow is asleep on, until the server fires it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkflowTimer {
+    pub workflow_id: String,
+    /// The id of the [`WorkflowEventKind::TimerStarted`] event that set the timer.
+    pub timer_id: i64,
+    pub name: String,
+    pub fire_at: i64,
+}
+
+/// Where a workflow has got to, read from its history without replaying it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkflowState {
+    pub workflow: Workflow,
+    /// The outcomes of the steps the workflow has taken, in order.
+    pub steps: Vec<WorkflowEvent>,
+    /// The timers the workflow is asleep on.
+    pub timers: Vec<WorkflowTimer>,
+    /// The signals the workflow has been sent, in order.
+    pub signals: Vec<WorkflowEvent>,
+}
+
+impl WorkflowState {
+    /// Reads the state from `history` as of the workflow's `last_event_id`, so a history read
+    /// after the workflow can not get ahead of it.
+    pub fn new(workflow: Workflow, history: Vec<WorkflowEvent>) -> Self {
+        let mut steps = Vec::new();
+        let mut timers = Vec::new();
+        let mut signals = Vec::new();
+
+        for event in history
+            .into_iter()
+            .take_while(|event| event.event_id <= workflow.last_event_id)
+        {
+            match event.kind {
+                WorkflowEventKind::StepCompleted | WorkflowEventKind::StepFailed => {
+                    steps.push(event)
+                }
+                WorkflowEventKind::TimerStarted => timers.push(WorkflowTimer {
+                    workflow_id: workflow.workflow_id.clone(),
+                    timer_id: event.event_id,
+                    name: event.name.unwrap_or_default(),
+                    fire_at: event.fire_at.unwrap_or_default(),
+                }),
+                WorkflowEventKind::TimerFired => {
+                    timers.retain(|timer| Some(&timer.name) != event.name.as_ref())
+                }
+                WorkflowEventKind::SignalReceived => signals.push(event),
+                _ => {}
+            }
+        }
+
+        if workflow.status != WorkflowStatus::Running {
+            timers.clear();
+        }
+
+        Self {
+            workflow,
+            steps,
+            timers,
+            signals,
+        }
+    }
+}
+
+/// Describes a kind of task and the payloads it takes. Every change is stored as a new
+/// version, so tasks scheduled against an earlier one can still be checked against it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskDefinition {
+    pub definition_id: String,
+    /// Counts up from `1` every time the definition changes.
+    pub version: i32,
+    pub name: String,
+    pub description: String,
+    /// The JSON Schema payloads of the task are checked against.
+    pub parameters: String,
+    pub created_at: i64,
+}
+
+impl TaskDefinition {
+    /// Whether registering `definition` would change nothing.
+    pub fn matches(&self, definition: &NewTaskDefinition<'_>) -> bool {
+        self.definition_id == definition.definition_id
+            && self.name == definition.name
+            && self.description == definition.description
+            && self.parameters == definition.parameters
+    }
+}
+
+/// A task definition to be registered by [`TaskQueue::register_task_definition`].
+#[derive(Debug, Clone, Copy)]
+pub struct NewTaskDefinition<'a> {
+    pub definition_id: &'a str,
+    pub name: &'a str,
+    pub description: &'a str,
+    pub parameters: &'a str,
 }
 
 #[async_trait]
@@ -34,7 +1206,7 @@ pub trait TaskQueue {
         &self,
         queue_id: &str,
         partition_id: i16,
-        payloads: Vec<&[u8]>,
+        tasks: Vec<NewTask<'_>>,
     ) -> Result<Vec<TaskId>>;
 
     async fn process_tasks<T: TaskProcessor + Sync>(
@@ -49,12 +1221,330 @@ pub trait TaskQueue {
         &self,
         queue_id: &str,
         partition_id: i16,
-        status: i16,
+        status: TaskStatus,
         count: i64,
     ) -> Result<Vec<TaskData>>;
+
+    /// Leases up to `count` due tasks for `lease_ms`, earliest `scheduled_at` first, for
+    /// workers that process tasks outside of [`TaskQueue::process_tasks`]. The claim is
+    /// committed before returning.
+    async fn lease_tasks(
+        &self,
+        queue_id: &str,
+        partition_id: i16,
+        count: i64,
+        lease_ms: i64,
+    ) -> Result<Vec<LeasedTask>>;
+
+    /// Looks up a single task, `None` if it does not exist.
+    async fn get_task(&self, task_id: &TaskId) -> Result<Option<TaskData>>;
+
+    /// Completes a leased task, saving `result` with it. Returns `false` if `lease_token` is not
+    /// the task's current lease.
+    async fn ack_task(
+        &self,
+        task_id: &TaskId,
+        lease_token: &str,
+        result: Option<&[u8]>,
+    ) -> Result<bool>;
+
+    /// Extends a leased task's lease to `lease_ms` from now, capped by its timeout, and saves
+    /// `progress` when given. Returns the new `lease_expires_at`, or `None` if `lease_token` is
+    /// not the task's current lease or the lease has already lapsed.
+    async fn heartbeat_task(
+        &self,
+        task_id: &TaskId,
+        lease_token: &str,
+        lease_ms: i64,
+        progress: Option<&TaskProgress>,
+    ) -> Result<Option<i64>>;
+
+    /// Records a failed attempt at a leased task, which is retried according to the queue's
+    /// [`RetryPolicy`]. Returns `false` if `lease_token` is not the task's current lease.
+    async fn nack_task(&self, task_id: &TaskId, lease_token: &str, error: &str) -> Result<bool>;
+
+    /// Hands a leased task back to the queue without counting the attempt, for workers that
+    /// give up a task they never ran. Returns `false` if `lease_token` is not the task's
+    /// current lease.
+    async fn release_task(&self, task_id: &TaskId, lease_token: &str) -> Result<bool>;
+
+    /// Whether `lease_token` still holds the task, so cooperative workers can stop early once
+    /// it has been cancelled.
+    async fn lease_state(&self, task_id: &TaskId, lease_token: &str) -> Result<LeaseState>;
+
+    /// Cancels a task that has not finished. A leased task keeps its lease token, so the
+    /// worker running it sees [`LeaseState::Cancelled`]. Returns `false` if the task does not
+    /// exist or has already finished.
+    async fn cancel_task(&self, task_id: &TaskId) -> Result<bool>;
+
+    /// Moves a task that is waiting to run to a new due time, `0` for now. Returns `false` if
+    /// the task does not exist or is no longer waiting.
+    async fn reschedule_task(&self, task_id: &TaskId, scheduled_at: i64) -> Result<bool>;
+
+    /// Every task connected to `task_id` through dependencies, with the edges between them.
+    /// Blocked tasks whose parents have finished are released first. `None` if the task does
+    /// not exist.
+    async fn task_graph(&self, task_id: &TaskId) -> Result<Option<TaskGraph>>;
+
+    async fn set_retry_policy(&self, queue_id: &str, policy: &RetryPolicy) -> Result<()>;
+
+    /// The queue's retry policy, or the default if none has been set.
+    async fn retry_policy(&self, queue_id: &str) -> Result<RetryPolicy>;
+
+    /// Tasks that used up their attempts, oldest first. Each task's `last_error` holds the
+    /// failure that exhausted it.
+    async fn dead_lettered_tasks(
+        &self,
+        queue_id: &str,
+        partition_id: i16,
+        count: i64,
+    ) -> Result<Vec<TaskData>> {
+        self.query_tasks(queue_id, partition_id, TaskStatus::DeadLettered, count)
+            .await
+    }
+
+    /// Puts the given dead-lettered tasks back to pending with a fresh set of attempts.
+    /// Tasks that are not dead-lettered are left alone. Returns how many were redriven.
+    async fn redrive_tasks(&self, task_ids: &[TaskId]) -> Result<u64>;
+
+    /// [`TaskQueue::redrive_tasks`] for every dead-lettered task in the partition.
+    async fn redrive_all_tasks(&self, queue_id: &str, partition_id: i16) -> Result<u64>;
+
+    /// Deletes every dead-lettered task in the partition. Returns how many were deleted.
+    async fn purge_dead_lettered(&self, queue_id: &str, partition_id: i16) -> Result<u64>;
+
+    /// Creates the schedule, or replaces the one with the same id.
+    async fn put_schedule(&self, schedule: &Schedule) -> Result<()>;
+
+    async fn get_schedule(&self, schedule_id: &str) -> Result<Option<Schedule>>;
+
+    /// Every schedule, by id.
+    async fn list_schedules(&self) -> Result<Vec<Schedule>>;
+
+    /// Returns `false` if the schedule does not exist.
+    async fn delete_schedule(&self, schedule_id: &str) -> Result<bool>;
+
+    /// Up to `count` schedules that are due to fire at `now`, most overdue first. Pass the
+    /// last schedule of a page as `after` for the next one.
+    async fn due_schedules(
+        &self,
+        now: i64,
+        after: Option<&Schedule>,
+        count: i64,
+    ) -> Result<Vec<Schedule>>;
+
+    /// Moves a schedule's `next_fire_at` from `from` to `to`. Returns `false` if it no longer
+    /// fires at `from`, because it was advanced by someone else, replaced or deleted.
+    async fn advance_schedule(&self, schedule_id: &str, from: i64, to: i64) -> Result<bool>;
+
+    /// Creates a running workflow, records its [`WorkflowEventKind::WorkflowStarted`] event and
+    /// enqueues its first workflow task. Returns `false` if the workflow id is taken.
+    async fn start_workflow(&self, workflow: &NewWorkflow<'_>) -> Result<bool>;
+
+    async fn get_workflow(&self, workflow_id: &str) -> Result<Option<Workflow>>;
+
+    /// The workflow's events, oldest first. Empty if the workflow does not exist.
+    async fn workflow_history(&self, workflow_id: &str) -> Result<Vec<WorkflowEvent>>;
+
+    /// Appends `events` to a running workflow whose history still ends at `last_event_id`, and
+    /// enqueues a workflow task to replay it unless the events finish it or put it to sleep.
+    /// Every [`WorkflowEventKind::TimerStarted`] event sets a [`WorkflowTimer`], a
+    /// [`WorkflowEventKind::SignalAwaited`] event blocks the workflow on that signal, and a
+    /// [`WorkflowEventKind::ChildWorkflowStarted`] event starts a child workflow on the same
+    /// queue. Finishing a child records how in its parent's history and wakes the parent.
+    /// Returns the new `last_event_id`, or `None` if the history has moved on or the workflow
+    /// has finished.
+    async fn append_workflow_events(
+        &self,
+        workflow_id: &str,
+        last_event_id: i64,
+        events: &[NewWorkflowEvent],
+    ) -> Result<Option<i64>>;
+
+    /// Up to `count` timers due to fire at `now`, most overdue first. Pass the last timer of a
+    /// page as `after` for the next one.
+    async fn due_workflow_timers(
+        &self,
+        now: i64,
+        after: Option<&WorkflowTimer>,
+        count: i64,
+    ) -> Result<Vec<WorkflowTimer>>;
+
+    /// Removes the timer and, while its workflow is running, records a
+    /// [`WorkflowEventKind::TimerFired`] event and enqueues a workflow task to replay it.
+    /// Returns `false` if the timer was already fired.
+    async fn fire_workflow_timer(&self, timer: &WorkflowTimer) -> Result<bool>;
+
+    /// Records a [`WorkflowEventKind::SignalReceived`] event on a running workflow and, unless
+    /// it is blocked on another signal, enqueues a workflow task to replay it. Returns the new
+    /// `last_event_id`, or `None` if the workflow does not exist or has finished.
+    async fn signal_workflow(
+        &self,
+        workflow_id: &str,
+        name: &str,
+        payload: &[u8],
+    ) -> Result<Option<i64>>;
+
+    /// Stores `definition` as the next version of its id, unless it matches the latest
+    /// version, which is returned instead. Registering the same definition from every worker
+    /// that starts up leaves one version.
+    async fn register_task_definition(
+        &self,
+        definition: &NewTaskDefinition<'_>,
+    ) -> Result<TaskDefinition>;
+
+    /// The given version of the task definition, or the latest one for `None`.
+    async fn get_task_definition(
+        &self,
+        definition_id: &str,
+        version: Option<i32>,
+    ) -> Result<Option<TaskDefinition>>;
+
+    /// The latest version of every task definition, by id.
+    async fn list_task_definitions(&self) -> Result<Vec<TaskDefinition>>;
 }
 
 #[async_trait]
 pub trait TaskProcessor: Sync {
-    async fn process_task(&self, task: TaskData) -> Result<()>;
+    /// Runs a task, returning the result to save with it, if any. Tasks that run for longer
+    /// than the queue's lease duration have to `heartbeat`, or their lease lapses and the task
+    /// is handed to someone else.
+    async fn process_task(
+        &self,
+        task: TaskData,
+        heartbeat: &dyn Heartbeat,
+    ) -> Result<Option<TaskPayload>>;
+}
+
+/// Keeps the lease on a task that is being processed.
+#[async_trait]
+pub trait Heartbeat: Sync {
+    /// Extends the lease and saves `progress` when given. Anything but [`LeaseState::Held`]
+    /// means the task is no longer the processor's to finish and it should stop.
+    async fn beat(&self, progress: Option<TaskProgress>) -> Result<LeaseState>;
+}
+
+/// The [`Heartbeat`] backends hand to processors, renewing a lease for `lease_ms` at a time.
+pub struct LeaseHeartbeat<'a, Q> {
+    task_queue: &'a Q,
+    task_id: TaskId,
+    lease_token: String,
+    lease_ms: i64,
+}
+
+impl<'a, Q> LeaseHeartbeat<'a, Q> {
+    pub fn new(task_queue: &'a Q, leased: &LeasedTask, lease_ms: i64) -> Self {
+        Self {
+            task_queue,
+            task_id: leased.task.task_id.clone(),
+            lease_token: leased.lease_token.clone(),
+            lease_ms,
+        }
+    }
+}
+
+#[async_trait]
+impl<Q: TaskQueue + Sync> Heartbeat for LeaseHeartbeat<'_, Q> {
+    async fn beat(&self, progress: Option<TaskProgress>) -> Result<LeaseState> {
+        let renewed = self
+            .task_queue
+            .heartbeat_task(
+                &self.task_id,
+                &self.lease_token,
+                self.lease_ms,
+                progress.as_ref(),
+            )
+            .await?;
+
+        if renewed.is_some() {
+            return Ok(LeaseState::Held);
+        }
+
+        // A lapsed lease may not have been swept yet, so only a cancellation is passed on.
+        match self
+            .task_queue
+            .lease_state(&self.task_id, &self.lease_token)
+            .await?
+        {
+            LeaseState::Cancelled => Ok(LeaseState::Cancelled),
+            _ => Ok(LeaseState::Lost),
+        }
+    }
+}
+
+/// Runs a leased task through `task_processor`. A task that overruns its timeout fails the
+/// same way a remote worker whose lease runs out does.
+pub async fn run_task<T: TaskProcessor>(
+    task_processor: &T,
+    task: TaskData,
+    heartbeat: &dyn Heartbeat,
+) -> Result<Option<TaskPayload>> {
+    let timeout_ms = task.timeout_ms;
+    let processed = task_processor.process_task(task, heartbeat);
+
+    match timeout_ms {
+        Some(timeout_ms) => {
+            tokio::time::timeout(Duration::from_millis(timeout_ms as u64), processed)
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!(timed_out_reason(timeout_ms))))
+        }
+        None => processed.await,
+    }
+}
+
+/// The error recorded against a task that ran past its timeout.
+pub fn timed_out_reason(timeout_ms: i64) -> String {
+    format!("timed out after {}ms", timeout_ms)
+}
+
+/// Milliseconds since the unix epoch, the unit used for all task timestamps.
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// A source of the current time in unix millis, so backends can be driven by a fake clock.
+pub trait Clock: Send + Sync {
+    fn now_millis(&self) -> i64;
+}
+
+/// The wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        now_millis()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now: Arc<AtomicI64>,
+}
+
+impl ManualClock {
+    pub fn new(now: i64) -> Self {
+        Self {
+            now: Arc::new(AtomicI64::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now
+            .fetch_add(duration.as_millis() as i64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
 }
@@ -0,0 +1,1270 @@
+use super::common::{
+    awaited_signal, batch_dependencies, check_dependencies, check_max_attempts,
+    check_workflow_events, child_workflow_finished, child_workflows, effective_priority,
+    replays_workflow, run_task, timed_out_reason, waiting_status, workflow_outcome,
+    workflow_timers, Clock, LeaseHeartbeat, LeaseState, LeasedTask, NewTask, NewTaskDefinition,
+    NewWorkflow, NewWorkflowEvent, ParentFailurePolicy, RetryPolicy, Schedule, SystemClock,
+    TaskData, TaskDefinition, TaskDependency, TaskGraph, TaskId, TaskProcessor, TaskProgress,
+    TaskQueue, TaskStatus, Workflow, WorkflowEvent, WorkflowEventKind, WorkflowStatus,
+    WorkflowTimer, CANCELLED_REASON, DEADLINE_EXCEEDED_REASON, DEFAULT_IDEMPOTENCY_RETENTION,
+    DEPENDENCY_FAILED_REASON, LEASE_EXPIRED_REASON,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+const DEFAULT_LEASE_DURATION_MS: i64 = 30_000;
+
+struct StoredTask {
+    data: TaskData,
+    lease_token: Option<String>,
+    leased_at: Option<i64>,
+    lease_expires_at: Option<i64>,
+    on_parent_failure: ParentFailurePolicy,
+}
+
+impl StoredTask {
+    fn holds_lease(&self, lease_token: &str) -> bool {
+        self.data.status == TaskStatus::Leased && self.lease_token.as_deref() == Some(lease_token)
+    }
+
+    fn clear_lease(&mut self) {
+        self.lease_token = None;
+        self.leased_at = None;
+        self.lease_expires_at = None;
+    }
+
+    /// What the task moves to while blocked, given the statuses of its parents. `None` while
+    /// it has to keep waiting.
+    fn unblocked_status(&self, parents: &[TaskStatus], now: i64) -> Option<TaskStatus> {
+        let policy = self.on_parent_failure;
+
+        if parents.iter().any(|parent| parent.fails_dependents()) {
+            if let Some(status) = policy.failed_status() {
+                return Some(status);
+            }
+        }
+
+        let finished = parents.iter().all(|parent| {
+            *parent == TaskStatus::Succeeded
+                || (policy == ParentFailurePolicy::Run && parent.fails_dependents())
+        });
+
+        finished.then(|| waiting_status(self.data.scheduled_at, now))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    next_seq_id: i64,
+    /// Tasks by queue partition, keyed by `seq_id`.
+    partitions: HashMap<(String, i16), BTreeMap<i64, StoredTask>>,
+    retry_policies: HashMap<String, RetryPolicy>,
+    /// Task ids by queue and idempotency key, with when the key expires.
+    idempotency_keys: HashMap<(String, String), (TaskId, i64)>,
+    /// The parents of every task that has any.
+    dependencies: HashMap<TaskId, Vec<TaskId>>,
+    schedules: BTreeMap<String, Schedule>,
+    /// Workflows by id, with their history.
+    workflows: HashMap<String, (Workflow, Vec<WorkflowEvent>)>,
+    /// Timers that have yet to fire, by workflow and timer id.
+    workflow_timers: BTreeMap<(String, i64), WorkflowTimer>,
+    /// Every version of every task definition, oldest first, by id.
+    task_definitions: BTreeMap<String, Vec<TaskDefinition>>,
+}
+
+impl State {
+    fn retry_policy(&self, queue_id: &str) -> RetryPolicy {
+        self.retry_policies
+            .get(queue_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn partition(&mut self, queue_id: &str, partition_id: i16) -> &mut BTreeMap<i64, StoredTask> {
+        self.partitions
+            .entry((queue_id.to_string(), partition_id))
+            .or_default()
+    }
+
+    fn task(&mut self, task_id: &TaskId) -> Option<&mut StoredTask> {
+        self.partitions
+            .get_mut(&(task_id.queue_id().to_string(), task_id.partition_id()))
+            .and_then(|tasks| tasks.get_mut(&task_id.seq_id()))
+    }
+
+    fn insert_task(
+        &mut self,
+        queue_id: &str,
+        partition_id: i16,
+        task: &NewTask<'_>,
+        policy: &RetryPolicy,
+        now: i64,
+    ) -> TaskId {
+        self.next_seq_id += 1;
+
+        let task_id = TaskId::from_parts(queue_id, partition_id, self.next_seq_id);
+
+        let stored = StoredTask {
+            data: TaskData {
+                task_id: task_id.clone(),
+                status: task.initial_status(now),
+                payload: task.payload.to_vec(),
+                content_type: task.content_type.map(str::to_string),
+                scheduled_at: task.due_at(now),
+                deadline_at: task.deadline_at,
+                timeout_ms: task.timeout_ms,
+                last_error: None,
+                attempts: 0,
+                max_attempts: task.max_attempts.unwrap_or(policy.max_attempts),
+                priority: task.priority,
+                result: None,
+                progress: None,
+                definition_id: task.definition_id.map(str::to_string),
+                definition_version: task.definition_version,
+            },
+            lease_token: None,
+            leased_at: None,
+            lease_expires_at: None,
+            on_parent_failure: task.on_parent_failure,
+        };
+
+        self.partition(queue_id, partition_id)
+            .insert(task_id.seq_id(), stored);
+
+        task_id
+    }
+
+    /// Enqueues a task to replay the workflow.
+    fn enqueue_workflow_task(&mut self, workflow: &Workflow, now: i64) {
+        let policy = self.retry_policy(&workflow.queue_id);
+        let task = NewTask::workflow_task(&workflow.workflow_id);
+
+        self.insert_task(
+            &workflow.queue_id,
+            workflow.partition_id,
+            &task,
+            &policy,
+            now,
+        );
+    }
+
+    /// Creates the workflow and enqueues its first workflow task, unless the id is taken.
+    fn insert_workflow(
+        &mut self,
+        workflow: &NewWorkflow<'_>,
+        parent_workflow_id: Option<&str>,
+        now: i64,
+    ) -> bool {
+        if self.workflows.contains_key(workflow.workflow_id) {
+            return false;
+        }
+
+        let started = WorkflowEvent {
+            event_id: 1,
+            kind: WorkflowEventKind::WorkflowStarted,
+            name: Some(workflow.workflow_type.to_string()),
+            payload: Some(workflow.input.to_vec()),
+            error: None,
+            fire_at: None,
+            recorded_at: now,
+        };
+
+        let workflow = Workflow {
+            workflow_id: workflow.workflow_id.to_string(),
+            workflow_type: workflow.workflow_type.to_string(),
+            queue_id: workflow.queue_id.to_string(),
+            partition_id: workflow.partition_id,
+            status: WorkflowStatus::Running,
+            result: None,
+            error: None,
+            last_event_id: started.event_id,
+            awaiting_signal: None,
+            parent_workflow_id: parent_workflow_id.map(str::to_string),
+            created_at: now,
+        };
+
+        self.enqueue_workflow_task(&workflow, now);
+        self.workflows
+            .insert(workflow.workflow_id.clone(), (workflow, vec![started]));
+
+        true
+    }
+
+    /// Records an event the server writes on a running workflow and enqueues a workflow task
+    /// to replay it. `None` if the workflow does not exist or has finished.
+    fn record_workflow_event(
+        &mut self,
+        workflow_id: &str,
+        event: NewWorkflowEvent,
+        now: i64,
+    ) -> Option<i64> {
+        let (workflow, history) = self
+            .workflows
+            .get_mut(workflow_id)
+            .filter(|(workflow, _)| workflow.status == WorkflowStatus::Running)?;
+
+        workflow.last_event_id += 1;
+        history.push(WorkflowEvent {
+            event_id: workflow.last_event_id,
+            kind: event.kind,
+            name: event.name,
+            payload: event.payload,
+            error: event.error,
+            fire_at: event.fire_at,
+            recorded_at: now,
+        });
+
+        let workflow = workflow.clone();
+        self.enqueue_workflow_task(&workflow, now);
+
+        Some(workflow.last_event_id)
+    }
+
+    /// The task's status, with a task that no longer exists counted as failed.
+    fn parent_status(&self, task_id: &TaskId) -> TaskStatus {
+        self.partitions
+            .get(&(task_id.queue_id().to_string(), task_id.partition_id()))
+            .and_then(|tasks| tasks.get(&task_id.seq_id()))
+            .map_or(TaskStatus::Failed, |task| task.data.status)
+    }
+
+    /// The tasks depending on a task.
+    fn dependents(&self, parent_id: &TaskId) -> Vec<TaskId> {
+        self.dependencies
+            .iter()
+            .filter(|(_, parent_ids)| parent_ids.contains(parent_id))
+            .map(|(task_id, _)| task_id.clone())
+            .collect()
+    }
+
+    /// Moves the tasks depending on a task that has just finished on.
+    fn release_dependents(&mut self, task_id: &TaskId, now: i64) {
+        let blocked = self.dependents(task_id);
+
+        self.release_blocked_tasks(blocked, now);
+    }
+
+    /// Moves the given blocked tasks on once their parents have finished, failing or
+    /// cancelling them when a parent failed, and in turn the tasks depending on those.
+    fn release_blocked_tasks(&mut self, task_ids: Vec<TaskId>, now: i64) {
+        let mut blocked = VecDeque::from(task_ids);
+
+        while let Some(task_id) = blocked.pop_front() {
+            let parents: Vec<TaskStatus> = self
+                .dependencies
+                .get(&task_id)
+                .map(|parent_ids| {
+                    parent_ids
+                        .iter()
+                        .map(|parent_id| self.parent_status(parent_id))
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let Some(task) = self
+                .task(&task_id)
+                .filter(|task| task.data.status == TaskStatus::Blocked)
+            else {
+                continue;
+            };
+
+            let Some(status) = task.unblocked_status(&parents, now) else {
+                continue;
+            };
+
+            task.data.status = status;
+
+            if status.fails_dependents() {
+                task.data.last_error = Some(DEPENDENCY_FAILED_REASON.to_string());
+                blocked.extend(self.dependents(&task_id));
+            }
+        }
+    }
+}
+
+/// A [`TaskQueue`] that keeps everything in process memory, with the same ordering, leasing
+/// and status rules as the database backends.
+///
+/// Time comes from a [`Clock`], so tests and turmoil simulations can step through delays,
+/// deadlines and lease expiry without sleeping. Only `timeout_ms` on tasks run by
+/// [`TaskQueue::process_tasks`] is enforced with a tokio timer.
+pub struct InMemoryTaskQueue<C = SystemClock> {
+    state: Mutex<State>,
+    clock: C,
+    lease_duration_ms: i64,
+    idempotency_retention_ms: i64,
+    priority_aging_ms: Option<i64>,
+}
+
+impl InMemoryTaskQueue<SystemClock> {
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+}
+
+impl Default for InMemoryTaskQueue<SystemClock> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Clock> InMemoryTaskQueue<C> {
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+            clock,
+            lease_duration_ms: DEFAULT_LEASE_DURATION_MS,
+            idempotency_retention_ms: DEFAULT_IDEMPOTENCY_RETENTION.as_millis() as i64,
+            priority_aging_ms: None,
+        }
+    }
+
+    /// Sets how long tasks claimed by [`TaskQueue::process_tasks`] stay leased before
+    /// another caller may claim them again.
+    pub fn with_lease_duration(mut self, lease_duration: Duration) -> Self {
+        self.lease_duration_ms = lease_duration.as_millis() as i64;
+        self
+    }
+
+    /// Sets how long idempotency keys are remembered after the task using them is enqueued.
+    pub fn with_idempotency_retention(mut self, retention: Duration) -> Self {
+        self.idempotency_retention_ms = retention.as_millis() as i64;
+        self
+    }
+
+    /// Guards against starvation by lifting due tasks one priority level for every `aging`
+    /// they have waited.
+    pub fn with_priority_aging(mut self, aging: Duration) -> Self {
+        self.priority_aging_ms = Some((aging.as_millis() as i64).max(1));
+        self
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        // Nothing panics while holding the lock, but a poisoned queue is still usable.
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Marks a leased task succeeded and saves its result, provided `lease_token` still
+    /// identifies the current lease.
+    fn complete_lease(
+        &self,
+        task_id: &TaskId,
+        lease_token: &str,
+        result: Option<&[u8]>,
+    ) -> Result<bool> {
+        TaskStatus::Leased.check_transition(TaskStatus::Succeeded)?;
+
+        let mut state = self.state();
+
+        let Some(task) = state
+            .task(task_id)
+            .filter(|task| task.holds_lease(lease_token))
+        else {
+            return Ok(false);
+        };
+
+        task.data.status = TaskStatus::Succeeded;
+        task.data.result = result.map(<[u8]>::to_vec);
+        task.clear_lease();
+
+        state.release_dependents(task_id, self.clock.now_millis());
+
+        Ok(true)
+    }
+
+    /// Records a failed attempt at a leased task. The task is scheduled again after the
+    /// queue's backoff, or dead-lettered once it has used up its attempts.
+    fn fail_lease(&self, task_id: &TaskId, lease_token: &str, reason: &str) -> Result<bool> {
+        let now = self.clock.now_millis();
+        let mut state = self.state();
+        let policy = state.retry_policy(task_id.queue_id());
+
+        let Some(task) = state
+            .task(task_id)
+            .filter(|task| task.holds_lease(lease_token))
+        else {
+            return Ok(false);
+        };
+
+        fail_task(task, &policy, reason, now);
+
+        if task.data.status.fails_dependents() {
+            state.release_dependents(task_id, now);
+        }
+
+        Ok(true)
+    }
+
+    /// Fails tasks whose deadline has passed, retries tasks that ran past their timeout and
+    /// returns lapsed leases to the queue, the same as the database backends.
+    fn expire_tasks(state: &mut State, queue_id: &str, partition_id: i16, now: i64) {
+        let policy = state.retry_policy(queue_id);
+        let mut failed = Vec::new();
+
+        for task in state.partition(queue_id, partition_id).values_mut() {
+            let lapsed = task.data.status == TaskStatus::Leased
+                && task
+                    .lease_expires_at
+                    .is_some_and(|expires_at| expires_at <= now);
+
+            let past_deadline = task
+                .data
+                .deadline_at
+                .is_some_and(|deadline_at| deadline_at <= now);
+
+            let waiting = matches!(
+                task.data.status,
+                TaskStatus::Pending | TaskStatus::Scheduled | TaskStatus::Blocked
+            );
+
+            if past_deadline && (waiting || lapsed) {
+                task.data.status = TaskStatus::Failed;
+                task.data.last_error = Some(DEADLINE_EXCEEDED_REASON.to_string());
+                task.clear_lease();
+                failed.push(task.data.task_id.clone());
+                continue;
+            }
+
+            if task.data.status != TaskStatus::Leased {
+                continue;
+            }
+
+            let timed_out = match (task.leased_at, task.data.timeout_ms) {
+                (Some(leased_at), Some(timeout_ms)) => leased_at + timeout_ms <= now,
+                _ => false,
+            };
+
+            if timed_out {
+                let reason = timed_out_reason(task.data.timeout_ms.unwrap_or_default());
+                fail_task(task, &policy, &reason, now);
+            } else if lapsed {
+                task.data.status = if task.data.attempts >= task.data.max_attempts {
+                    TaskStatus::DeadLettered
+                } else {
+                    TaskStatus::Pending
+                };
+                task.data.last_error = Some(LEASE_EXPIRED_REASON.to_string());
+                task.clear_lease();
+            }
+
+            if task.data.status.fails_dependents() {
+                failed.push(task.data.task_id.clone());
+            }
+        }
+
+        for task_id in failed {
+            state.release_dependents(&task_id, now);
+        }
+    }
+
+    fn redrive(task: &mut StoredTask, now: i64) -> bool {
+        if task.data.status != TaskStatus::DeadLettered {
+            return false;
+        }
+
+        task.data.status = TaskStatus::Pending;
+        task.data.scheduled_at = now;
+        task.data.attempts = 0;
+
+        true
+    }
+}
+
+/// Schedules a retry of `task` after the policy's backoff, or dead-letters it once it has
+/// used up its attempts.
+fn fail_task(task: &mut StoredTask, policy: &RetryPolicy, reason: &str, now: i64) {
+    if task.data.attempts >= task.data.max_attempts {
+        task.data.status = TaskStatus::DeadLettered;
+    } else {
+        task.data.status = TaskStatus::Scheduled;
+        task.data.scheduled_at = now + policy.backoff_ms(task.data.attempts, rand::random());
+    }
+
+    task.data.last_error = Some(reason.to_string());
+    task.clear_lease();
+}
+
+#[async_trait]
+impl<C: Clock> TaskQueue for InMemoryTaskQueue<C> {
+    async fn enqueue_tasks(
+        &self,
+        queue_id: &str,
+        partition_id: i16,
+        tasks: Vec<NewTask<'_>>,
+    ) -> Result<Vec<TaskId>> {
+        check_dependencies(&tasks)?;
+        check_max_attempts(&tasks)?;
+
+        let now = self.clock.now_millis();
+        let mut state = self.state();
+        let policy = state.retry_policy(queue_id);
+        let mut task_ids = Vec::with_capacity(tasks.len());
+        let mut written = Vec::with_capacity(tasks.len());
+
+        state
+            .idempotency_keys
+            .retain(|_, (_, expires_at)| *expires_at > now);
+
+        for task in &tasks {
+            let key = task
+                .idempotency_key
+                .map(|key| (queue_id.to_string(), key.to_string()));
+
+            if let Some((task_id, _)) = key.as_ref().and_then(|key| state.idempotency_keys.get(key))
+            {
+                task_ids.push(task_id.clone());
+                continue;
+            }
+
+            let task_id = state.insert_task(queue_id, partition_id, task, &policy, now);
+
+            if let Some(key) = key {
+                let expires_at = now + self.idempotency_retention_ms;
+                state
+                    .idempotency_keys
+                    .insert(key, (task_id.clone(), expires_at));
+            }
+
+            written.push(task_id.clone());
+            task_ids.push(task_id);
+        }
+
+        let mut blocked = Vec::new();
+
+        for dependency in batch_dependencies(&tasks, &task_ids, &written) {
+            if blocked.last() != Some(&dependency.task_id) {
+                blocked.push(dependency.task_id.clone());
+            }
+
+            state
+                .dependencies
+                .entry(dependency.task_id)
+                .or_default()
+                .push(dependency.parent_id);
+        }
+
+        // Parents can be earlier tasks found by idempotency key, which may have finished.
+        state.release_blocked_tasks(blocked, now);
+
+        Ok(task_ids)
+    }
+
+    async fn process_tasks<T: TaskProcessor>(
+        &self,
+        queue_id: &str,
+        partition_id: i16,
+        count: i64,
+        task_processor: &T,
+    ) -> Result<()> {
+        let leased = self
+            .lease_tasks(queue_id, partition_id, count, self.lease_duration_ms)
+            .await?;
+
+        let futures = leased.into_iter().map(|leased| async move {
+            let task_id = leased.task.task_id.clone();
+            let heartbeat = LeaseHeartbeat::new(self, &leased, self.lease_duration_ms);
+            let result = run_task(task_processor, leased.task, &heartbeat).await;
+
+            let settled = match result {
+                Ok(result) => self.complete_lease(&task_id, &leased.lease_token, result.as_deref()),
+                Err(err) => {
+                    tracing::warn!(err = ?err, task_id = %task_id, "task_failed");
+
+                    self.fail_lease(&task_id, &leased.lease_token, &err.to_string())
+                }
+            }?;
+
+            if !settled {
+                tracing::warn!(task_id = %task_id, "task_lease_lost");
+            }
+
+            Ok::<_, anyhow::Error>(())
+        });
+
+        let results = futures::future::join_all(futures).await;
+
+        for result in results {
+            result?;
+        }
+
+        Ok(())
+    }
+
+    async fn query_tasks(
+        &self,
+        queue_id: &str,
+        partition_id: i16,
+        status: TaskStatus,
+        count: i64,
+    ) -> Result<Vec<TaskData>> {
+        let mut state = self.state();
+
+        let mut tasks: Vec<TaskData> = state
+            .partition(queue_id, partition_id)
+            .values()
+            .filter(|task| task.data.status == status)
+            .map(|task| task.data.clone())
+            .collect();
+
+        tasks.sort_by_key(|task| (task.scheduled_at, task.task_id.seq_id()));
+        tasks.truncate(count.max(0) as usize);
+
+        Ok(tasks)
+    }
+
+    async fn lease_tasks(
+        &self,
+        queue_id: &str,
+        partition_id: i16,
+        count: i64,
+        lease_ms: i64,
+    ) -> Result<Vec<LeasedTask>> {
+        let now = self.clock.now_millis();
+        let lease_token = nanoid::nanoid!();
+        let mut state = self.state();
+
+        Self::expire_tasks(&mut state, queue_id, partition_id, now);
+
+        let sources = TaskStatus::Leased.sources();
+        let priority_aging_ms = self.priority_aging_ms;
+        let tasks = state.partition(queue_id, partition_id);
+
+        let mut due: Vec<(Reverse<i64>, i64, i64)> = tasks
+            .values()
+            .filter(|task| {
+                sources.contains(&task.data.status)
+                    && task.data.scheduled_at <= now
+                    && task
+                        .data
+                        .deadline_at
+                        .map_or(true, |deadline_at| deadline_at > now)
+            })
+            .map(|task| {
+                let data = &task.data;
+                let priority =
+                    effective_priority(data.priority, data.scheduled_at, now, priority_aging_ms);
+
+                (Reverse(priority), data.scheduled_at, data.task_id.seq_id())
+            })
+            .collect();
+
+        due.sort();
+        due.truncate(count.max(0) as usize);
+
+        let mut leased = Vec::with_capacity(due.len());
+
+        for (_, _, seq_id) in due {
+            let Some(task) = tasks.get_mut(&seq_id) else {
+                continue;
+            };
+
+            let lease_expires_at = now + lease_ms.min(task.data.timeout_ms.unwrap_or(lease_ms));
+
+            task.data.status = TaskStatus::Leased;
+            task.data.attempts += 1;
+            task.data.progress = None;
+            task.lease_token = Some(lease_token.clone());
+            task.leased_at = Some(now);
+            task.lease_expires_at = Some(lease_expires_at);
+
+            leased.push(LeasedTask {
+                task: task.data.clone(),
+                lease_token: lease_token.clone(),
+                lease_expires_at,
+            });
+        }
+
+        Ok(leased)
+    }
+
+    async fn get_task(&self, task_id: &TaskId) -> Result<Option<TaskData>> {
+        Ok(self.state().task(task_id).map(|task| task.data.clone()))
+    }
+
+    async fn ack_task(
+        &self,
+        task_id: &TaskId,
+        lease_token: &str,
+        result: Option<&[u8]>,
+    ) -> Result<bool> {
+        self.complete_lease(task_id, lease_token, result)
+    }
+
+    async fn heartbeat_task(
+        &self,
+        task_id: &TaskId,
+        lease_token: &str,
+        lease_ms: i64,
+        progress: Option<&TaskProgress>,
+    ) -> Result<Option<i64>> {
+        let now = self.clock.now_millis();
+        let mut state = self.state();
+
+        let Some(task) = state.task(task_id).filter(|task| {
+            task.holds_lease(lease_token)
+                && task
+                    .lease_expires_at
+                    .map_or(false, |expires_at| expires_at > now)
+        }) else {
+            return Ok(None);
+        };
+
+        // Like a fresh lease, a renewal cannot run past the task's timeout.
+        let timeout_at = task
+            .leased_at
+            .zip(task.data.timeout_ms)
+            .map(|(leased_at, timeout_ms)| leased_at + timeout_ms);
+        let lease_expires_at = timeout_at.map_or(now + lease_ms, |timeout_at| {
+            (now + lease_ms).min(timeout_at)
+        });
+
+        task.lease_expires_at = Some(lease_expires_at);
+
+        if let Some(progress) = progress {
+            task.data.progress = Some(progress.clone());
+        }
+
+        Ok(Some(lease_expires_at))
+    }
+
+    async fn nack_task(&self, task_id: &TaskId, lease_token: &str, error: &str) -> Result<bool> {
+        self.fail_lease(task_id, lease_token, error)
+    }
+
+    async fn release_task(&self, task_id: &TaskId, lease_token: &str) -> Result<bool> {
+        TaskStatus::Leased.check_transition(TaskStatus::Pending)?;
+
+        let mut state = self.state();
+
+        let Some(task) = state
+            .task(task_id)
+            .filter(|task| task.holds_lease(lease_token))
+        else {
+            return Ok(false);
+        };
+
+        task.data.status = TaskStatus::Pending;
+        task.data.attempts -= 1;
+        task.clear_lease();
+
+        Ok(true)
+    }
+
+    async fn lease_state(&self, task_id: &TaskId, lease_token: &str) -> Result<LeaseState> {
+        let mut state = self.state();
+
+        let state = match state
+            .task(task_id)
+            .filter(|task| task.lease_token.as_deref() == Some(lease_token))
+            .map(|task| task.data.status)
+        {
+            Some(TaskStatus::Leased) => LeaseState::Held,
+            Some(TaskStatus::Cancelled) => LeaseState::Cancelled,
+            _ => LeaseState::Lost,
+        };
+
+        Ok(state)
+    }
+
+    async fn cancel_task(&self, task_id: &TaskId) -> Result<bool> {
+        let mut state = self.state();
+
+        let Some(task) = state
+            .task(task_id)
+            .filter(|task| task.data.status.can_transition_to(TaskStatus::Cancelled))
+        else {
+            return Ok(false);
+        };
+
+        // The lease token is kept so the worker holding it can tell it was cancelled.
+        task.data.status = TaskStatus::Cancelled;
+        task.data.last_error = Some(CANCELLED_REASON.to_string());
+        task.leased_at = None;
+        task.lease_expires_at = None;
+
+        state.release_dependents(task_id, self.clock.now_millis());
+
+        Ok(true)
+    }
+
+    async fn reschedule_task(&self, task_id: &TaskId, scheduled_at: i64) -> Result<bool> {
+        let now = self.clock.now_millis();
+        let due_at = if scheduled_at > 0 { scheduled_at } else { now };
+        let mut state = self.state();
+
+        let Some(task) = state.task(task_id).filter(|task| {
+            matches!(
+                task.data.status,
+                TaskStatus::Pending | TaskStatus::Scheduled
+            )
+        }) else {
+            return Ok(false);
+        };
+
+        task.data.status = waiting_status(due_at, now);
+        task.data.scheduled_at = due_at;
+
+        Ok(true)
+    }
+
+    async fn task_graph(&self, task_id: &TaskId) -> Result<Option<TaskGraph>> {
+        let mut state = self.state();
+
+        if state.task(task_id).is_none() {
+            return Ok(None);
+        }
+
+        let dependencies: Vec<TaskDependency> = state
+            .dependencies
+            .iter()
+            .flat_map(|(task_id, parent_ids)| {
+                parent_ids.iter().map(|parent_id| TaskDependency {
+                    task_id: task_id.clone(),
+                    parent_id: parent_id.clone(),
+                })
+            })
+            .collect();
+
+        // Walks the edges both ways, from the task to its parents and to its dependents.
+        let mut members = HashSet::from([task_id.clone()]);
+        let mut unvisited = VecDeque::from([task_id.clone()]);
+
+        while let Some(member) = unvisited.pop_front() {
+            for dependency in &dependencies {
+                let neighbour = if dependency.task_id == member {
+                    &dependency.parent_id
+                } else if dependency.parent_id == member {
+                    &dependency.task_id
+                } else {
+                    continue;
+                };
+
+                if members.insert(neighbour.clone()) {
+                    unvisited.push_back(neighbour.clone());
+                }
+            }
+        }
+
+        let mut tasks: Vec<TaskData> = members
+            .iter()
+            .filter_map(|member| state.task(member).map(|task| task.data.clone()))
+            .collect();
+
+        tasks.sort_by_key(|task| task.task_id.seq_id());
+
+        // Purged tasks drop out of the graph along with their edges.
+        let present: HashSet<&TaskId> = tasks.iter().map(|task| &task.task_id).collect();
+
+        let mut dependencies: Vec<TaskDependency> = dependencies
+            .into_iter()
+            .filter(|dependency| {
+                present.contains(&dependency.task_id) && present.contains(&dependency.parent_id)
+            })
+            .collect();
+
+        dependencies
+            .sort_by_key(|dependency| (dependency.task_id.seq_id(), dependency.parent_id.seq_id()));
+
+        Ok(Some(TaskGraph {
+            tasks,
+            dependencies,
+        }))
+    }
+
+    async fn set_retry_policy(&self, queue_id: &str, policy: &RetryPolicy) -> Result<()> {
+        policy.validate()?;
+
+        self.state()
+            .retry_policies
+            .insert(queue_id.to_string(), policy.clone());
+
+        Ok(())
+    }
+
+    async fn retry_policy(&self, queue_id: &str) -> Result<RetryPolicy> {
+        Ok(self.state().retry_policy(queue_id))
+    }
+
+    async fn redrive_tasks(&self, task_ids: &[TaskId]) -> Result<u64> {
+        TaskStatus::DeadLettered.check_transition(TaskStatus::Pending)?;
+
+        let now = self.clock.now_millis();
+        let mut state = self.state();
+
+        let redriven = task_ids
+            .iter()
+            .filter(|task_id| {
+                state
+                    .task(task_id)
+                    .is_some_and(|task| Self::redrive(task, now))
+            })
+            .count();
+
+        Ok(redriven as u64)
+    }
+
+    async fn redrive_all_tasks(&self, queue_id: &str, partition_id: i16) -> Result<u64> {
+        TaskStatus::DeadLettered.check_transition(TaskStatus::Pending)?;
+
+        let now = self.clock.now_millis();
+        let mut state = self.state();
+
+        let redriven = state
+            .partition(queue_id, partition_id)
+            .values_mut()
+            .map(|task| Self::redrive(task, now))
+            .filter(|redriven| *redriven)
+            .count();
+
+        Ok(redriven as u64)
+    }
+
+    async fn purge_dead_lettered(&self, queue_id: &str, partition_id: i16) -> Result<u64> {
+        let mut state = self.state();
+        let tasks = state.partition(queue_id, partition_id);
+
+        let purged: HashSet<TaskId> = tasks
+            .values()
+            .filter(|task| task.data.status == TaskStatus::DeadLettered)
+            .map(|task| task.data.task_id.clone())
+            .collect();
+
+        tasks.retain(|_, task| !purged.contains(&task.data.task_id));
+
+        // Keys pointing at a purged task would keep resolving to it.
+        state
+            .idempotency_keys
+            .retain(|_, (task_id, _)| !purged.contains(task_id));
+        state
+            .dependencies
+            .retain(|task_id, _| !purged.contains(task_id));
+
+        for parent_ids in state.dependencies.values_mut() {
+            parent_ids.retain(|parent_id| !purged.contains(parent_id));
+        }
+
+        Ok(purged.len() as u64)
+    }
+
+    async fn put_schedule(&self, schedule: &Schedule) -> Result<()> {
+        self.state()
+            .schedules
+            .insert(schedule.schedule_id.clone(), schedule.clone());
+
+        Ok(())
+    }
+
+    async fn get_schedule(&self, schedule_id: &str) -> Result<Option<Schedule>> {
+        Ok(self.state().schedules.get(schedule_id).cloned())
+    }
+
+    async fn list_schedules(&self) -> Result<Vec<Schedule>> {
+        Ok(self.state().schedules.values().cloned().collect())
+    }
+
+    async fn delete_schedule(&self, schedule_id: &str) -> Result<bool> {
+        Ok(self.state().schedules.remove(schedule_id).is_some())
+    }
+
+    async fn due_schedules(
+        &self,
+        now: i64,
+        after: Option<&Schedule>,
+        count: i64,
+    ) -> Result<Vec<Schedule>> {
+        let after = after.map(|schedule| (schedule.next_fire_at, &schedule.schedule_id));
+
+        let mut due: Vec<Schedule> = self
+            .state()
+            .schedules
+            .values()
+            .filter(|schedule| schedule.next_fire_at <= now)
+            // `None` sorts before any key, so without `after` every schedule is kept.
+            .filter(|schedule| Some((schedule.next_fire_at, &schedule.schedule_id)) > after)
+            .cloned()
+            .collect();
+
+        due.sort_by(|a, b| (a.next_fire_at, &a.schedule_id).cmp(&(b.next_fire_at, &b.schedule_id)));
+        due.truncate(count.max(0) as usize);
+
+        Ok(due)
+    }
+
+    async fn advance_schedule(&self, schedule_id: &str, from: i64, to: i64) -> Result<bool> {
+        let mut state = self.state();
+
+        let Some(schedule) = state
+            .schedules
+            .get_mut(schedule_id)
+            .filter(|schedule| schedule.next_fire_at == from)
+        else {
+            return Ok(false);
+        };
+
+        schedule.next_fire_at = to;
+
+        Ok(true)
+    }
+
+    async fn start_workflow(&self, workflow: &NewWorkflow<'_>) -> Result<bool> {
+        let now = self.clock.now_millis();
+
+        Ok(self.state().insert_workflow(workflow, None, now))
+    }
+
+    async fn get_workflow(&self, workflow_id: &str) -> Result<Option<Workflow>> {
+        Ok(self
+            .state()
+            .workflows
+            .get(workflow_id)
+            .map(|(workflow, _)| workflow.clone()))
+    }
+
+    async fn workflow_history(&self, workflow_id: &str) -> Result<Vec<WorkflowEvent>> {
+        Ok(self
+            .state()
+            .workflows
+            .get(workflow_id)
+            .map(|(_, history)| history.clone())
+            .unwrap_or_default())
+    }
+
+    async fn append_workflow_events(
+        &self,
+        workflow_id: &str,
+        last_event_id: i64,
+        events: &[NewWorkflowEvent],
+    ) -> Result<Option<i64>> {
+        check_workflow_events(events)?;
+
+        let now = self.clock.now_millis();
+        let mut state = self.state();
+
+        let Some((workflow, history)) =
+            state
+                .workflows
+                .get_mut(workflow_id)
+                .filter(|(workflow, _)| {
+                    workflow.status == WorkflowStatus::Running
+                        && workflow.last_event_id == last_event_id
+                })
+        else {
+            return Ok(None);
+        };
+
+        let timers = workflow_timers(workflow_id, last_event_id, events);
+        let children = child_workflows(workflow_id, last_event_id, events);
+
+        for event in events {
+            workflow.last_event_id += 1;
+            history.push(WorkflowEvent {
+                event_id: workflow.last_event_id,
+                kind: event.kind,
+                name: event.name.clone(),
+                payload: event.payload.clone(),
+                error: event.error.clone(),
+                fire_at: event.fire_at,
+                recorded_at: now,
+            });
+        }
+
+        let (status, result, error) = workflow_outcome(events);
+
+        workflow.status = status;
+        workflow.result = result.map(<[u8]>::to_vec);
+        workflow.error = error.map(str::to_string);
+        workflow.awaiting_signal = awaited_signal(events).map(str::to_string);
+
+        let workflow = workflow.clone();
+
+        for timer in timers {
+            state
+                .workflow_timers
+                .insert((timer.workflow_id.clone(), timer.timer_id), timer);
+        }
+
+        for (child_id, event) in children {
+            let child = NewWorkflow {
+                workflow_id: &child_id,
+                workflow_type: event.name.as_deref().unwrap_or_default(),
+                queue_id: &workflow.queue_id,
+                partition_id: workflow.partition_id,
+                input: event.payload.as_deref().unwrap_or_default(),
+            };
+
+            state.insert_workflow(&child, Some(workflow_id), now);
+        }
+
+        if replays_workflow(events) {
+            state.enqueue_workflow_task(&workflow, now);
+        }
+
+        if let Some(parent_workflow_id) = &workflow.parent_workflow_id {
+            if let Some(finished) = child_workflow_finished(workflow_id, events) {
+                state.record_workflow_event(parent_workflow_id, finished, now);
+            }
+        }
+
+        Ok(Some(workflow.last_event_id))
+    }
+
+    async fn due_workflow_timers(
+        &self,
+        now: i64,
+        after: Option<&WorkflowTimer>,
+        count: i64,
+    ) -> Result<Vec<WorkflowTimer>> {
+        let state = self.state();
+        let after = after.map(|timer| (timer.fire_at, &timer.workflow_id, timer.timer_id));
+
+        let mut due: Vec<WorkflowTimer> = state
+            .workflow_timers
+            .values()
+            .filter(|timer| timer.fire_at <= now)
+            .filter(|timer| Some((timer.fire_at, &timer.workflow_id, timer.timer_id)) > after)
+            .cloned()
+            .collect();
+
+        due.sort_by(|a, b| {
+            (a.fire_at, &a.workflow_id, a.timer_id).cmp(&(b.fire_at, &b.workflow_id, b.timer_id))
+        });
+        due.truncate(count.max(0) as usize);
+
+        Ok(due)
+    }
+
+    async fn fire_workflow_timer(&self, timer: &WorkflowTimer) -> Result<bool> {
+        let now = self.clock.now_millis();
+        let mut state = self.state();
+
+        if state
+            .workflow_timers
+            .remove(&(timer.workflow_id.clone(), timer.timer_id))
+            .is_none()
+        {
+            return Ok(false);
+        }
+
+        let fired = NewWorkflowEvent {
+            kind: WorkflowEventKind::TimerFired,
+            name: Some(timer.name.clone()),
+            payload: None,
+            error: None,
+            fire_at: Some(timer.fire_at),
+        };
+
+        state.record_workflow_event(&timer.workflow_id, fired, now);
+
+        Ok(true)
+    }
+
+    async fn signal_workflow(
+        &self,
+        workflow_id: &str,
+        name: &str,
+        payload: &[u8],
+    ) -> Result<Option<i64>> {
+        let now = self.clock.now_millis();
+        let mut state = self.state();
+
+        let Some((workflow, history)) = state
+            .workflows
+            .get_mut(workflow_id)
+            .filter(|(workflow, _)| workflow.status == WorkflowStatus::Running)
+        else {
+            return Ok(None);
+        };
+
+        workflow.last_event_id += 1;
+        history.push(WorkflowEvent {
+            event_id: workflow.last_event_id,
+            kind: WorkflowEventKind::SignalReceived,
+            name: Some(name.to_string()),
+            payload: Some(payload.to_vec()),
+            error: None,
+            fire_at: None,
+            recorded_at: now,
+        });
+
+        if workflow.awaiting_signal.as_deref() == Some(name) {
+            workflow.awaiting_signal = None;
+        }
+
+        let workflow = workflow.clone();
+
+        // A workflow blocked on nothing may have a replay under way, which loses the race for
+        // the history to the signal and has to be followed by another.
+        if workflow.awaiting_signal.is_none() {
+            state.enqueue_workflow_task(&workflow, now);
+        }
+
+        Ok(Some(workflow.last_event_id))
+    }
+
+    async fn register_task_definition(
+        &self,
+        definition: &NewTaskDefinition<'_>,
+    ) -> Result<TaskDefinition> {
+        let now = self.clock.now_millis();
+        let mut state = self.state();
+
+        let versions = state
+            .task_definitions
+            .entry(definition.definition_id.to_string())
+            .or_default();
+
+        if let Some(latest) = versions.last().filter(|latest| latest.matches(definition)) {
+            return Ok(latest.clone());
+        }
+
+        let registered = TaskDefinition {
+            definition_id: definition.definition_id.to_string(),
+            version: versions.len() as i32 + 1,
+            name: definition.name.to_string(),
+            description: definition.description.to_string(),
+            parameters: definition.parameters.to_string(),
+            created_at: now,
+        };
+
+        versions.push(registered.clone());
+
+        Ok(registered)
+    }
+
+    async fn get_task_definition(
+        &self,
+        definition_id: &str,
+        version: Option<i32>,
+    ) -> Result<Option<TaskDefinition>> {
+        let state = self.state();
+        let Some(versions) = state.task_definitions.get(definition_id) else {
+            return Ok(None);
+        };
+
+        let definition = match version {
+            Some(version) => versions
+                .iter()
+                .find(|definition| definition.version == version),
+            None => versions.last(),
+        };
+
+        Ok(definition.cloned())
+    }
+
+    async fn list_task_definitions(&self) -> Result<Vec<TaskDefinition>> {
+        Ok(self
+            .state()
+            .task_definitions
+            .values()
+            .filter_map(|versions| versions.last().cloned())
+            .collect())
+    }
+}
@@ -0,0 +1,84 @@
+//! Versioned schema migrations.
+//!
+//! Each backend embeds its own forward-only list of migrations and records the versions it
+//! has applied in `svppl_schema_version`. Nodes migrate on startup, `svppl migrate` does the
+//! same from the command line.
+
+use anyhow::{bail, Result};
+
+use super::{
+    postgres::{self, PersistencePostgres},
+    sqlite::{self, PersistenceSqlite},
+};
+
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    /// When the migration was applied, in milliseconds since the epoch.
+    pub applied_at: Option<i64>,
+}
+
+/// The migrations missing from `applied`, in version order.
+///
+/// Fails if the database holds a version this binary does not know, as it has been migrated
+/// by a newer release.
+pub fn pending<'a>(migrations: &'a [Migration], applied: &[i64]) -> Result<Vec<&'a Migration>> {
+    let latest = migrations.iter().map(|m| m.version).max().unwrap_or(0);
+
+    if let Some(unknown) = applied.iter().find(|version| **version > latest) {
+        bail!(
+            "database schema version {} is newer than the latest known migration {}",
+            unknown,
+            latest
+        );
+    }
+
+    Ok(migrations
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .collect())
+}
+
+/// Pairs every known migration with when it was applied, given `(version, applied_at)` rows.
+pub fn status(migrations: &[Migration], applied: &[(i64, i64)]) -> Vec<MigrationStatus> {
+    migrations
+        .iter()
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            applied_at: applied
+                .iter()
+                .find(|(version, _)| *version == m.version)
+                .map(|(_, applied_at)| *applied_at),
+        })
+        .collect()
+}
+
+/// Connects to `database_url` and applies its pending migrations, returning their versions.
+pub async fn migrate_database(database_url: &str) -> Result<Vec<i64>> {
+    if sqlite::is_sqlite_url(database_url) {
+        let pool = sqlite::create_connection_pool(database_url).await?;
+        PersistenceSqlite::new(pool).migrate().await
+    } else {
+        let pool = postgres::create_connection_pool(database_url).await?;
+        PersistencePostgres::new(pool).migrate().await
+    }
+}
+
+/// Connects to `database_url` and reports which migrations it has applied.
+pub async fn database_status(database_url: &str) -> Result<Vec<MigrationStatus>> {
+    if sqlite::is_sqlite_url(database_url) {
+        let pool = sqlite::create_connection_pool(database_url).await?;
+        PersistenceSqlite::new(pool).migration_status().await
+    } else {
+        let pool = postgres::create_connection_pool(database_url).await?;
+        PersistencePostgres::new(pool).migration_status().await
+    }
+}
@@ -1,2 +1,5 @@
 pub mod common;
+pub mod memory;
+pub mod migrations;
 pub mod postgres;
+pub mod sqlite;
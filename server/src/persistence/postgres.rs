@@ -1,60 +1,251 @@
-use super::common::{TaskData, TaskId, TaskProcessor, TaskQueue};
-use anyhow::Result;
+use super::common::{
+    awaited_signal, batch_dependencies, check_dependencies, check_max_attempts,
+    check_workflow_events, child_workflow_finished, child_workflows, effective_priority,
+    now_millis, replays_workflow, run_task, timed_out_reason, waiting_status, workflow_outcome,
+    workflow_timers, ExpirySweeps, IdempotentBatch, LeaseHeartbeat, LeaseState, LeasedTask,
+    MisfirePolicy, NewTask, NewTaskDefinition, NewWorkflow, NewWorkflowEvent, ParentFailurePolicy,
+    RetryPolicy, Schedule, TaskData, TaskDefinition, TaskDependency, TaskGraph, TaskId,
+    TaskProcessor, TaskProgress, TaskQueue, TaskStatus, Workflow, WorkflowEvent, WorkflowEventKind,
+    WorkflowStatus, WorkflowTimer, CANCELLED_REASON, DEADLINE_EXCEEDED_REASON,
+    DEFAULT_EXPIRY_INTERVAL, DEFAULT_IDEMPOTENCY_RETENTION, DEPENDENCY_FAILED_REASON,
+    LEASE_EXPIRED_REASON, WORKFLOW_TASK_CONTENT_TYPE,
+};
+use super::migrations::{self, Migration, MigrationStatus};
+use anyhow::{Context, Result};
 use async_trait::async_trait;
+use std::{cmp::Reverse, collections::HashSet, time::Duration};
 
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use sqlx::{
+    postgres::{PgConnection, PgPoolOptions, PgRow},
+    Pool, Postgres,
+};
 use sqlx::{Executor, QueryBuilder, Row};
+
+/// Postgres caps a single statement at 65535 bind parameters.
+const MAX_BIND_PARAMS: usize = 65535;
+const ENQUEUE_BINDS_PER_TASK: usize = 13;
+
+const DEFAULT_LEASE_DURATION_MS: i64 = 30_000;
+
+/// Schema migrations, applied in order by [`PersistencePostgres::migrate`].
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create task tables",
+        sql: include_str!("../../migrations/postgres/0001_create_task_tables.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "create idempotency keys",
+        sql: include_str!("../../migrations/postgres/0002_create_idempotency_keys.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "add task priority",
+        sql: include_str!("../../migrations/postgres/0003_add_task_priority.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "add task result",
+        sql: include_str!("../../migrations/postgres/0004_add_task_result.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "add task progress",
+        sql: include_str!("../../migrations/postgres/0005_add_task_progress.sql"),
+    },
+    Migration {
+        version: 6,
+        description: "create schedules",
+        sql: include_str!("../../migrations/postgres/0006_create_schedules.sql"),
+    },
+    Migration {
+        version: 7,
+        description: "create task dependencies",
+        sql: include_str!("../../migrations/postgres/0007_create_task_dependencies.sql"),
+    },
+    Migration {
+        version: 8,
+        description: "create workflows",
+        sql: include_str!("../../migrations/postgres/0008_create_workflows.sql"),
+    },
+    Migration {
+        version: 9,
+        description: "create workflow timers",
+        sql: include_str!("../../migrations/postgres/0009_create_workflow_timers.sql"),
+    },
+    Migration {
+        version: 10,
+        description: "add workflow signals",
+        sql: include_str!("../../migrations/postgres/0010_add_workflow_signals.sql"),
+    },
+    Migration {
+        version: 11,
+        description: "add child workflows",
+        sql: include_str!("../../migrations/postgres/0011_add_child_workflows.sql"),
+    },
+    Migration {
+        version: 12,
+        description: "create task definitions",
+        sql: include_str!("../../migrations/postgres/0012_create_task_definitions.sql"),
+    },
+    Migration {
+        version: 13,
+        description: "add task definition to tasks",
+        sql: include_str!("../../migrations/postgres/0013_add_task_definition_to_tasks.sql"),
+    },
+    Migration {
+        version: 14,
+        description: "add missing task columns",
+        sql: include_str!("../../migrations/postgres/0014_add_missing_task_columns.sql"),
+    },
+    Migration {
+        version: 15,
+        description: "drop redundant task indexes",
+        sql: include_str!("../../migrations/postgres/0015_drop_redundant_task_indexes.sql"),
+    },
+];
+
+/// Every task connected to the one at `($2, $3)` in queue `$1`, walking dependencies both ways.
+const TASK_GRAPH_CTE: &str = r#"
+    WITH RECURSIVE graph (partition_id, seq_id) AS (
+        SELECT $2::SMALLINT, $3::BIGINT
+        UNION
+        SELECT
+            CASE WHEN dependency.partition_id = graph.partition_id AND dependency.seq_id = graph.seq_id
+                THEN dependency.parent_partition_id ELSE dependency.partition_id END,
+            CASE WHEN dependency.partition_id = graph.partition_id AND dependency.seq_id = graph.seq_id
+                THEN dependency.parent_seq_id ELSE dependency.seq_id END
+        FROM svppl_task_dependency dependency
+        JOIN graph
+        ON (dependency.partition_id = graph.partition_id AND dependency.seq_id = graph.seq_id)
+        OR (dependency.parent_partition_id = graph.partition_id AND dependency.parent_seq_id = graph.seq_id)
+        WHERE dependency.queue_id = $1
+    )
+"#;
+
+/// Advisory lock key serialising migrations across nodes, "svppl" in ASCII.
+const MIGRATION_LOCK_KEY: i64 = 0x73_76_70_70_6c;
+
+const CREATE_SCHEMA_VERSION_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS svppl_schema_version (
+        version BIGINT NOT NULL PRIMARY KEY,
+        description TEXT NOT NULL,
+        applied_at BIGINT NOT NULL
+    );
+"#;
+
 pub struct PersistencePostgres {
     pool: Pool<Postgres>,
+    lease_duration_ms: i64,
+    idempotency_retention_ms: i64,
+    priority_aging_ms: Option<i64>,
+    expiry_sweeps: ExpirySweeps,
 }
 
 impl PersistencePostgres {
     pub fn new(pool: Pool<Postgres>) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            lease_duration_ms: DEFAULT_LEASE_DURATION_MS,
+            idempotency_retention_ms: DEFAULT_IDEMPOTENCY_RETENTION.as_millis() as i64,
+            priority_aging_ms: None,
+            expiry_sweeps: ExpirySweeps::new(DEFAULT_EXPIRY_INTERVAL),
+        }
     }
 
-    pub async fn initialize_tables(&self) -> Result<()> {
-        let mut tx = self.pool.begin().await?; // Start a transaction
+    /// Sets how long tasks claimed by [`TaskQueue::process_tasks`] stay leased before
+    /// another caller may claim them again.
+    pub fn with_lease_duration(mut self, lease_duration: Duration) -> Self {
+        self.lease_duration_ms = lease_duration.as_millis() as i64;
+        self
+    }
 
-        // Create the table
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS svppl_task (
-                seq_id BIGSERIAL NOT NULL,
-                queue_id TEXT NOT NULL,
-                partition_id SMALLINT NOT NULL,
-                payload BYTEA NOT NULL,
-                status SMALLINT NOT NULL,
-                scheduled_at BIGINT NOT NULL DEFAULT 0,
-                deadline_at BIGINT,
-                PRIMARY KEY (queue_id, partition_id, seq_id)
+    /// Sets how long idempotency keys are remembered after the task using them is enqueued.
+    pub fn with_idempotency_retention(mut self, retention: Duration) -> Self {
+        self.idempotency_retention_ms = retention.as_millis() as i64;
+        self
+    }
+
+    /// Guards against starvation by lifting due tasks one priority level for every `aging`
+    /// they have waited.
+    pub fn with_priority_aging(mut self, aging: Duration) -> Self {
+        self.priority_aging_ms = Some((aging.as_millis() as i64).max(1));
+        self
+    }
+
+    /// Sets how often lease calls sweep a queue partition for tasks past their deadline,
+    /// timeout or lease. Tasks expiring in between wait for the next sweep.
+    pub fn with_expiry_interval(mut self, interval: Duration) -> Self {
+        self.expiry_sweeps = ExpirySweeps::new(interval);
+        self
+    }
+
+    /// Applies pending [`MIGRATIONS`] and returns their versions. Nodes starting together
+    /// take turns on an advisory lock, so each migration runs once.
+    pub async fn migrate(&self) -> Result<Vec<i64>> {
+        let mut tx = self.pool.begin().await?;
+
+        // Held until the transaction ends, DDL in Postgres is transactional so a failed
+        // migration leaves nothing behind.
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .execute(&mut *tx)
+            .await?;
+
+        (&mut *tx).execute(CREATE_SCHEMA_VERSION_TABLE).await?;
+
+        let applied: Vec<i64> = sqlx::query_scalar("SELECT version FROM svppl_schema_version")
+            .fetch_all(&mut *tx)
+            .await?;
+
+        let pending = migrations::pending(MIGRATIONS, &applied)?;
+
+        for migration in &pending {
+            (&mut *tx)
+                .execute(migration.sql)
+                .await
+                .with_context(|| format!("migration {} failed", migration.version))?;
+
+            sqlx::query(
+                r#"
+                INSERT INTO svppl_schema_version (version, description, applied_at)
+                VALUES ($1, $2, $3)
+                "#,
+            )
+            .bind(migration.version)
+            .bind(migration.description)
+            .bind(now_millis())
+            .execute(&mut *tx)
+            .await?;
+
+            tracing::info!(
+                version = migration.version,
+                description = migration.description,
+                "migration_applied"
             );
-            "#,
-        )
-        .execute(&mut *tx)
-        .await?;
+        }
 
-        // Create the first index
-        sqlx::query(
-            r#"
-            CREATE INDEX IF NOT EXISTS svppl_idx_task_status ON svppl_task(status);
-            "#,
-        )
-        .execute(&mut *tx)
-        .await?;
+        tx.commit().await?;
 
-        // Create the second index
-        sqlx::query(
-            r#"
-            CREATE INDEX IF NOT EXISTS svppl_idx_task_scheduled_at ON svppl_task(scheduled_at);
-            "#,
-        )
-        .execute(&mut *tx)
-        .await?;
+        Ok(pending.iter().map(|migration| migration.version).collect())
+    }
 
-        tx.commit().await?; // Commit the transaction
+    pub async fn migration_status(&self) -> Result<Vec<MigrationStatus>> {
+        let exists: bool =
+            sqlx::query_scalar("SELECT to_regclass('svppl_schema_version') IS NOT NULL")
+                .fetch_one(&self.pool)
+                .await?;
 
-        Ok(())
+        let applied: Vec<(i64, i64)> = if exists {
+            sqlx::query_as("SELECT version, applied_at FROM svppl_schema_version")
+                .fetch_all(&self.pool)
+                .await?
+        } else {
+            Vec::new()
+        };
+
+        Ok(migrations::status(MIGRATIONS, &applied))
     }
 }
 
@@ -64,42 +255,83 @@ impl TaskQueue for PersistencePostgres {
         &self,
         queue_id: &str,
         partition_id: i16,
-        payloads: Vec<&[u8]>,
+        tasks: Vec<NewTask<'_>>,
     ) -> Result<Vec<TaskId>> {
-        let mut conn = self.pool.acquire().await?;
+        check_dependencies(&tasks)?;
+        check_max_attempts(&tasks)?;
 
-        let mut query_builder =
-            QueryBuilder::new("INSERT INTO svppl_task (queue_id, partition_id, payload, status) ");
-
-        query_builder.push_values(payloads, |mut b, payload| {
-            b.push_bind(queue_id)
-                .push_bind(partition_id)
-                .push_bind(payload)
-                .push_bind(0);
-        });
+        let now = now_millis();
+        let policy = self.retry_policy(queue_id).await?;
+        let batch = IdempotentBatch::new(tasks.clone());
+        let mut tx = self.pool.begin().await?;
+        let mut task_ids = Vec::with_capacity(batch.tasks().len());
 
-        query_builder.push("RETURNING seq_id");
+        // Large batches are split so no single statement exceeds the bind parameter limit,
+        // the transaction keeps the batch all-or-nothing.
+        for chunk in batch
+            .tasks()
+            .chunks(MAX_BIND_PARAMS / ENQUEUE_BINDS_PER_TASK)
+        {
+            let mut query_builder = QueryBuilder::new(
+                "INSERT INTO svppl_task (queue_id, partition_id, payload, content_type, status, scheduled_at, deadline_at, timeout_ms, max_attempts, priority, parent_failure_policy, definition_id, definition_version) ",
+            );
 
-        let query = query_builder.build();
-        let fetched = conn.fetch_all(query).await;
+            query_builder.push_values(chunk, |mut b, task| {
+                b.push_bind(queue_id)
+                    .push_bind(partition_id)
+                    .push_bind(task.payload)
+                    .push_bind(task.content_type)
+                    .push_bind(task.initial_status(now).as_i16())
+                    .push_bind(task.due_at(now))
+                    .push_bind(task.deadline_at)
+                    .push_bind(task.timeout_ms)
+                    .push_bind(task.max_attempts.unwrap_or(policy.max_attempts))
+                    .push_bind(task.priority)
+                    .push_bind(task.on_parent_failure.as_i16())
+                    .push_bind(task.definition_id)
+                    .push_bind(task.definition_version);
+            });
 
-        match fetched {
-            Ok(rows) => {
-                let mapped_vec: anyhow::Result<Vec<TaskId>> = rows
-                    .into_iter()
-                    .map(|row| {
-                        let maybe_seq_id: sqlx::Result<i64, sqlx::Error> = row.try_get(0);
+            query_builder.push("RETURNING seq_id");
 
-                        maybe_seq_id
-                            .map_err(|err| anyhow::Error::new(err))
-                            .map(|seq_id| TaskId::from_parts(queue_id, partition_id, seq_id))
-                    })
-                    .collect();
+            let rows = query_builder.build().fetch_all(&mut *tx).await?;
 
-                mapped_vec
+            for row in rows {
+                let seq_id: i64 = row.try_get(0)?;
+                task_ids.push(TaskId::from_parts(queue_id, partition_id, seq_id));
             }
-            Err(err) => Err(anyhow::Error::new(err)),
         }
+
+        // RETURNING rows come back in no particular order.
+        task_ids.sort_by_key(|task_id| task_id.seq_id());
+
+        let written = task_ids.clone();
+
+        self.claim_idempotency_keys(&mut tx, queue_id, batch.tasks(), &mut task_ids, now)
+            .await?;
+
+        let task_ids = batch.task_ids(task_ids);
+        let dependencies = batch_dependencies(&tasks, &task_ids, &written);
+
+        insert_dependencies(&mut tx, queue_id, &dependencies).await?;
+
+        tx.commit().await?;
+
+        // Parents can be earlier tasks found by idempotency key, which may have finished.
+        let mut blocked: Vec<(i16, i64)> = dependencies
+            .iter()
+            .map(|dependency| {
+                (
+                    dependency.task_id.partition_id(),
+                    dependency.task_id.seq_id(),
+                )
+            })
+            .collect();
+        blocked.dedup();
+
+        self.release_blocked_tasks(queue_id, blocked, now).await?;
+
+        Ok(task_ids)
     }
 
     async fn process_tasks<T: TaskProcessor>(
@@ -109,44 +341,42 @@ impl TaskQueue for PersistencePostgres {
         count: i64,
         task_processor: &T,
     ) -> Result<()> {
-        let mut tx = self.pool.begin().await?;
+        // The claim is committed before any processor runs, so no row lock or connection
+        // is held while tasks execute.
+        let leased = self
+            .lease_tasks(queue_id, partition_id, count, self.lease_duration_ms)
+            .await?;
 
-        let rows = sqlx::query(
-            r#"
-            SELECT seq_id, payload, scheduled_at, deadline_at
-            FROM svppl_task
-            WHERE queue_id = $1
-            AND partition_id = $2
-            AND status = 0
-            ORDER BY seq_id ASC, scheduled_at ASC
-            LIMIT $3
-            SKIP LOCKED
-            FOR UPDATE;
-            "#,
-        )
-        .bind(queue_id)
-        .bind(partition_id)
-        .bind(count)
-        .fetch_all(&mut *tx)
-        .await?;
+        let futures = leased.into_iter().map(|leased| async move {
+            let task_id = leased.task.task_id.clone();
+            let heartbeat = LeaseHeartbeat::new(self, &leased, self.lease_duration_ms);
+            let result = run_task(task_processor, leased.task, &heartbeat).await;
 
-        let mut futures = Vec::new();
+            // A failing task only affects itself, it is retried or failed on its own.
+            let settled = match result {
+                Ok(result) => {
+                    self.complete_lease(&task_id, &leased.lease_token, result.as_deref())
+                        .await
+                }
+                Err(err) => {
+                    tracing::warn!(err = ?err, task_id = %task_id, "task_failed");
 
-        for row in rows {
-            let seq_id: i64 = row.try_get(0)?;
-            let payload: Vec<u8> = row.try_get(1)?;
-            let scheduled_at: i64 = row.try_get(2)?;
-            let deadline_at: Option<i64> = row.try_get(3)?;
+                    self.fail_lease(
+                        &task_id,
+                        &leased.lease_token,
+                        &err.to_string(),
+                        now_millis(),
+                    )
+                    .await
+                }
+            }?;
 
-            let future = task_processor.process_task(TaskData {
-                task_id: TaskId::from_parts(queue_id, partition_id, seq_id),
-                payload: payload,
-                scheduled_at,
-                deadline_at,
-            });
+            if !settled {
+                tracing::warn!(task_id = %task_id, "task_lease_lost");
+            }
 
-            futures.push(future);
-        }
+            Ok::<_, anyhow::Error>(())
+        });
 
         let results = futures::future::join_all(futures).await;
 
@@ -154,8 +384,6 @@ impl TaskQueue for PersistencePostgres {
             result?; // Handle each result or error
         }
 
-        tx.commit().await?;
-
         Ok(())
     }
 
@@ -163,49 +391,1864 @@ impl TaskQueue for PersistencePostgres {
         &self,
         queue_id: &str,
         partition_id: i16,
-        status: i16,
+        status: TaskStatus,
         count: i64,
     ) -> Result<Vec<TaskData>> {
         let rows = sqlx::query(
             r#"
-            SELECT seq_id, payload, scheduled_at, deadline_at
+            SELECT seq_id, status, payload, scheduled_at, deadline_at, timeout_ms, content_type, last_error, attempts, max_attempts, priority, result, progress_percent, progress_detail, definition_id, definition_version
             FROM svppl_task
             WHERE queue_id = $1
             AND partition_id = $2
             AND status = $3
-            ORDER BY seq_id ASC, scheduled_at ASC
+            ORDER BY scheduled_at ASC, seq_id ASC
             LIMIT $4
             "#,
         )
         .bind(queue_id)
         .bind(partition_id)
-        .bind(status)
+        .bind(status.as_i16())
         .bind(count)
         .fetch_all(&self.pool)
         .await?;
 
-        let mut tasks = Vec::new();
+        rows.iter()
+            .map(|row| task_data_from_row(queue_id, partition_id, row))
+            .collect()
+    }
 
-        for row in rows {
-            let seq_id: i64 = row.try_get(0)?;
-            let payload: Vec<u8> = row.try_get(1)?;
-            let scheduled_at: i64 = row.try_get(2)?;
-            let deadline_at: Option<i64> = row.try_get(3)?;
+    async fn lease_tasks(
+        &self,
+        queue_id: &str,
+        partition_id: i16,
+        count: i64,
+        lease_ms: i64,
+    ) -> Result<Vec<LeasedTask>> {
+        let now = now_millis();
+        let lease_token = nanoid::nanoid!();
 
-            tasks.push(TaskData {
-                task_id: TaskId::from_parts(queue_id, partition_id, seq_id),
-                payload: payload,
-                scheduled_at,
-                deadline_at,
-            });
+        if self.expiry_sweeps.claim(queue_id, partition_id, now) {
+            self.expire_tasks(queue_id, partition_id, now).await?;
         }
 
-        Ok(tasks)
+        // Every claim counts as an attempt. A task's timeout caps how long its lease can be.
+        let rows = sqlx::query(&format!(
+            r#"
+            WITH claimed AS (
+                SELECT seq_id
+                FROM svppl_task
+                WHERE queue_id = $1
+                AND partition_id = $2
+                AND status = ANY($4)
+                AND scheduled_at <= $6
+                AND (deadline_at IS NULL OR deadline_at > $6)
+                ORDER BY {} DESC, scheduled_at ASC, seq_id ASC
+                LIMIT $3
+                FOR UPDATE SKIP LOCKED
+            )
+            UPDATE svppl_task
+            SET status = $5,
+                lease_token = $7,
+                leased_at = $6,
+                lease_expires_at = $6 + LEAST($8, COALESCE(timeout_ms, $8)),
+                attempts = attempts + 1,
+                progress_percent = NULL,
+                progress_detail = NULL
+            FROM claimed
+            WHERE svppl_task.queue_id = $1
+            AND svppl_task.partition_id = $2
+            AND svppl_task.seq_id = claimed.seq_id
+            RETURNING svppl_task.seq_id, status, payload, scheduled_at, deadline_at, timeout_ms, content_type, last_error, attempts, max_attempts, priority, result, progress_percent, progress_detail, definition_id, definition_version, lease_expires_at
+            "#,
+            priority_order("$6", self.priority_aging_ms)
+        ))
+        .bind(queue_id)
+        .bind(partition_id)
+        .bind(count)
+        .bind(status_codes(&TaskStatus::Leased.sources()))
+        .bind(TaskStatus::Leased.as_i16())
+        .bind(now)
+        .bind(&lease_token)
+        .bind(lease_ms)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut leased = rows
+            .iter()
+            .map(|row| {
+                Ok(LeasedTask {
+                    task: task_data_from_row(queue_id, partition_id, row)?,
+                    lease_token: lease_token.clone(),
+                    lease_expires_at: row.try_get(16)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // UPDATE ... RETURNING gives no ordering guarantee.
+        leased.sort_by_key(|leased| {
+            let task = &leased.task;
+            let priority = effective_priority(
+                task.priority,
+                task.scheduled_at,
+                now,
+                self.priority_aging_ms,
+            );
+
+            (Reverse(priority), task.scheduled_at, task.task_id.seq_id())
+        });
+
+        Ok(leased)
     }
-}
 
-pub async fn create_connection_pool(url: &str) -> Result<sqlx::PgPool> {
-    let pool = PgPoolOptions::new().max_connections(5).connect(url).await?;
+    async fn get_task(&self, task_id: &TaskId) -> Result<Option<TaskData>> {
+        let row = sqlx::query(
+            r#"
+            SELECT seq_id, status, payload, scheduled_at, deadline_at, timeout_ms, content_type, last_error, attempts, max_attempts, priority, result, progress_percent, progress_detail, definition_id, definition_version
+            FROM svppl_task
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND seq_id = $3
+            "#,
+        )
+        .bind(task_id.queue_id())
+        .bind(task_id.partition_id())
+        .bind(task_id.seq_id())
+        .fetch_optional(&self.pool)
+        .await?;
 
-    Ok(pool)
+        row.map(|row| task_data_from_row(task_id.queue_id(), task_id.partition_id(), &row))
+            .transpose()
+    }
+
+    async fn ack_task(
+        &self,
+        task_id: &TaskId,
+        lease_token: &str,
+        result: Option<&[u8]>,
+    ) -> Result<bool> {
+        self.complete_lease(task_id, lease_token, result).await
+    }
+
+    async fn heartbeat_task(
+        &self,
+        task_id: &TaskId,
+        lease_token: &str,
+        lease_ms: i64,
+        progress: Option<&TaskProgress>,
+    ) -> Result<Option<i64>> {
+        let now = now_millis();
+
+        // Like a fresh lease, a renewal cannot run past the task's timeout.
+        let lease_expires_at = sqlx::query_scalar(
+            r#"
+            UPDATE svppl_task
+            SET lease_expires_at = LEAST($6 + $7, COALESCE(leased_at + timeout_ms, $6 + $7)),
+                progress_percent = CASE WHEN $8 THEN $9 ELSE progress_percent END,
+                progress_detail = CASE WHEN $8 THEN $10 ELSE progress_detail END
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND seq_id = $3
+            AND status = $4
+            AND lease_token = $5
+            AND lease_expires_at > $6
+            RETURNING lease_expires_at
+            "#,
+        )
+        .bind(task_id.queue_id())
+        .bind(task_id.partition_id())
+        .bind(task_id.seq_id())
+        .bind(TaskStatus::Leased.as_i16())
+        .bind(lease_token)
+        .bind(now)
+        .bind(lease_ms)
+        .bind(progress.is_some())
+        .bind(progress.map(|progress| progress.percent))
+        .bind(progress.and_then(|progress| progress.detail.as_deref()))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(lease_expires_at)
+    }
+
+    async fn nack_task(&self, task_id: &TaskId, lease_token: &str, error: &str) -> Result<bool> {
+        self.fail_lease(task_id, lease_token, error, now_millis())
+            .await
+    }
+
+    async fn release_task(&self, task_id: &TaskId, lease_token: &str) -> Result<bool> {
+        TaskStatus::Leased.check_transition(TaskStatus::Pending)?;
+
+        let result = sqlx::query(
+            r#"
+            UPDATE svppl_task
+            SET status = $4,
+                lease_token = NULL,
+                leased_at = NULL,
+                lease_expires_at = NULL,
+                attempts = attempts - 1
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND seq_id = $3
+            AND status = $5
+            AND lease_token = $6
+            "#,
+        )
+        .bind(task_id.queue_id())
+        .bind(task_id.partition_id())
+        .bind(task_id.seq_id())
+        .bind(TaskStatus::Pending.as_i16())
+        .bind(TaskStatus::Leased.as_i16())
+        .bind(lease_token)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn lease_state(&self, task_id: &TaskId, lease_token: &str) -> Result<LeaseState> {
+        let status: Option<i16> = sqlx::query_scalar(
+            r#"
+            SELECT status
+            FROM svppl_task
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND seq_id = $3
+            AND lease_token = $4
+            "#,
+        )
+        .bind(task_id.queue_id())
+        .bind(task_id.partition_id())
+        .bind(task_id.seq_id())
+        .bind(lease_token)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let state = match status.map(TaskStatus::try_from).transpose()? {
+            Some(TaskStatus::Leased) => LeaseState::Held,
+            Some(TaskStatus::Cancelled) => LeaseState::Cancelled,
+            _ => LeaseState::Lost,
+        };
+
+        Ok(state)
+    }
+
+    async fn cancel_task(&self, task_id: &TaskId) -> Result<bool> {
+        // The lease token is kept so the worker holding it can tell it was cancelled.
+        let result = sqlx::query(
+            r#"
+            UPDATE svppl_task
+            SET status = $4,
+                leased_at = NULL,
+                lease_expires_at = NULL,
+                last_error = $5
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND seq_id = $3
+            AND status = ANY($6)
+            "#,
+        )
+        .bind(task_id.queue_id())
+        .bind(task_id.partition_id())
+        .bind(task_id.seq_id())
+        .bind(TaskStatus::Cancelled.as_i16())
+        .bind(CANCELLED_REASON)
+        .bind(status_codes(&TaskStatus::Cancelled.sources()))
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        self.release_dependents(task_id, now_millis()).await?;
+
+        Ok(true)
+    }
+
+    async fn reschedule_task(&self, task_id: &TaskId, scheduled_at: i64) -> Result<bool> {
+        let now = now_millis();
+        let due_at = if scheduled_at > 0 { scheduled_at } else { now };
+
+        let result = sqlx::query(
+            r#"
+            UPDATE svppl_task
+            SET status = $4,
+                scheduled_at = $5
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND seq_id = $3
+            AND status = ANY($6)
+            "#,
+        )
+        .bind(task_id.queue_id())
+        .bind(task_id.partition_id())
+        .bind(task_id.seq_id())
+        .bind(waiting_status(due_at, now).as_i16())
+        .bind(due_at)
+        .bind(status_codes(&[TaskStatus::Pending, TaskStatus::Scheduled]))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn task_graph(&self, task_id: &TaskId) -> Result<Option<TaskGraph>> {
+        let queue_id = task_id.queue_id();
+
+        let rows = sqlx::query(&format!(
+            r#"
+            {}
+            SELECT seq_id, status, payload, scheduled_at, deadline_at, timeout_ms, content_type, last_error, attempts, max_attempts, priority, result, progress_percent, progress_detail, definition_id, definition_version, partition_id
+            FROM svppl_task
+            WHERE queue_id = $1
+            AND (partition_id, seq_id) IN (SELECT partition_id, seq_id FROM graph)
+            ORDER BY seq_id ASC
+            "#,
+            TASK_GRAPH_CTE
+        ))
+        .bind(queue_id)
+        .bind(task_id.partition_id())
+        .bind(task_id.seq_id())
+        .fetch_all(&self.pool)
+        .await?;
+
+        let tasks = rows
+            .iter()
+            .map(|row| task_data_from_row(queue_id, row.try_get(16)?, row))
+            .collect::<Result<Vec<_>>>()?;
+
+        if !tasks.iter().any(|task| task.task_id == *task_id) {
+            return Ok(None);
+        }
+
+        let edges: Vec<(i16, i64, i16, i64)> = sqlx::query_as(&format!(
+            r#"
+            {}
+            SELECT partition_id, seq_id, parent_partition_id, parent_seq_id
+            FROM svppl_task_dependency
+            WHERE queue_id = $1
+            AND (partition_id, seq_id) IN (SELECT partition_id, seq_id FROM graph)
+            ORDER BY seq_id ASC, parent_seq_id ASC
+            "#,
+            TASK_GRAPH_CTE
+        ))
+        .bind(queue_id)
+        .bind(task_id.partition_id())
+        .bind(task_id.seq_id())
+        .fetch_all(&self.pool)
+        .await?;
+
+        // Purged tasks drop out of the graph along with their edges.
+        let present: HashSet<&TaskId> = tasks.iter().map(|task| &task.task_id).collect();
+
+        let dependencies = edges
+            .into_iter()
+            .map(
+                |(partition_id, seq_id, parent_partition_id, parent_seq_id)| TaskDependency {
+                    task_id: TaskId::from_parts(queue_id, partition_id, seq_id),
+                    parent_id: TaskId::from_parts(queue_id, parent_partition_id, parent_seq_id),
+                },
+            )
+            .filter(|dependency| {
+                present.contains(&dependency.task_id) && present.contains(&dependency.parent_id)
+            })
+            .collect();
+
+        Ok(Some(TaskGraph {
+            tasks,
+            dependencies,
+        }))
+    }
+
+    async fn set_retry_policy(&self, queue_id: &str, policy: &RetryPolicy) -> Result<()> {
+        policy.validate()?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO svppl_retry_policy (queue_id, max_attempts, initial_backoff_ms, max_backoff_ms, backoff_multiplier, jitter)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (queue_id) DO UPDATE
+            SET max_attempts = EXCLUDED.max_attempts,
+                initial_backoff_ms = EXCLUDED.initial_backoff_ms,
+                max_backoff_ms = EXCLUDED.max_backoff_ms,
+                backoff_multiplier = EXCLUDED.backoff_multiplier,
+                jitter = EXCLUDED.jitter
+            "#,
+        )
+        .bind(queue_id)
+        .bind(policy.max_attempts)
+        .bind(policy.initial_backoff_ms)
+        .bind(policy.max_backoff_ms)
+        .bind(policy.backoff_multiplier)
+        .bind(policy.jitter)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn retry_policy(&self, queue_id: &str) -> Result<RetryPolicy> {
+        let row = sqlx::query(
+            r#"
+            SELECT max_attempts, initial_backoff_ms, max_backoff_ms, backoff_multiplier, jitter
+            FROM svppl_retry_policy
+            WHERE queue_id = $1
+            "#,
+        )
+        .bind(queue_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match row {
+            Some(row) => Ok(RetryPolicy {
+                max_attempts: row.try_get(0)?,
+                initial_backoff_ms: row.try_get(1)?,
+                max_backoff_ms: row.try_get(2)?,
+                backoff_multiplier: row.try_get(3)?,
+                jitter: row.try_get(4)?,
+            }),
+            None => Ok(RetryPolicy::default()),
+        }
+    }
+
+    async fn redrive_tasks(&self, task_ids: &[TaskId]) -> Result<u64> {
+        TaskStatus::DeadLettered.check_transition(TaskStatus::Pending)?;
+
+        let queue_ids: Vec<&str> = task_ids.iter().map(|task_id| task_id.queue_id()).collect();
+        let partition_ids: Vec<i16> = task_ids
+            .iter()
+            .map(|task_id| task_id.partition_id())
+            .collect();
+        let seq_ids: Vec<i64> = task_ids.iter().map(|task_id| task_id.seq_id()).collect();
+
+        let result = sqlx::query(
+            r#"
+            UPDATE svppl_task
+            SET status = $4,
+                scheduled_at = $6,
+                attempts = 0
+            WHERE (queue_id, partition_id, seq_id) IN (
+                SELECT * FROM UNNEST($1::TEXT[], $2::SMALLINT[], $3::BIGINT[])
+            )
+            AND status = $5
+            "#,
+        )
+        .bind(queue_ids)
+        .bind(partition_ids)
+        .bind(seq_ids)
+        .bind(TaskStatus::Pending.as_i16())
+        .bind(TaskStatus::DeadLettered.as_i16())
+        .bind(now_millis())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn redrive_all_tasks(&self, queue_id: &str, partition_id: i16) -> Result<u64> {
+        TaskStatus::DeadLettered.check_transition(TaskStatus::Pending)?;
+
+        let result = sqlx::query(
+            r#"
+            UPDATE svppl_task
+            SET status = $3,
+                scheduled_at = $5,
+                attempts = 0
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND status = $4
+            "#,
+        )
+        .bind(queue_id)
+        .bind(partition_id)
+        .bind(TaskStatus::Pending.as_i16())
+        .bind(TaskStatus::DeadLettered.as_i16())
+        .bind(now_millis())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn purge_dead_lettered(&self, queue_id: &str, partition_id: i16) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let purged: Vec<i64> = sqlx::query_scalar(
+            r#"
+            DELETE FROM svppl_task
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND status = $3
+            RETURNING seq_id
+            "#,
+        )
+        .bind(queue_id)
+        .bind(partition_id)
+        .bind(TaskStatus::DeadLettered.as_i16())
+        .fetch_all(&mut *tx)
+        .await?;
+
+        // Keys pointing at a purged task would keep resolving to it.
+        sqlx::query(
+            r#"
+            DELETE FROM svppl_idempotency_key
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND seq_id = ANY($3)
+            "#,
+        )
+        .bind(queue_id)
+        .bind(partition_id)
+        .bind(&purged)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM svppl_task_dependency
+            WHERE queue_id = $1
+            AND (
+                (partition_id = $2 AND seq_id = ANY($3))
+                OR (parent_partition_id = $2 AND parent_seq_id = ANY($3))
+            )
+            "#,
+        )
+        .bind(queue_id)
+        .bind(partition_id)
+        .bind(&purged)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(purged.len() as u64)
+    }
+
+    async fn put_schedule(&self, schedule: &Schedule) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO svppl_schedule (schedule_id, cron, timezone, queue_id, partition_id, payload, content_type, misfire_policy, next_fire_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (schedule_id) DO UPDATE
+            SET cron = EXCLUDED.cron,
+                timezone = EXCLUDED.timezone,
+                queue_id = EXCLUDED.queue_id,
+                partition_id = EXCLUDED.partition_id,
+                payload = EXCLUDED.payload,
+                content_type = EXCLUDED.content_type,
+                misfire_policy = EXCLUDED.misfire_policy,
+                next_fire_at = EXCLUDED.next_fire_at
+            "#,
+        )
+        .bind(&schedule.schedule_id)
+        .bind(&schedule.cron)
+        .bind(&schedule.timezone)
+        .bind(&schedule.queue_id)
+        .bind(schedule.partition_id)
+        .bind(&schedule.payload)
+        .bind(&schedule.content_type)
+        .bind(schedule.misfire_policy.as_i16())
+        .bind(schedule.next_fire_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_schedule(&self, schedule_id: &str) -> Result<Option<Schedule>> {
+        let row = sqlx::query(
+            r#"
+            SELECT schedule_id, cron, timezone, queue_id, partition_id, payload, content_type, misfire_policy, next_fire_at
+            FROM svppl_schedule
+            WHERE schedule_id = $1
+            "#,
+        )
+        .bind(schedule_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(schedule_from_row).transpose()
+    }
+
+    async fn list_schedules(&self) -> Result<Vec<Schedule>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT schedule_id, cron, timezone, queue_id, partition_id, payload, content_type, misfire_policy, next_fire_at
+            FROM svppl_schedule
+            ORDER BY schedule_id ASC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(schedule_from_row).collect()
+    }
+
+    async fn delete_schedule(&self, schedule_id: &str) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM svppl_schedule
+            WHERE schedule_id = $1
+            "#,
+        )
+        .bind(schedule_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn due_schedules(
+        &self,
+        now: i64,
+        after: Option<&Schedule>,
+        count: i64,
+    ) -> Result<Vec<Schedule>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT schedule_id, cron, timezone, queue_id, partition_id, payload, content_type, misfire_policy, next_fire_at
+            FROM svppl_schedule
+            WHERE next_fire_at <= $1
+              AND ($2 IS NULL OR (next_fire_at, schedule_id) > ($2, $3))
+            ORDER BY next_fire_at ASC, schedule_id ASC
+            LIMIT $4
+            "#,
+        )
+        .bind(now)
+        .bind(after.map(|schedule| schedule.next_fire_at))
+        .bind(after.map(|schedule| schedule.schedule_id.as_str()))
+        .bind(count)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(schedule_from_row).collect()
+    }
+
+    async fn advance_schedule(&self, schedule_id: &str, from: i64, to: i64) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE svppl_schedule
+            SET next_fire_at = $3
+            WHERE schedule_id = $1
+            AND next_fire_at = $2
+            "#,
+        )
+        .bind(schedule_id)
+        .bind(from)
+        .bind(to)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn start_workflow(&self, workflow: &NewWorkflow<'_>) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let started = insert_workflow(&mut tx, workflow, None, now_millis()).await?;
+
+        tx.commit().await?;
+
+        Ok(started)
+    }
+
+    async fn get_workflow(&self, workflow_id: &str) -> Result<Option<Workflow>> {
+        let row = sqlx::query(
+            r#"
+            SELECT workflow_id, workflow_type, queue_id, partition_id, status, result, error, last_event_id, created_at, awaiting_signal, parent_workflow_id
+            FROM svppl_workflow
+            WHERE workflow_id = $1
+            "#,
+        )
+        .bind(workflow_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(workflow_from_row).transpose()
+    }
+
+    async fn workflow_history(&self, workflow_id: &str) -> Result<Vec<WorkflowEvent>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT event_id, kind, name, payload, error, fire_at, recorded_at
+            FROM svppl_workflow_event
+            WHERE workflow_id = $1
+            ORDER BY event_id ASC
+            "#,
+        )
+        .bind(workflow_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(workflow_event_from_row).collect()
+    }
+
+    async fn append_workflow_events(
+        &self,
+        workflow_id: &str,
+        last_event_id: i64,
+        events: &[NewWorkflowEvent],
+    ) -> Result<Option<i64>> {
+        check_workflow_events(events)?;
+
+        let now = now_millis();
+        let (status, result, error) = workflow_outcome(events);
+        let next_event_id = last_event_id + events.len() as i64;
+        let mut tx = self.pool.begin().await?;
+
+        // The compare-and-set on `last_event_id` lets one replay win when several race.
+        let row = sqlx::query(
+            r#"
+            UPDATE svppl_workflow
+            SET last_event_id = $3,
+                status = $4,
+                result = $5,
+                error = $6,
+                awaiting_signal = $8
+            WHERE workflow_id = $1
+            AND last_event_id = $2
+            AND status = $7
+            RETURNING queue_id, partition_id, parent_workflow_id
+            "#,
+        )
+        .bind(workflow_id)
+        .bind(last_event_id)
+        .bind(next_event_id)
+        .bind(status.as_i16())
+        .bind(result)
+        .bind(error)
+        .bind(WorkflowStatus::Running.as_i16())
+        .bind(awaited_signal(events))
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let queue_id: String = row.try_get(0)?;
+        let partition_id: i16 = row.try_get(1)?;
+        let parent_workflow_id: Option<String> = row.try_get(2)?;
+
+        let mut query_builder = QueryBuilder::new(
+            "INSERT INTO svppl_workflow_event (workflow_id, event_id, kind, name, payload, error, fire_at, recorded_at) ",
+        );
+
+        query_builder.push_values(
+            events.iter().zip(last_event_id + 1..),
+            |mut b, (event, event_id)| {
+                b.push_bind(workflow_id)
+                    .push_bind(event_id)
+                    .push_bind(event.kind.as_i16())
+                    .push_bind(&event.name)
+                    .push_bind(&event.payload)
+                    .push_bind(&event.error)
+                    .push_bind(event.fire_at)
+                    .push_bind(now);
+            },
+        );
+
+        query_builder.build().execute(&mut *tx).await?;
+
+        insert_workflow_timers(
+            &mut tx,
+            &workflow_timers(workflow_id, last_event_id, events),
+        )
+        .await?;
+
+        for (child_id, event) in child_workflows(workflow_id, last_event_id, events) {
+            let child = NewWorkflow {
+                workflow_id: &child_id,
+                workflow_type: event.name.as_deref().unwrap_or_default(),
+                queue_id: &queue_id,
+                partition_id,
+                input: event.payload.as_deref().unwrap_or_default(),
+            };
+
+            insert_workflow(&mut tx, &child, Some(workflow_id), now).await?;
+        }
+
+        if replays_workflow(events) {
+            insert_workflow_task(&mut tx, workflow_id, &queue_id, partition_id, now).await?;
+        }
+
+        if let Some(parent_workflow_id) = parent_workflow_id {
+            if let Some(finished) = child_workflow_finished(workflow_id, events) {
+                record_workflow_event(&mut tx, &parent_workflow_id, &finished, now).await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(Some(next_event_id))
+    }
+
+    async fn due_workflow_timers(
+        &self,
+        now: i64,
+        after: Option<&WorkflowTimer>,
+        count: i64,
+    ) -> Result<Vec<WorkflowTimer>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT workflow_id, timer_id, name, fire_at
+            FROM svppl_workflow_timer
+            WHERE fire_at <= $1
+              AND ($2 IS NULL OR (fire_at, workflow_id, timer_id) > ($2, $3, $4))
+            ORDER BY fire_at ASC, workflow_id ASC, timer_id ASC
+            LIMIT $5
+            "#,
+        )
+        .bind(now)
+        .bind(after.map(|timer| timer.fire_at))
+        .bind(after.map(|timer| timer.workflow_id.as_str()))
+        .bind(after.map(|timer| timer.timer_id))
+        .bind(count)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(WorkflowTimer {
+                    workflow_id: row.try_get(0)?,
+                    timer_id: row.try_get(1)?,
+                    name: row.try_get(2)?,
+                    fire_at: row.try_get(3)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn fire_workflow_timer(&self, timer: &WorkflowTimer) -> Result<bool> {
+        let now = now_millis();
+        let mut tx = self.pool.begin().await?;
+
+        // Deleting the timer is what claims the firing.
+        let removed = sqlx::query(
+            r#"
+            DELETE FROM svppl_workflow_timer
+            WHERE workflow_id = $1
+            AND timer_id = $2
+            "#,
+        )
+        .bind(&timer.workflow_id)
+        .bind(timer.timer_id)
+        .execute(&mut *tx)
+        .await?;
+
+        if removed.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        let fired = NewWorkflowEvent {
+            kind: WorkflowEventKind::TimerFired,
+            name: Some(timer.name.clone()),
+            payload: None,
+            error: None,
+            fire_at: Some(timer.fire_at),
+        };
+
+        record_workflow_event(&mut tx, &timer.workflow_id, &fired, now).await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    async fn signal_workflow(
+        &self,
+        workflow_id: &str,
+        name: &str,
+        payload: &[u8],
+    ) -> Result<Option<i64>> {
+        let now = now_millis();
+        let mut tx = self.pool.begin().await?;
+
+        // Clears the signal the workflow was blocked on if this is it. A workflow blocked on
+        // nothing may have a replay under way, which loses the race for the history to the
+        // signal and has to be followed by another, so either way it is woken.
+        let row = sqlx::query(
+            r#"
+            UPDATE svppl_workflow
+            SET last_event_id = last_event_id + 1,
+                awaiting_signal = CASE WHEN awaiting_signal = $2 THEN NULL ELSE awaiting_signal END
+            WHERE workflow_id = $1
+            AND status = $3
+            RETURNING last_event_id, queue_id, partition_id, awaiting_signal IS NULL
+            "#,
+        )
+        .bind(workflow_id)
+        .bind(name)
+        .bind(WorkflowStatus::Running.as_i16())
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let event_id: i64 = row.try_get(0)?;
+        let queue_id: String = row.try_get(1)?;
+        let partition_id: i16 = row.try_get(2)?;
+        let wakes: bool = row.try_get(3)?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO svppl_workflow_event (workflow_id, event_id, kind, name, payload, recorded_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(workflow_id)
+        .bind(event_id)
+        .bind(WorkflowEventKind::SignalReceived.as_i16())
+        .bind(name)
+        .bind(payload)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+        if wakes {
+            insert_workflow_task(&mut tx, workflow_id, &queue_id, partition_id, now).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(Some(event_id))
+    }
+
+    async fn register_task_definition(
+        &self,
+        definition: &NewTaskDefinition<'_>,
+    ) -> Result<TaskDefinition> {
+        let latest = self
+            .get_task_definition(definition.definition_id, None)
+            .await?;
+
+        if let Some(latest) = latest.filter(|latest| latest.matches(definition)) {
+            return Ok(latest);
+        }
+
+        let now = now_millis();
+
+        let row = sqlx::query(
+            r#"
+            INSERT INTO svppl_task_definition (definition_id, version, name, description, parameters, created_at)
+            SELECT $1, COALESCE(MAX(version), 0) + 1, $2, $3, $4, $5
+            FROM svppl_task_definition
+            WHERE definition_id = $1
+            ON CONFLICT (definition_id, version) DO NOTHING
+            RETURNING version
+            "#,
+        )
+        .bind(definition.definition_id)
+        .bind(definition.name)
+        .bind(definition.description)
+        .bind(definition.parameters)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if let Some(row) = row {
+            return Ok(TaskDefinition {
+                definition_id: definition.definition_id.to_string(),
+                version: row.try_get(0)?,
+                name: definition.name.to_string(),
+                description: definition.description.to_string(),
+                parameters: definition.parameters.to_string(),
+                created_at: now,
+            });
+        }
+
+        // Lost the version to a concurrent registration, which is fine if it was the same.
+        self.get_task_definition(definition.definition_id, None)
+            .await?
+            .filter(|latest| latest.matches(definition))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "task definition was changed concurrently: {}",
+                    definition.definition_id
+                )
+            })
+    }
+
+    async fn get_task_definition(
+        &self,
+        definition_id: &str,
+        version: Option<i32>,
+    ) -> Result<Option<TaskDefinition>> {
+        let row = sqlx::query(
+            r#"
+            SELECT definition_id, version, name, description, parameters, created_at
+            FROM svppl_task_definition
+            WHERE definition_id = $1
+            AND ($2::INTEGER IS NULL OR version = $2)
+            ORDER BY version DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(definition_id)
+        .bind(version)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(task_definition_from_row).transpose()
+    }
+
+    async fn list_task_definitions(&self) -> Result<Vec<TaskDefinition>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT definition_id, version, name, description, parameters, created_at
+            FROM svppl_task_definition AS definition
+            WHERE version = (
+                SELECT MAX(version)
+                FROM svppl_task_definition
+                WHERE definition_id = definition.definition_id
+            )
+            ORDER BY definition_id ASC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(task_definition_from_row).collect()
+    }
+}
+
+impl PersistencePostgres {
+    /// Records the idempotency keys of freshly written tasks. A key still held by an earlier
+    /// task keeps pointing at it, the fresh row is deleted and its id replaced by the earlier one.
+    async fn claim_idempotency_keys(
+        &self,
+        conn: &mut PgConnection,
+        queue_id: &str,
+        tasks: &[NewTask<'_>],
+        task_ids: &mut [TaskId],
+        now: i64,
+    ) -> Result<()> {
+        let keyed: Vec<(usize, &str)> = tasks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, task)| task.idempotency_key.map(|key| (index, key)))
+            .collect();
+
+        if keyed.is_empty() {
+            return Ok(());
+        }
+
+        sqlx::query("DELETE FROM svppl_idempotency_key WHERE queue_id = $1 AND expires_at <= $2")
+            .bind(queue_id)
+            .bind(now)
+            .execute(&mut *conn)
+            .await?;
+
+        let keys: Vec<&str> = keyed.iter().map(|(_, key)| *key).collect();
+        let partition_ids: Vec<i16> = keyed
+            .iter()
+            .map(|(index, _)| task_ids[*index].partition_id())
+            .collect();
+        let seq_ids: Vec<i64> = keyed
+            .iter()
+            .map(|(index, _)| task_ids[*index].seq_id())
+            .collect();
+
+        // Conflicting inserts wait for the transaction holding the key, so concurrent
+        // enqueues with the same key settle on one task.
+        let claimed: Vec<String> = sqlx::query_scalar(
+            r#"
+            INSERT INTO svppl_idempotency_key (queue_id, idempotency_key, partition_id, seq_id, expires_at)
+            SELECT $1, key, partition_id, seq_id, $5
+            FROM UNNEST($2::TEXT[], $3::SMALLINT[], $4::BIGINT[]) AS k(key, partition_id, seq_id)
+            ON CONFLICT (queue_id, idempotency_key) DO NOTHING
+            RETURNING idempotency_key
+            "#,
+        )
+        .bind(queue_id)
+        .bind(&keys)
+        .bind(&partition_ids)
+        .bind(&seq_ids)
+        .bind(now + self.idempotency_retention_ms)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let lost: Vec<(usize, &str)> = keyed
+            .into_iter()
+            .filter(|(_, key)| !claimed.iter().any(|claimed| claimed == key))
+            .collect();
+
+        if lost.is_empty() {
+            return Ok(());
+        }
+
+        let lost_keys: Vec<&str> = lost.iter().map(|(_, key)| *key).collect();
+
+        let existing: Vec<(String, i16, i64)> = sqlx::query_as(
+            r#"
+            SELECT idempotency_key, partition_id, seq_id
+            FROM svppl_idempotency_key
+            WHERE queue_id = $1 AND idempotency_key = ANY($2)
+            "#,
+        )
+        .bind(queue_id)
+        .bind(&lost_keys)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        for (index, key) in lost {
+            let (_, partition_id, seq_id) = existing
+                .iter()
+                .find(|(existing_key, _, _)| existing_key == key)
+                .with_context(|| format!("idempotency key vanished: {}", key))?;
+
+            let duplicate = std::mem::replace(
+                &mut task_ids[index],
+                TaskId::from_parts(queue_id, *partition_id, *seq_id),
+            );
+
+            sqlx::query(
+                "DELETE FROM svppl_task WHERE queue_id = $1 AND partition_id = $2 AND seq_id = $3",
+            )
+            .bind(queue_id)
+            .bind(duplicate.partition_id())
+            .bind(duplicate.seq_id())
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves a leased task to `status` and clears its lease, provided `lease_token` still
+    /// identifies the current lease. `reason` replaces the task's last error when given.
+    async fn settle_lease(
+        &self,
+        task_id: &TaskId,
+        lease_token: &str,
+        status: TaskStatus,
+        reason: Option<&str>,
+    ) -> Result<bool> {
+        self.settle_lease_at(task_id, lease_token, status, reason, None)
+            .await
+    }
+
+    /// Marks a leased task succeeded and saves its result, provided `lease_token` still
+    /// identifies the current lease.
+    async fn complete_lease(
+        &self,
+        task_id: &TaskId,
+        lease_token: &str,
+        result: Option<&[u8]>,
+    ) -> Result<bool> {
+        TaskStatus::Leased.check_transition(TaskStatus::Succeeded)?;
+
+        let updated = sqlx::query(
+            r#"
+            UPDATE svppl_task
+            SET status = $4,
+                lease_token = NULL,
+                leased_at = NULL,
+                lease_expires_at = NULL,
+                result = $7
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND seq_id = $3
+            AND status = $5
+            AND lease_token = $6
+            "#,
+        )
+        .bind(task_id.queue_id())
+        .bind(task_id.partition_id())
+        .bind(task_id.seq_id())
+        .bind(TaskStatus::Succeeded.as_i16())
+        .bind(TaskStatus::Leased.as_i16())
+        .bind(lease_token)
+        .bind(result)
+        .execute(&self.pool)
+        .await?;
+
+        if updated.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        self.release_dependents(task_id, now_millis()).await?;
+
+        Ok(true)
+    }
+
+    /// [`Self::settle_lease`], also moving the task's `scheduled_at` when given.
+    async fn settle_lease_at(
+        &self,
+        task_id: &TaskId,
+        lease_token: &str,
+        status: TaskStatus,
+        reason: Option<&str>,
+        scheduled_at: Option<i64>,
+    ) -> Result<bool> {
+        TaskStatus::Leased.check_transition(status)?;
+
+        let result = sqlx::query(
+            r#"
+            UPDATE svppl_task
+            SET status = $4,
+                lease_token = NULL,
+                leased_at = NULL,
+                lease_expires_at = NULL,
+                last_error = COALESCE($7, last_error),
+                scheduled_at = COALESCE($8, scheduled_at)
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND seq_id = $3
+            AND status = $5
+            AND lease_token = $6
+            "#,
+        )
+        .bind(task_id.queue_id())
+        .bind(task_id.partition_id())
+        .bind(task_id.seq_id())
+        .bind(status.as_i16())
+        .bind(TaskStatus::Leased.as_i16())
+        .bind(lease_token)
+        .bind(reason)
+        .bind(scheduled_at)
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        if status.fails_dependents() {
+            self.release_dependents(task_id, now_millis()).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Records a failed attempt at a leased task. The task is scheduled again after the
+    /// queue's backoff, or dead-lettered once it has used up its attempts.
+    async fn fail_lease(
+        &self,
+        task_id: &TaskId,
+        lease_token: &str,
+        reason: &str,
+        now: i64,
+    ) -> Result<bool> {
+        // The lease token pins the row, attempts only change when the task is leased again.
+        let row = sqlx::query(
+            r#"
+            SELECT attempts, max_attempts
+            FROM svppl_task
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND seq_id = $3
+            AND status = $4
+            AND lease_token = $5
+            "#,
+        )
+        .bind(task_id.queue_id())
+        .bind(task_id.partition_id())
+        .bind(task_id.seq_id())
+        .bind(TaskStatus::Leased.as_i16())
+        .bind(lease_token)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(false);
+        };
+
+        let attempts: i32 = row.try_get(0)?;
+        let max_attempts: i32 = row.try_get(1)?;
+
+        if attempts >= max_attempts {
+            return self
+                .settle_lease(task_id, lease_token, TaskStatus::DeadLettered, Some(reason))
+                .await;
+        }
+
+        let policy = self.retry_policy(task_id.queue_id()).await?;
+        let retry_at = now + policy.backoff_ms(attempts, rand::random());
+
+        self.settle_lease_at(
+            task_id,
+            lease_token,
+            TaskStatus::Scheduled,
+            Some(reason),
+            Some(retry_at),
+        )
+        .await
+    }
+
+    /// Fails tasks whose deadline has passed so they are never leased, retries tasks that
+    /// have been running for longer than their timeout, and returns lapsed leases from
+    /// crashed or stalled workers to the queue.
+    async fn expire_tasks(&self, queue_id: &str, partition_id: i16, now: i64) -> Result<()> {
+        // Running tasks keep their lease until it lapses, only then is the deadline applied.
+        let mut failed: Vec<i64> = sqlx::query_scalar(
+            r#"
+            UPDATE svppl_task
+            SET status = $4,
+                lease_token = NULL,
+                leased_at = NULL,
+                lease_expires_at = NULL,
+                last_error = $7
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND deadline_at <= $3
+            AND (status = ANY($5) OR (status = $6 AND lease_expires_at <= $3))
+            RETURNING seq_id
+            "#,
+        )
+        .bind(queue_id)
+        .bind(partition_id)
+        .bind(now)
+        .bind(TaskStatus::Failed.as_i16())
+        .bind(status_codes(&[
+            TaskStatus::Pending,
+            TaskStatus::Scheduled,
+            TaskStatus::Blocked,
+        ]))
+        .bind(TaskStatus::Leased.as_i16())
+        .bind(DEADLINE_EXCEEDED_REASON)
+        .fetch_all(&self.pool)
+        .await?;
+
+        // Timeouts are failed attempts and back off like any other failure.
+        let timed_out = sqlx::query(
+            r#"
+            SELECT seq_id, lease_token, timeout_ms
+            FROM svppl_task
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND status = $4
+            AND leased_at + timeout_ms <= $3
+            "#,
+        )
+        .bind(queue_id)
+        .bind(partition_id)
+        .bind(now)
+        .bind(TaskStatus::Leased.as_i16())
+        .fetch_all(&self.pool)
+        .await?;
+
+        for row in timed_out {
+            let task_id = TaskId::from_parts(queue_id, partition_id, row.try_get(0)?);
+            let lease_token: String = row.try_get(1)?;
+            let timeout_ms: i64 = row.try_get(2)?;
+
+            self.fail_lease(&task_id, &lease_token, &timed_out_reason(timeout_ms), now)
+                .await?;
+        }
+
+        // A lapsed lease is put straight back, the attempt it used still counts.
+        let lapsed: Vec<(i64, i16)> = sqlx::query_as(
+            r#"
+            UPDATE svppl_task
+            SET status = CASE WHEN attempts >= max_attempts THEN $5 ELSE $4 END,
+                lease_token = NULL,
+                leased_at = NULL,
+                lease_expires_at = NULL,
+                last_error = $7
+            WHERE queue_id = $1
+            AND partition_id = $2
+            AND status = $6
+            AND lease_expires_at <= $3
+            RETURNING seq_id, status
+            "#,
+        )
+        .bind(queue_id)
+        .bind(partition_id)
+        .bind(now)
+        .bind(TaskStatus::Pending.as_i16())
+        .bind(TaskStatus::DeadLettered.as_i16())
+        .bind(TaskStatus::Leased.as_i16())
+        .bind(LEASE_EXPIRED_REASON)
+        .fetch_all(&self.pool)
+        .await?;
+
+        failed.extend(
+            lapsed
+                .into_iter()
+                .filter(|(_, status)| *status == TaskStatus::DeadLettered.as_i16())
+                .map(|(seq_id, _)| seq_id),
+        );
+
+        let parent_ids: Vec<(i16, i64)> = failed
+            .into_iter()
+            .map(|seq_id| (partition_id, seq_id))
+            .collect();
+        let blocked = self.dependents(queue_id, &parent_ids).await?;
+
+        self.release_blocked_tasks(queue_id, blocked, now).await
+    }
+
+    /// Moves the tasks depending on a task that has just finished on.
+    async fn release_dependents(&self, task_id: &TaskId, now: i64) -> Result<()> {
+        let blocked = self
+            .dependents(
+                task_id.queue_id(),
+                &[(task_id.partition_id(), task_id.seq_id())],
+            )
+            .await?;
+
+        self.release_blocked_tasks(task_id.queue_id(), blocked, now)
+            .await
+    }
+
+    /// The `(partition_id, seq_id)` of the tasks depending on any of `parent_ids`.
+    async fn dependents(
+        &self,
+        queue_id: &str,
+        parent_ids: &[(i16, i64)],
+    ) -> Result<Vec<(i16, i64)>> {
+        if parent_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let partition_ids: Vec<i16> = parent_ids
+            .iter()
+            .map(|(partition_id, _)| *partition_id)
+            .collect();
+        let seq_ids: Vec<i64> = parent_ids.iter().map(|(_, seq_id)| *seq_id).collect();
+
+        let dependents = sqlx::query_as(
+            r#"
+            SELECT DISTINCT partition_id, seq_id
+            FROM svppl_task_dependency
+            WHERE queue_id = $1
+            AND (parent_partition_id, parent_seq_id) IN (
+                SELECT * FROM UNNEST($2::SMALLINT[], $3::BIGINT[])
+            )
+            "#,
+        )
+        .bind(queue_id)
+        .bind(partition_ids)
+        .bind(seq_ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(dependents)
+    }
+
+    /// Moves the given blocked tasks on once their parents have finished, failing or
+    /// cancelling them when a parent failed. A parent that no longer exists counts as failed.
+    async fn release_blocked_tasks(
+        &self,
+        queue_id: &str,
+        mut task_ids: Vec<(i16, i64)>,
+        now: i64,
+    ) -> Result<()> {
+        let failing = status_codes(
+            &TaskStatus::ALL
+                .into_iter()
+                .filter(|status| status.fails_dependents())
+                .collect::<Vec<_>>(),
+        );
+
+        // Each pass moves on the tasks depending on those failed by the pass before.
+        while !task_ids.is_empty() {
+            let partition_ids: Vec<i16> = task_ids
+                .iter()
+                .map(|(partition_id, _)| *partition_id)
+                .collect();
+            let seq_ids: Vec<i64> = task_ids.iter().map(|(_, seq_id)| *seq_id).collect();
+
+            let failed: Vec<(i16, i64)> = sqlx::query_as(
+                r#"
+                UPDATE svppl_task
+                SET status = CASE WHEN parent_failure_policy = $6 THEN $7 ELSE $5 END,
+                    last_error = $9
+                WHERE queue_id = $1
+                AND (partition_id, seq_id) IN (
+                    SELECT * FROM UNNEST($2::SMALLINT[], $3::BIGINT[])
+                )
+                AND status = $4
+                AND parent_failure_policy <> $8
+                AND EXISTS (
+                    SELECT 1
+                    FROM svppl_task_dependency dependency
+                    LEFT JOIN svppl_task parent
+                    ON parent.queue_id = dependency.queue_id
+                    AND parent.partition_id = dependency.parent_partition_id
+                    AND parent.seq_id = dependency.parent_seq_id
+                    WHERE dependency.queue_id = svppl_task.queue_id
+                    AND dependency.partition_id = svppl_task.partition_id
+                    AND dependency.seq_id = svppl_task.seq_id
+                    AND COALESCE(parent.status, $5) = ANY($10)
+                )
+                RETURNING partition_id, seq_id
+                "#,
+            )
+            .bind(queue_id)
+            .bind(&partition_ids)
+            .bind(&seq_ids)
+            .bind(TaskStatus::Blocked.as_i16())
+            .bind(TaskStatus::Failed.as_i16())
+            .bind(ParentFailurePolicy::Cancel.as_i16())
+            .bind(TaskStatus::Cancelled.as_i16())
+            .bind(ParentFailurePolicy::Run.as_i16())
+            .bind(DEPENDENCY_FAILED_REASON)
+            .bind(&failing)
+            .fetch_all(&self.pool)
+            .await?;
+
+            sqlx::query(
+                r#"
+                UPDATE svppl_task
+                SET status = CASE WHEN scheduled_at > $5 THEN $6 ELSE $7 END
+                WHERE queue_id = $1
+                AND (partition_id, seq_id) IN (
+                    SELECT * FROM UNNEST($2::SMALLINT[], $3::BIGINT[])
+                )
+                AND status = $4
+                AND NOT EXISTS (
+                    SELECT 1
+                    FROM svppl_task_dependency dependency
+                    LEFT JOIN svppl_task parent
+                    ON parent.queue_id = dependency.queue_id
+                    AND parent.partition_id = dependency.parent_partition_id
+                    AND parent.seq_id = dependency.parent_seq_id
+                    WHERE dependency.queue_id = svppl_task.queue_id
+                    AND dependency.partition_id = svppl_task.partition_id
+                    AND dependency.seq_id = svppl_task.seq_id
+                    AND COALESCE(parent.status, $10) <> $8
+                    AND NOT (svppl_task.parent_failure_policy = $9 AND COALESCE(parent.status, $10) = ANY($11))
+                )
+                "#,
+            )
+            .bind(queue_id)
+            .bind(&partition_ids)
+            .bind(&seq_ids)
+            .bind(TaskStatus::Blocked.as_i16())
+            .bind(now)
+            .bind(TaskStatus::Scheduled.as_i16())
+            .bind(TaskStatus::Pending.as_i16())
+            .bind(TaskStatus::Succeeded.as_i16())
+            .bind(ParentFailurePolicy::Run.as_i16())
+            .bind(TaskStatus::Failed.as_i16())
+            .bind(&failing)
+            .execute(&self.pool)
+            .await?;
+
+            task_ids = self.dependents(queue_id, &failed).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Creates the workflow and enqueues its first workflow task, unless the id is taken.
+async fn insert_workflow(
+    conn: &mut PgConnection,
+    workflow: &NewWorkflow<'_>,
+    parent_workflow_id: Option<&str>,
+    now: i64,
+) -> Result<bool> {
+    let created = sqlx::query(
+        r#"
+        INSERT INTO svppl_workflow (workflow_id, workflow_type, queue_id, partition_id, status, last_event_id, parent_workflow_id, created_at)
+        VALUES ($1, $2, $3, $4, $5, 1, $6, $7)
+        ON CONFLICT (workflow_id) DO NOTHING
+        "#,
+    )
+    .bind(workflow.workflow_id)
+    .bind(workflow.workflow_type)
+    .bind(workflow.queue_id)
+    .bind(workflow.partition_id)
+    .bind(WorkflowStatus::Running.as_i16())
+    .bind(parent_workflow_id)
+    .bind(now)
+    .execute(&mut *conn)
+    .await?;
+
+    if created.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO svppl_workflow_event (workflow_id, event_id, kind, name, payload, recorded_at)
+        VALUES ($1, 1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(workflow.workflow_id)
+    .bind(WorkflowEventKind::WorkflowStarted.as_i16())
+    .bind(workflow.workflow_type)
+    .bind(workflow.input)
+    .bind(now)
+    .execute(&mut *conn)
+    .await?;
+
+    insert_workflow_task(
+        conn,
+        workflow.workflow_id,
+        workflow.queue_id,
+        workflow.partition_id,
+        now,
+    )
+    .await?;
+
+    Ok(true)
+}
+
+/// Records an event the server writes on a running workflow and enqueues a workflow task to
+/// replay it. `None` if the workflow does not exist or has finished.
+async fn record_workflow_event(
+    conn: &mut PgConnection,
+    workflow_id: &str,
+    event: &NewWorkflowEvent,
+    now: i64,
+) -> Result<Option<i64>> {
+    let row = sqlx::query(
+        r#"
+        UPDATE svppl_workflow
+        SET last_event_id = last_event_id + 1
+        WHERE workflow_id = $1
+        AND status = $2
+        RETURNING last_event_id, queue_id, partition_id
+        "#,
+    )
+    .bind(workflow_id)
+    .bind(WorkflowStatus::Running.as_i16())
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    let event_id: i64 = row.try_get(0)?;
+    let queue_id: String = row.try_get(1)?;
+    let partition_id: i16 = row.try_get(2)?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO svppl_workflow_event (workflow_id, event_id, kind, name, payload, error, fire_at, recorded_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+    )
+    .bind(workflow_id)
+    .bind(event_id)
+    .bind(event.kind.as_i16())
+    .bind(&event.name)
+    .bind(&event.payload)
+    .bind(&event.error)
+    .bind(event.fire_at)
+    .bind(now)
+    .execute(&mut *conn)
+    .await?;
+
+    insert_workflow_task(conn, workflow_id, &queue_id, partition_id, now).await?;
+
+    Ok(Some(event_id))
+}
+
+/// Enqueues a task to replay the workflow, with the queue's retry policy.
+async fn insert_workflow_task(
+    conn: &mut PgConnection,
+    workflow_id: &str,
+    queue_id: &str,
+    partition_id: i16,
+    now: i64,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO svppl_task (queue_id, partition_id, payload, content_type, status, scheduled_at, max_attempts)
+        SELECT $1, $2, $3, $4, $5, $6, COALESCE(
+            (SELECT max_attempts FROM svppl_retry_policy WHERE queue_id = $1),
+            $7
+        )
+        "#,
+    )
+    .bind(queue_id)
+    .bind(partition_id)
+    .bind(workflow_id.as_bytes())
+    .bind(WORKFLOW_TASK_CONTENT_TYPE)
+    .bind(TaskStatus::Pending.as_i16())
+    .bind(now)
+    .bind(RetryPolicy::default().max_attempts)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_workflow_timers(conn: &mut PgConnection, timers: &[WorkflowTimer]) -> Result<()> {
+    if timers.is_empty() {
+        return Ok(());
+    }
+
+    let mut query_builder = QueryBuilder::new(
+        "INSERT INTO svppl_workflow_timer (workflow_id, timer_id, name, fire_at) ",
+    );
+
+    query_builder.push_values(timers, |mut b, timer| {
+        b.push_bind(&timer.workflow_id)
+            .push_bind(timer.timer_id)
+            .push_bind(&timer.name)
+            .push_bind(timer.fire_at);
+    });
+
+    query_builder.build().execute(conn).await?;
+
+    Ok(())
+}
+
+async fn insert_dependencies(
+    conn: &mut PgConnection,
+    queue_id: &str,
+    dependencies: &[TaskDependency],
+) -> Result<()> {
+    if dependencies.is_empty() {
+        return Ok(());
+    }
+
+    let partition_ids: Vec<i16> = dependencies
+        .iter()
+        .map(|dependency| dependency.task_id.partition_id())
+        .collect();
+    let seq_ids: Vec<i64> = dependencies
+        .iter()
+        .map(|dependency| dependency.task_id.seq_id())
+        .collect();
+    let parent_partition_ids: Vec<i16> = dependencies
+        .iter()
+        .map(|dependency| dependency.parent_id.partition_id())
+        .collect();
+    let parent_seq_ids: Vec<i64> = dependencies
+        .iter()
+        .map(|dependency| dependency.parent_id.seq_id())
+        .collect();
+
+    sqlx::query(
+        r#"
+        INSERT INTO svppl_task_dependency (queue_id, partition_id, seq_id, parent_partition_id, parent_seq_id)
+        SELECT $1, * FROM UNNEST($2::SMALLINT[], $3::BIGINT[], $4::SMALLINT[], $5::BIGINT[])
+        "#,
+    )
+    .bind(queue_id)
+    .bind(partition_ids)
+    .bind(seq_ids)
+    .bind(parent_partition_ids)
+    .bind(parent_seq_ids)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+fn task_data_from_row(queue_id: &str, partition_id: i16, row: &PgRow) -> Result<TaskData> {
+    let seq_id: i64 = row.try_get(0)?;
+    let status: i16 = row.try_get(1)?;
+    let payload: Vec<u8> = row.try_get(2)?;
+    let scheduled_at: i64 = row.try_get(3)?;
+    let deadline_at: Option<i64> = row.try_get(4)?;
+    let timeout_ms: Option<i64> = row.try_get(5)?;
+    let content_type: Option<String> = row.try_get(6)?;
+    let last_error: Option<String> = row.try_get(7)?;
+    let attempts: i32 = row.try_get(8)?;
+    let max_attempts: i32 = row.try_get(9)?;
+    let priority: i16 = row.try_get(10)?;
+    let result: Option<Vec<u8>> = row.try_get(11)?;
+    let progress_percent: Option<f32> = row.try_get(12)?;
+    let progress_detail: Option<String> = row.try_get(13)?;
+    let definition_id: Option<String> = row.try_get(14)?;
+    let definition_version: Option<i32> = row.try_get(15)?;
+
+    Ok(TaskData {
+        task_id: TaskId::from_parts(queue_id, partition_id, seq_id),
+        status: TaskStatus::try_from(status)?,
+        payload,
+        content_type,
+        scheduled_at,
+        deadline_at,
+        timeout_ms,
+        last_error,
+        attempts,
+        max_attempts,
+        priority,
+        result,
+        progress: progress_percent.map(|percent| TaskProgress {
+            percent,
+            detail: progress_detail,
+        }),
+        definition_id,
+        definition_version,
+    })
+}
+
+fn schedule_from_row(row: &PgRow) -> Result<Schedule> {
+    let misfire_policy: i16 = row.try_get(7)?;
+
+    Ok(Schedule {
+        schedule_id: row.try_get(0)?,
+        cron: row.try_get(1)?,
+        timezone: row.try_get(2)?,
+        queue_id: row.try_get(3)?,
+        partition_id: row.try_get(4)?,
+        payload: row.try_get(5)?,
+        content_type: row.try_get(6)?,
+        misfire_policy: MisfirePolicy::try_from(misfire_policy)?,
+        next_fire_at: row.try_get(8)?,
+    })
+}
+
+fn workflow_from_row(row: &PgRow) -> Result<Workflow> {
+    let status: i16 = row.try_get(4)?;
+
+    Ok(Workflow {
+        workflow_id: row.try_get(0)?,
+        workflow_type: row.try_get(1)?,
+        queue_id: row.try_get(2)?,
+        partition_id: row.try_get(3)?,
+        status: WorkflowStatus::try_from(status)?,
+        result: row.try_get(5)?,
+        error: row.try_get(6)?,
+        last_event_id: row.try_get(7)?,
+        created_at: row.try_get(8)?,
+        awaiting_signal: row.try_get(9)?,
+        parent_workflow_id: row.try_get(10)?,
+    })
+}
+
+fn workflow_event_from_row(row: &PgRow) -> Result<WorkflowEvent> {
+    let kind: i16 = row.try_get(1)?;
+
+    Ok(WorkflowEvent {
+        event_id: row.try_get(0)?,
+        kind: WorkflowEventKind::try_from(kind)?,
+        name: row.try_get(2)?,
+        payload: row.try_get(3)?,
+        error: row.try_get(4)?,
+        fire_at: row.try_get(5)?,
+        recorded_at: row.try_get(6)?,
+    })
+}
+
+pub async fn create_connection_pool(url: &str) -> Result<sqlx::PgPool> {
+    let pool = PgPoolOptions::new().max_connections(5).connect(url).await?;
+
+    Ok(pool)
+}
+
+/// The claim's ordering expression, matching [`effective_priority`] for the `now` bound at
+/// `now_param`.
+fn priority_order(now_param: &str, aging_ms: Option<i64>) -> String {
+    match aging_ms {
+        Some(aging_ms) => format!("priority + ({} - scheduled_at) / {}", now_param, aging_ms),
+        None => "priority".to_string(),
+    }
+}
+
+fn status_codes(statuses: &[TaskStatus]) -> Vec<i16> {
+    statuses.iter().map(|status| status.as_i16()).collect()
+}
+
+fn task_definition_from_row(row: &PgRow) -> Result<TaskDefinition> {
+    Ok(TaskDefinition {
+        definition_id: row.try_get(0)?,
+        version: row.try_get(1)?,
+        name: row.try_get(2)?,
+        description: row.try_get(3)?,
+        parameters: row.try_get(4)?,
+        created_at: row.try_get(5)?,
+    })
 }
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use super::proto::{self, admin_server::Admin};
+use super::task_service::{internal_error, partition_id, query_limit, task_id};
+use crate::persistence::common::{TaskId, TaskQueue};
+
+pub struct AdminService<Q> {
+    task_queue: Arc<Q>,
+}
+
+impl<Q> AdminService<Q> {
+    pub fn new(task_queue: Arc<Q>) -> Self {
+        Self { task_queue }
+    }
+}
+
+#[tonic::async_trait]
+impl<Q> Admin for AdminService<Q>
+where
+    Q: TaskQueue + Send + Sync + 'static,
+{
+    async fn list_dead_lettered(
+        &self,
+        request: tonic::Request<proto::ListDeadLetteredRequest>,
+    ) -> Result<tonic::Response<proto::ListDeadLetteredReply>, tonic::Status> {
+        let request = request.into_inner();
+        let partition_id = partition_id(request.partition)?;
+
+        let tasks = self
+            .task_queue
+            .dead_lettered_tasks(&request.queue_id, partition_id, query_limit(request.limit))
+            .await
+            .map_err(internal_error)?;
+
+        let response = proto::ListDeadLetteredReply {
+            tasks: tasks.into_iter().map(proto::TaskInfo::from).collect(),
+        };
+
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn redrive_dead_lettered(
+        &self,
+        request: tonic::Request<proto::RedriveDeadLetteredRequest>,
+    ) -> Result<tonic::Response<proto::RedriveDeadLetteredReply>, tonic::Status> {
+        let request = request.into_inner();
+        let partition_id = partition_id(request.partition)?;
+
+        let redriven = if request.all {
+            if !request.task_ids.is_empty() {
+                return Err(tonic::Status::invalid_argument(
+                    "task_ids must be empty when redriving all tasks",
+                ));
+            }
+
+            self.task_queue
+                .redrive_all_tasks(&request.queue_id, partition_id)
+                .await
+        } else {
+            // Requests are routed by partition, so every task has to live on it.
+            let task_ids = request
+                .task_ids
+                .iter()
+                .map(|raw| {
+                    let task_id = task_id(raw)?;
+
+                    if task_id.queue_id() != request.queue_id
+                        || task_id.partition_id() != partition_id
+                    {
+                        return Err(tonic::Status::invalid_argument(format!(
+                            "task is not in {}:{}: {}",
+                            request.queue_id, partition_id, task_id
+                        )));
+                    }
+
+                    Ok(task_id)
+                })
+                .collect::<Result<Vec<TaskId>, tonic::Status>>()?;
+
+            self.task_queue.redrive_tasks(&task_ids).await
+        }
+        .map_err(internal_error)?;
+
+        Ok(tonic::Response::new(proto::RedriveDeadLetteredReply {
+            redriven: redriven as i64,
+        }))
+    }
+
+    async fn purge_dead_lettered(
+        &self,
+        request: tonic::Request<proto::PurgeDeadLetteredRequest>,
+    ) -> Result<tonic::Response<proto::PurgeDeadLetteredReply>, tonic::Status> {
+        let request = request.into_inner();
+        let partition_id = partition_id(request.partition)?;
+
+        let purged = self
+            .task_queue
+            .purge_dead_lettered(&request.queue_id, partition_id)
+            .await
+            .map_err(internal_error)?;
+
+        Ok(tonic::Response::new(proto::PurgeDeadLetteredReply {
+            purged: purged as i64,
+        }))
+    }
+}
@@ -1,5 +1,10 @@
 pub mod proto;
 pub mod server;
 
-mod partition_router;
+mod admin_service;
+pub mod partition_router;
+mod schedule_service;
+mod task_lease;
 mod task_service;
+mod task_wait;
+mod workflow_service;
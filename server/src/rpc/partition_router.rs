@@ -8,13 +8,28 @@ use crate::partition_resolver::PartitionResolver;
 
 use futures::future::BoxFuture;
 use hyper::body::HttpBody;
+use prost::Message;
 
 use tonic::body::BoxBody;
 
 use tonic::transport::Body;
-use tower::Service;
+use tower::{Service, ServiceExt};
 use tracing::{span, Instrument, Level};
 
+/// RPCs routed by the workflow id in their request, so they reach the node that owns the
+/// workflow whatever headers the client sends.
+const WORKFLOW_ROUTED_PATHS: [&str; 2] = [
+    "/svppl.v0.Workflows/SignalWorkflow",
+    "/svppl.v0.Workflows/QueryWorkflow",
+];
+
+/// The field the workflow routed requests share, decoded without the rest of the request.
+#[derive(Clone, PartialEq, Message)]
+struct WorkflowKey {
+    #[prost(string, tag = "1")]
+    workflow_id: String,
+}
+
 pub struct PartitionRouter<S> {
     partition_resolver: PartitionResolver,
     // cluster_monitor: ClusterMonitor,
@@ -62,10 +77,12 @@ where
         let partition_resolver = self.partition_resolver.clone();
 
         Box::pin(async move {
-            let meta = tonic::metadata::MetadataMap::from_headers(req.headers().clone());
-            let partition_key = meta.get("partition_key");
+            let (req, partition_key) = match partition_key(req).await {
+                Ok(routed) => routed,
+                Err(status) => return Ok(status.to_http()),
+            };
 
-            let maybe_channel = if let Some(key) = partition_key {
+            let maybe_channel = if let Some(key) = &partition_key {
                 partition_resolver.resolve(key.as_bytes()).await
             } else {
                 None
@@ -82,8 +99,8 @@ where
                     .boxed_unsync();
 
                 let req_boxed = hyper::Request::from_parts(parts, boxed);
-                let res = channel
-                    .call(req_boxed)
+                // The channel buffers calls, so it has to be ready for one first.
+                let res = async { channel.ready().await?.call(req_boxed).await }
                     .instrument(span!(Level::INFO, "external_rpc", partition_key =? partition_key))
                     .await;
 
@@ -125,6 +142,49 @@ where
     }
 }
 
+/// The key `req` is routed by, the workflow id of a workflow routed request or otherwise the
+/// `partition_key` header. Buffers the body of a workflow routed request to read it.
+async fn partition_key(
+    req: hyper::Request<Body>,
+) -> Result<(hyper::Request<Body>, Option<String>), tonic::Status> {
+    if !WORKFLOW_ROUTED_PATHS.contains(&req.uri().path()) {
+        let partition_key = req
+            .headers()
+            .get("partition_key")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        return Ok((req, partition_key));
+    }
+
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(|err| tonic::Status::internal(format!("failed to read request: {}", err)))?;
+
+    let workflow_id = workflow_id(&body)
+        .ok_or_else(|| tonic::Status::invalid_argument("expected one uncompressed request"))?;
+
+    Ok((
+        hyper::Request::from_parts(parts, Body::from(body)),
+        Some(workflow_id),
+    ))
+}
+
+/// Decodes the workflow id from the body of a unary request, a single uncompressed gRPC
+/// message behind a compression flag and a big endian length.
+fn workflow_id(body: &[u8]) -> Option<String> {
+    let message = body
+        .strip_prefix(&[0])
+        .filter(|rest| rest.len() >= 4)
+        .and_then(|rest| {
+            let (len, message) = rest.split_at(4);
+            message.get(..u32::from_be_bytes(len.try_into().ok()?) as usize)
+        })?;
+
+    WorkflowKey::decode(message).ok().map(|key| key.workflow_id)
+}
+
 #[derive(Clone)]
 pub struct PartitionRoutingLayer {
     // cluster_monitor: ClusterMonitor,
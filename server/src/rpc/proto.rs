@@ -1 +1,273 @@
 tonic::include_proto!("svppl.v0");
+
+use crate::persistence::common;
+
+impl From<common::TaskStatus> for TaskStatus {
+    fn from(status: common::TaskStatus) -> Self {
+        match status {
+            common::TaskStatus::Pending => TaskStatus::Pending,
+            common::TaskStatus::Scheduled => TaskStatus::Scheduled,
+            common::TaskStatus::Leased => TaskStatus::Leased,
+            common::TaskStatus::Succeeded => TaskStatus::Succeeded,
+            common::TaskStatus::Failed => TaskStatus::Failed,
+            common::TaskStatus::Cancelled => TaskStatus::Cancelled,
+            common::TaskStatus::DeadLettered => TaskStatus::DeadLettered,
+            common::TaskStatus::Blocked => TaskStatus::Blocked,
+        }
+    }
+}
+
+impl From<TaskStatus> for common::TaskStatus {
+    fn from(status: TaskStatus) -> Self {
+        match status {
+            TaskStatus::Pending => common::TaskStatus::Pending,
+            TaskStatus::Scheduled => common::TaskStatus::Scheduled,
+            TaskStatus::Leased => common::TaskStatus::Leased,
+            TaskStatus::Succeeded => common::TaskStatus::Succeeded,
+            TaskStatus::Failed => common::TaskStatus::Failed,
+            TaskStatus::Cancelled => common::TaskStatus::Cancelled,
+            TaskStatus::DeadLettered => common::TaskStatus::DeadLettered,
+            TaskStatus::Blocked => common::TaskStatus::Blocked,
+        }
+    }
+}
+
+impl From<common::TaskData> for TaskInfo {
+    fn from(task: common::TaskData) -> Self {
+        TaskInfo {
+            task_id: task.task_id.to_string(),
+            status: TaskStatus::from(task.status).into(),
+            payload: task.payload,
+            content_type: task.content_type,
+            scheduled_at: task.scheduled_at,
+            deadline_at: task.deadline_at,
+            timeout_ms: task.timeout_ms,
+            last_error: task.last_error,
+            attempts: task.attempts,
+            max_attempts: task.max_attempts,
+            priority: task.priority.into(),
+            result: task.result,
+            progress: task.progress.map(Into::into),
+            definition_id: task.definition_id,
+            definition_version: task.definition_version,
+        }
+    }
+}
+
+impl From<common::TaskProgress> for TaskProgress {
+    fn from(progress: common::TaskProgress) -> Self {
+        TaskProgress {
+            percent: progress.percent,
+            detail: progress.detail,
+        }
+    }
+}
+
+impl From<TaskProgress> for common::TaskProgress {
+    fn from(progress: TaskProgress) -> Self {
+        common::TaskProgress {
+            percent: progress.percent,
+            detail: progress.detail,
+        }
+    }
+}
+
+impl From<ParentFailurePolicy> for common::ParentFailurePolicy {
+    fn from(policy: ParentFailurePolicy) -> Self {
+        match policy {
+            ParentFailurePolicy::Fail => common::ParentFailurePolicy::Fail,
+            ParentFailurePolicy::Cancel => common::ParentFailurePolicy::Cancel,
+            ParentFailurePolicy::Run => common::ParentFailurePolicy::Run,
+        }
+    }
+}
+
+impl From<common::TaskDependency> for TaskDependency {
+    fn from(dependency: common::TaskDependency) -> Self {
+        TaskDependency {
+            task_id: dependency.task_id.to_string(),
+            parent_id: dependency.parent_id.to_string(),
+        }
+    }
+}
+
+impl From<common::MisfirePolicy> for MisfirePolicy {
+    fn from(policy: common::MisfirePolicy) -> Self {
+        match policy {
+            common::MisfirePolicy::CatchUp => MisfirePolicy::CatchUp,
+            common::MisfirePolicy::Skip => MisfirePolicy::Skip,
+        }
+    }
+}
+
+impl From<MisfirePolicy> for common::MisfirePolicy {
+    fn from(policy: MisfirePolicy) -> Self {
+        match policy {
+            MisfirePolicy::CatchUp => common::MisfirePolicy::CatchUp,
+            MisfirePolicy::Skip => common::MisfirePolicy::Skip,
+        }
+    }
+}
+
+impl From<common::Schedule> for Schedule {
+    fn from(schedule: common::Schedule) -> Self {
+        Schedule {
+            schedule_id: schedule.schedule_id,
+            cron: schedule.cron,
+            timezone: schedule.timezone,
+            queue_id: schedule.queue_id,
+            partition: schedule.partition_id.into(),
+            payload: schedule.payload,
+            content_type: schedule.content_type,
+            misfire_policy: MisfirePolicy::from(schedule.misfire_policy).into(),
+            next_fire_at: schedule.next_fire_at,
+        }
+    }
+}
+
+impl From<common::RetryPolicy> for RetryPolicy {
+    fn from(policy: common::RetryPolicy) -> Self {
+        RetryPolicy {
+            max_attempts: policy.max_attempts,
+            initial_backoff_ms: policy.initial_backoff_ms,
+            max_backoff_ms: policy.max_backoff_ms,
+            backoff_multiplier: policy.backoff_multiplier,
+            jitter: policy.jitter,
+        }
+    }
+}
+
+impl From<RetryPolicy> for common::RetryPolicy {
+    fn from(policy: RetryPolicy) -> Self {
+        common::RetryPolicy {
+            max_attempts: policy.max_attempts,
+            initial_backoff_ms: policy.initial_backoff_ms,
+            max_backoff_ms: policy.max_backoff_ms,
+            backoff_multiplier: policy.backoff_multiplier,
+            jitter: policy.jitter,
+        }
+    }
+}
+
+impl From<common::WorkflowStatus> for WorkflowStatus {
+    fn from(status: common::WorkflowStatus) -> Self {
+        match status {
+            common::WorkflowStatus::Running => WorkflowStatus::Running,
+            common::WorkflowStatus::Completed => WorkflowStatus::Completed,
+            common::WorkflowStatus::Failed => WorkflowStatus::Failed,
+        }
+    }
+}
+
+impl From<common::WorkflowEventKind> for WorkflowEventKind {
+    fn from(kind: common::WorkflowEventKind) -> Self {
+        match kind {
+            common::WorkflowEventKind::WorkflowStarted => WorkflowEventKind::WorkflowStarted,
+            common::WorkflowEventKind::StepCompleted => WorkflowEventKind::StepCompleted,
+            common::WorkflowEventKind::StepFailed => WorkflowEventKind::StepFailed,
+            common::WorkflowEventKind::WorkflowCompleted => WorkflowEventKind::WorkflowCompleted,
+            common::WorkflowEventKind::WorkflowFailed => WorkflowEventKind::WorkflowFailed,
+            common::WorkflowEventKind::TimerStarted => WorkflowEventKind::TimerStarted,
+            common::WorkflowEventKind::TimerFired => WorkflowEventKind::TimerFired,
+            common::WorkflowEventKind::SignalAwaited => WorkflowEventKind::SignalAwaited,
+            common::WorkflowEventKind::SignalReceived => WorkflowEventKind::SignalReceived,
+            common::WorkflowEventKind::ChildWorkflowStarted => {
+                WorkflowEventKind::ChildWorkflowStarted
+            }
+            common::WorkflowEventKind::ChildWorkflowCompleted => {
+                WorkflowEventKind::ChildWorkflowCompleted
+            }
+            common::WorkflowEventKind::ChildWorkflowFailed => {
+                WorkflowEventKind::ChildWorkflowFailed
+            }
+            common::WorkflowEventKind::CompensationCompleted => {
+                WorkflowEventKind::CompensationCompleted
+            }
+            common::WorkflowEventKind::CompensationFailed => WorkflowEventKind::CompensationFailed,
+        }
+    }
+}
+
+impl From<WorkflowEventKind> for common::WorkflowEventKind {
+    fn from(kind: WorkflowEventKind) -> Self {
+        match kind {
+            WorkflowEventKind::WorkflowStarted => common::WorkflowEventKind::WorkflowStarted,
+            WorkflowEventKind::StepCompleted => common::WorkflowEventKind::StepCompleted,
+            WorkflowEventKind::StepFailed => common::WorkflowEventKind::StepFailed,
+            WorkflowEventKind::WorkflowCompleted => common::WorkflowEventKind::WorkflowCompleted,
+            WorkflowEventKind::WorkflowFailed => common::WorkflowEventKind::WorkflowFailed,
+            WorkflowEventKind::TimerStarted => common::WorkflowEventKind::TimerStarted,
+            WorkflowEventKind::TimerFired => common::WorkflowEventKind::TimerFired,
+            WorkflowEventKind::SignalAwaited => common::WorkflowEventKind::SignalAwaited,
+            WorkflowEventKind::SignalReceived => common::WorkflowEventKind::SignalReceived,
+            WorkflowEventKind::ChildWorkflowStarted => {
+                common::WorkflowEventKind::ChildWorkflowStarted
+            }
+            WorkflowEventKind::ChildWorkflowCompleted => {
+                common::WorkflowEventKind::ChildWorkflowCompleted
+            }
+            WorkflowEventKind::ChildWorkflowFailed => {
+                common::WorkflowEventKind::ChildWorkflowFailed
+            }
+            WorkflowEventKind::CompensationCompleted => {
+                common::WorkflowEventKind::CompensationCompleted
+            }
+            WorkflowEventKind::CompensationFailed => common::WorkflowEventKind::CompensationFailed,
+        }
+    }
+}
+
+impl From<common::Workflow> for Workflow {
+    fn from(workflow: common::Workflow) -> Self {
+        Workflow {
+            workflow_id: workflow.workflow_id,
+            workflow_type: workflow.workflow_type,
+            queue_id: workflow.queue_id,
+            partition: workflow.partition_id.into(),
+            status: WorkflowStatus::from(workflow.status).into(),
+            result: workflow.result,
+            error: workflow.error,
+            last_event_id: workflow.last_event_id,
+            created_at: workflow.created_at,
+            awaiting_signal: workflow.awaiting_signal,
+            parent_workflow_id: workflow.parent_workflow_id,
+        }
+    }
+}
+
+impl From<common::WorkflowEvent> for WorkflowEvent {
+    fn from(event: common::WorkflowEvent) -> Self {
+        WorkflowEvent {
+            event_id: event.event_id,
+            kind: WorkflowEventKind::from(event.kind).into(),
+            name: event.name,
+            payload: event.payload,
+            error: event.error,
+            recorded_at: event.recorded_at,
+            fire_at: event.fire_at,
+        }
+    }
+}
+
+impl From<common::WorkflowTimer> for WorkflowTimer {
+    fn from(timer: common::WorkflowTimer) -> Self {
+        WorkflowTimer {
+            timer_id: timer.timer_id,
+            name: timer.name,
+            fire_at: timer.fire_at,
+        }
+    }
+}
+
+impl From<common::TaskDefinition> for TaskDefinition {
+    fn from(definition: common::TaskDefinition) -> Self {
+        TaskDefinition {
+            definition_id: definition.definition_id,
+            version: definition.version,
+            name: definition.name,
+            description: definition.description,
+            parameters: definition.parameters,
+            created_at: definition.created_at,
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use super::proto::{self, schedules_server::Schedules};
+use super::task_service::{internal_error, partition_id};
+use crate::persistence::common::{now_millis, Schedule, TaskQueue};
+use crate::scheduler::CronSchedule;
+
+const DEFAULT_TIMEZONE: &str = "UTC";
+
+pub struct ScheduleService<Q> {
+    task_queue: Arc<Q>,
+}
+
+impl<Q> ScheduleService<Q> {
+    pub fn new(task_queue: Arc<Q>) -> Self {
+        Self { task_queue }
+    }
+}
+
+#[tonic::async_trait]
+impl<Q> Schedules for ScheduleService<Q>
+where
+    Q: TaskQueue + Send + Sync + 'static,
+{
+    async fn put_schedule(
+        &self,
+        request: tonic::Request<proto::PutScheduleRequest>,
+    ) -> Result<tonic::Response<proto::PutScheduleReply>, tonic::Status> {
+        let schedule = request
+            .into_inner()
+            .schedule
+            .ok_or_else(|| tonic::Status::invalid_argument("schedule is required"))?;
+
+        if schedule.schedule_id.is_empty() {
+            return Err(tonic::Status::invalid_argument("schedule_id is required"));
+        }
+
+        let partition_id = partition_id(schedule.partition)?;
+
+        let misfire_policy =
+            proto::MisfirePolicy::try_from(schedule.misfire_policy).map_err(|_| {
+                tonic::Status::invalid_argument(format!(
+                    "invalid misfire policy: {}",
+                    schedule.misfire_policy
+                ))
+            })?;
+
+        let timezone = if schedule.timezone.is_empty() {
+            DEFAULT_TIMEZONE.to_string()
+        } else {
+            schedule.timezone
+        };
+
+        let cron = CronSchedule::parse(&schedule.cron, &timezone)
+            .map_err(|err| tonic::Status::invalid_argument(err.to_string()))?;
+
+        let next_fire_at = cron.next_after(now_millis()).ok_or_else(|| {
+            tonic::Status::invalid_argument(format!(
+                "cron expression never fires: {}",
+                schedule.cron
+            ))
+        })?;
+
+        let schedule = Schedule {
+            schedule_id: schedule.schedule_id,
+            cron: schedule.cron,
+            timezone,
+            queue_id: schedule.queue_id,
+            partition_id,
+            payload: schedule.payload,
+            content_type: schedule.content_type,
+            misfire_policy: misfire_policy.into(),
+            next_fire_at,
+        };
+
+        self.task_queue
+            .put_schedule(&schedule)
+            .await
+            .map_err(internal_error)?;
+
+        Ok(tonic::Response::new(proto::PutScheduleReply {
+            success: true,
+            next_fire_at,
+        }))
+    }
+
+    async fn get_schedule(
+        &self,
+        request: tonic::Request<proto::GetScheduleRequest>,
+    ) -> Result<tonic::Response<proto::GetScheduleReply>, tonic::Status> {
+        let request = request.into_inner();
+
+        let schedule = self
+            .task_queue
+            .get_schedule(&request.schedule_id)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| schedule_not_found(&request.schedule_id))?;
+
+        Ok(tonic::Response::new(proto::GetScheduleReply {
+            schedule: Some(schedule.into()),
+        }))
+    }
+
+    async fn list_schedules(
+        &self,
+        _request: tonic::Request<proto::ListSchedulesRequest>,
+    ) -> Result<tonic::Response<proto::ListSchedulesReply>, tonic::Status> {
+        let schedules = self
+            .task_queue
+            .list_schedules()
+            .await
+            .map_err(internal_error)?;
+
+        Ok(tonic::Response::new(proto::ListSchedulesReply {
+            schedules: schedules.into_iter().map(proto::Schedule::from).collect(),
+        }))
+    }
+
+    async fn delete_schedule(
+        &self,
+        request: tonic::Request<proto::DeleteScheduleRequest>,
+    ) -> Result<tonic::Response<proto::DeleteScheduleReply>, tonic::Status> {
+        let request = request.into_inner();
+
+        let deleted = self
+            .task_queue
+            .delete_schedule(&request.schedule_id)
+            .await
+            .map_err(internal_error)?;
+
+        if !deleted {
+            return Err(schedule_not_found(&request.schedule_id));
+        }
+
+        Ok(tonic::Response::new(proto::DeleteScheduleReply {
+            success: true,
+        }))
+    }
+}
+
+fn schedule_not_found(schedule_id: &str) -> tonic::Status {
+    tonic::Status::not_found(format!("schedule not found: {}", schedule_id))
+}
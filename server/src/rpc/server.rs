@@ -4,16 +4,24 @@ use tracing::span;
 use tracing::Instrument;
 use tracing::Level;
 
-use std::{convert::Infallible, net::SocketAddr};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
 use tokio::sync::oneshot;
 use tower::ServiceBuilder;
 
 use crate::partition_resolver::PartitionResolver;
+use crate::persistence::common::TaskQueue;
 
+use super::admin_service::AdminService;
 use super::partition_router::PartitionRoutingLayer;
-use super::proto::task_server::TaskServer;
+use super::proto::{
+    admin_server::AdminServer, schedules_server::SchedulesServer, task_server::TaskServer,
+    workflows_server::WorkflowsServer,
+};
+use super::schedule_service::ScheduleService;
 use super::task_service::TaskService;
+use super::workflow_service::WorkflowService;
 use hyper::{service::make_service_fn, Server};
+use tonic::server::NamedService;
 use tower::Service;
 
 pub struct RpcServerHandle {
@@ -40,12 +48,47 @@ impl RpcServerHandle {
     }
 }
 
-pub async fn start(
+pub async fn start<Q>(
     listen_addr: SocketAddr,
     partition_resolver: PartitionResolver,
-) -> RpcServerHandle {
-    let task_service = TaskService::default();
-    let task_server = TaskServer::new(task_service);
+    task_queue: Arc<Q>,
+) -> RpcServerHandle
+where
+    Q: TaskQueue + Send + Sync + 'static,
+{
+    let task_server = TaskServer::new(TaskService::new(task_queue.clone()));
+    let admin_server = AdminServer::new(AdminService::new(task_queue.clone()));
+    let schedules_server = SchedulesServer::new(ScheduleService::new(task_queue.clone()));
+    let workflows_server = WorkflowsServer::new(WorkflowService::new(task_queue));
+
+    // The services sit behind the one partition router, so requests are dispatched on the
+    // gRPC path's service name once routing is done.
+    let admin_path = format!("/{}/", AdminServer::<AdminService<Q>>::NAME);
+    let schedules_path = format!("/{}/", SchedulesServer::<ScheduleService<Q>>::NAME);
+    let workflows_path = format!("/{}/", WorkflowsServer::<WorkflowService<Q>>::NAME);
+
+    let grpc_services = tower::service_fn(move |req: hyper::Request<hyper::Body>| {
+        let mut task_server = task_server.clone();
+        let mut admin_server = admin_server.clone();
+        let mut schedules_server = schedules_server.clone();
+        let mut workflows_server = workflows_server.clone();
+        let path = req.uri().path();
+        let is_admin = path.starts_with(&admin_path);
+        let is_schedules = path.starts_with(&schedules_path);
+        let is_workflows = path.starts_with(&workflows_path);
+
+        async move {
+            if is_admin {
+                admin_server.call(req).await
+            } else if is_schedules {
+                schedules_server.call(req).await
+            } else if is_workflows {
+                workflows_server.call(req).await
+            } else {
+                task_server.call(req).await
+            }
+        }
+    });
 
     let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
 
@@ -53,7 +96,7 @@ pub async fn start(
         .serve(make_service_fn(move |_| {
             let mut core = ServiceBuilder::new()
                 .layer(PartitionRoutingLayer::new(partition_resolver.clone()))
-                .service(task_server.clone());
+                .service(grpc_services.clone());
 
             std::future::ready(Ok::<_, Infallible>(tower::service_fn(
                 move |req: hyper::Request<hyper::Body>| {
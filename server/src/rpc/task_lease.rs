@@ -0,0 +1,208 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::{mpsc, Mutex, OwnedSemaphorePermit, Semaphore};
+
+use super::proto;
+use crate::persistence::common::{now_millis, LeasedTask, TaskId, TaskQueue};
+
+/// How long a lease stream waits before polling an empty queue again, or checking a full
+/// stream for lapsed leases.
+const LEASE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub const DEFAULT_LEASE_MS: i64 = 30_000;
+
+pub type LeaseSender = mpsc::Sender<Result<proto::LeasedTask, tonic::Status>>;
+
+struct InFlightTask {
+    stream_id: u64,
+    lease_token: String,
+    lease_expires_at: i64,
+    _permit: OwnedSemaphorePermit,
+}
+
+/// Tracks tasks handed out over `LeaseTasks` streams that have not been acked or nacked yet.
+///
+/// Each in-flight task holds a permit from its stream's semaphore, so removing the task
+/// frees a slot for the stream to lease another one. Tasks whose lease lapses are removed
+/// too, a worker that drops a task must not hold its slot forever.
+#[derive(Clone, Default)]
+pub struct LeaseRegistry {
+    in_flight: Arc<Mutex<HashMap<TaskId, InFlightTask>>>,
+    next_stream_id: Arc<AtomicU64>,
+}
+
+impl LeaseRegistry {
+    fn next_stream_id(&self) -> u64 {
+        self.next_stream_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    async fn insert(&self, stream_id: u64, leased: &LeasedTask, permit: OwnedSemaphorePermit) {
+        self.in_flight.lock().await.insert(
+            leased.task.task_id.clone(),
+            InFlightTask {
+                stream_id,
+                lease_token: leased.lease_token.clone(),
+                lease_expires_at: leased.lease_expires_at,
+                _permit: permit,
+            },
+        );
+    }
+
+    /// Keeps a heartbeated task's slot for as long as its renewed lease.
+    pub async fn renew(&self, task_id: &TaskId, lease_token: &str, lease_expires_at: i64) {
+        if let Some(task) = self.in_flight.lock().await.get_mut(task_id) {
+            if task.lease_token == lease_token {
+                task.lease_expires_at = lease_expires_at;
+            }
+        }
+    }
+
+    /// Frees the task's slot if it is still in flight under `lease_token`. A worker whose
+    /// lease lapsed holds an old token, and must not free the slot of the stream that leased
+    /// the task again.
+    pub async fn remove(&self, task_id: &TaskId, lease_token: &str) {
+        let mut in_flight = self.in_flight.lock().await;
+
+        if in_flight
+            .get(task_id)
+            .is_some_and(|task| task.lease_token == lease_token)
+        {
+            in_flight.remove(task_id);
+        }
+    }
+
+    /// Frees the slots of the stream's tasks whose lease has lapsed.
+    async fn expire_stream(&self, stream_id: u64, now: i64) {
+        self.in_flight
+            .lock()
+            .await
+            .retain(|_, task| task.stream_id != stream_id || task.lease_expires_at > now);
+    }
+
+    async fn drain_stream(&self, stream_id: u64) -> Vec<(TaskId, String)> {
+        let mut in_flight = self.in_flight.lock().await;
+
+        let task_ids: Vec<TaskId> = in_flight
+            .iter()
+            .filter(|(_, task)| task.stream_id == stream_id)
+            .map(|(task_id, _)| task_id.clone())
+            .collect();
+
+        task_ids
+            .into_iter()
+            .filter_map(|task_id| {
+                in_flight
+                    .remove(&task_id)
+                    .map(|task| (task_id, task.lease_token))
+            })
+            .collect()
+    }
+}
+
+/// Leases tasks from `queue_id`/`partition_id` and sends them to `tx` until the receiver
+/// goes away. Anything still in flight when the stream ends is returned to the queue.
+pub async fn run_lease_stream<Q>(
+    task_queue: Arc<Q>,
+    leases: LeaseRegistry,
+    queue_id: String,
+    partition_id: i16,
+    max_in_flight: usize,
+    lease_ms: i64,
+    tx: LeaseSender,
+) where
+    Q: TaskQueue + Send + Sync + 'static,
+{
+    let stream_id = leases.next_stream_id();
+    let semaphore = Arc::new(Semaphore::new(max_in_flight));
+
+    loop {
+        leases.expire_stream(stream_id, now_millis()).await;
+
+        let permit = tokio::select! {
+            _ = tx.closed() => break,
+            permit = semaphore.clone().acquire_owned() => match permit {
+                Ok(permit) => permit,
+                Err(_) => break,
+            },
+            _ = tokio::time::sleep(LEASE_POLL_INTERVAL) => continue,
+        };
+
+        let mut permits = vec![permit];
+
+        while let Ok(permit) = semaphore.clone().try_acquire_owned() {
+            permits.push(permit);
+        }
+
+        let leased = task_queue
+            .lease_tasks(&queue_id, partition_id, permits.len() as i64, lease_ms)
+            .await;
+
+        let tasks = match leased {
+            Ok(tasks) => tasks,
+            Err(err) => {
+                tracing::error!(err = ?err, queue_id, partition_id, "lease_tasks_failed");
+                tx.send(Err(tonic::Status::internal(err.to_string())))
+                    .await
+                    .ok();
+                break;
+            }
+        };
+
+        if tasks.is_empty() {
+            drop(permits);
+
+            tokio::select! {
+                _ = tx.closed() => break,
+                _ = tokio::time::sleep(LEASE_POLL_INTERVAL) => continue,
+            }
+        }
+
+        // Register everything before sending so a disconnect part way through
+        // still releases the whole batch.
+        for (leased, permit) in tasks.iter().zip(permits) {
+            leases.insert(stream_id, leased, permit).await;
+        }
+
+        let mut disconnected = false;
+
+        for task in tasks {
+            if tx.send(Ok(leased_task(task))).await.is_err() {
+                disconnected = true;
+                break;
+            }
+        }
+
+        if disconnected {
+            break;
+        }
+    }
+
+    for (task_id, lease_token) in leases.drain_stream(stream_id).await {
+        if let Err(err) = task_queue.release_task(&task_id, &lease_token).await {
+            tracing::error!(err = ?err, task_id = %task_id, "lease_release_failed");
+        }
+    }
+}
+
+fn leased_task(leased: LeasedTask) -> proto::LeasedTask {
+    let task = leased.task;
+
+    proto::LeasedTask {
+        task_id: task.task_id.to_string(),
+        payload: task.payload,
+        content_type: task.content_type,
+        scheduled_at: task.scheduled_at,
+        deadline_at: task.deadline_at,
+        timeout_ms: task.timeout_ms,
+        lease_token: leased.lease_token,
+        lease_expires_at: leased.lease_expires_at,
+        attempt: task.attempts,
+    }
+}
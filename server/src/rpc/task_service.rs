@@ -1,20 +1,728 @@
+use std::{collections::HashMap, sync::Arc};
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic_types::{ErrorDetails, FieldViolation, StatusExt};
+
 use super::proto::{self, task_server::Task};
+use super::task_lease::{self, LeaseRegistry};
+use super::task_wait;
+use crate::persistence::common::{
+    LeaseState, NewTask, NewTaskDefinition, ParentFailurePolicy, RetryPolicy, TaskDefinition,
+    TaskId, TaskProgress, TaskQueue,
+};
+use crate::task_definition::{normalize_parameters, InvalidPayload, PayloadValidator};
+
+const DEFAULT_QUERY_LIMIT: i64 = 100;
+const MAX_QUERY_LIMIT: i64 = 1_000;
+
+pub struct TaskService<Q> {
+    task_queue: Arc<Q>,
+    leases: LeaseRegistry,
+    payloads: PayloadValidator,
+}
+
+impl<Q> TaskService<Q> {
+    pub fn new(task_queue: Arc<Q>) -> Self {
+        Self {
+            task_queue,
+            leases: LeaseRegistry::default(),
+            payloads: PayloadValidator::default(),
+        }
+    }
+}
+
+impl<Q: TaskQueue> TaskService<Q> {
+    /// The error for a settle that did not go through, telling cancelled tasks apart.
+    async fn lease_not_held(&self, task_id: &TaskId, lease_token: &str) -> tonic::Status {
+        match self.task_queue.lease_state(task_id, lease_token).await {
+            Ok(LeaseState::Cancelled) => {
+                tonic::Status::cancelled(format!("task was cancelled: {}", task_id))
+            }
+            Ok(_) => not_leased(task_id),
+            Err(err) => internal_error(err),
+        }
+    }
 
-#[derive(Default)]
-pub struct TaskService {}
+    /// Rejects a payload that does not match the task definition it was scheduled against,
+    /// with a field violation per mismatch, and returns the version it was checked against.
+    /// `index` is the task's place in a batch. Definitions are looked up once per request and
+    /// kept in `definitions` for the rest of it.
+    async fn check_payload(
+        &self,
+        definitions: &mut HashMap<(String, Option<i32>), TaskDefinition>,
+        definition_id: &str,
+        version: Option<i32>,
+        payload: &[u8],
+        index: Option<usize>,
+    ) -> Result<i32, tonic::Status> {
+        let (field, prefix) = match index {
+            Some(index) => (
+                format!("tasks[{}].payload", index),
+                format!("task {}: ", index),
+            ),
+            None => ("payload".to_string(), String::new()),
+        };
+
+        let key = (definition_id.to_string(), version);
+
+        if !definitions.contains_key(&key) {
+            let definition = self
+                .task_queue
+                .get_task_definition(definition_id, version)
+                .await
+                .map_err(internal_error)?
+                .ok_or_else(|| {
+                    let status = definition_not_found(definition_id, version);
+                    tonic::Status::not_found(format!("{}{}", prefix, status.message()))
+                })?;
+
+            definitions.insert(key.clone(), definition);
+        }
+
+        let definition = &definitions[&key];
+
+        self.payloads.check(definition, payload).map_err(|err| {
+            let invalid = match err.downcast::<InvalidPayload>() {
+                Ok(invalid) => invalid,
+                Err(err) => return internal_error(err),
+            };
+
+            let violations = invalid
+                .violations
+                .iter()
+                .map(|violation| {
+                    FieldViolation::new(
+                        format!("{}{}", field, violation.pointer),
+                        &violation.description,
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            tonic::Status::with_error_details(
+                tonic::Code::InvalidArgument,
+                format!("{}{}", prefix, invalid),
+                ErrorDetails::with_bad_request(violations),
+            )
+        })?;
+
+        Ok(definition.version)
+    }
+}
 
 #[tonic::async_trait]
-impl Task for TaskService {
+impl<Q> Task for TaskService<Q>
+where
+    Q: TaskQueue + Send + Sync + 'static,
+{
     async fn schedule_task(
         &self,
         request: tonic::Request<proto::ScheduleTaskRequest>,
     ) -> Result<tonic::Response<proto::ScheduleTaskReply>, tonic::Status> {
-        println!("Got a request: {:?}", request);
+        let request = request.into_inner();
+        let partition_id = partition_id(request.partition)?;
+
+        let definition_version = match &request.definition_id {
+            Some(definition_id) => Some(
+                self.check_payload(
+                    &mut HashMap::new(),
+                    definition_id,
+                    request.definition_version,
+                    &request.payload,
+                    None,
+                )
+                .await?,
+            ),
+            None => None,
+        };
+
+        let task = NewTask {
+            payload: &request.payload,
+            content_type: request.content_type.as_deref(),
+            scheduled_at: request.scheduled_at,
+            deadline_at: request.deadline_at,
+            timeout_ms: timeout_ms(request.timeout_ms),
+            max_attempts: max_attempts(request.max_attempts)?,
+            idempotency_key: request.idempotency_key.as_deref(),
+            priority: priority(request.priority)?,
+            definition_id: request.definition_id.as_deref(),
+            definition_version,
+            ..NewTask::default()
+        };
+
+        let task_ids = self
+            .task_queue
+            .enqueue_tasks(&request.queue_id, partition_id, vec![task])
+            .await
+            .map_err(internal_error)?;
+
+        let task_id = task_ids
+            .into_iter()
+            .next()
+            .ok_or_else(|| tonic::Status::internal("no task id returned"))?;
+
         let response = proto::ScheduleTaskReply {
             success: true,
-            task_id: None,
+            task_id: Some(task_id.to_string()),
+        };
+
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn schedule_tasks(
+        &self,
+        request: tonic::Request<proto::ScheduleTasksRequest>,
+    ) -> Result<tonic::Response<proto::ScheduleTasksReply>, tonic::Status> {
+        let request = request.into_inner();
+        let partition_id = partition_id(request.partition)?;
+
+        if request.tasks.is_empty() {
+            return Ok(tonic::Response::new(proto::ScheduleTasksReply {
+                success: true,
+                task_ids: Vec::new(),
+            }));
+        }
+
+        let depends_on = request
+            .tasks
+            .iter()
+            .enumerate()
+            .map(|(index, spec)| depends_on(index, &spec.depends_on))
+            .collect::<Result<Vec<_>, tonic::Status>>()?;
+
+        let mut definitions = HashMap::new();
+        let mut definition_versions = Vec::with_capacity(request.tasks.len());
+
+        for (index, spec) in request.tasks.iter().enumerate() {
+            let definition_version = match &spec.definition_id {
+                Some(definition_id) => Some(
+                    self.check_payload(
+                        &mut definitions,
+                        definition_id,
+                        spec.definition_version,
+                        &spec.payload,
+                        Some(index),
+                    )
+                    .await?,
+                ),
+                None => None,
+            };
+
+            definition_versions.push(definition_version);
+        }
+
+        let tasks = request
+            .tasks
+            .iter()
+            .zip(&depends_on)
+            .zip(definition_versions)
+            .map(|((spec, depends_on), definition_version)| {
+                Ok(NewTask {
+                    payload: &spec.payload,
+                    content_type: spec.content_type.as_deref(),
+                    scheduled_at: spec.scheduled_at,
+                    deadline_at: spec.deadline_at,
+                    timeout_ms: timeout_ms(spec.timeout_ms),
+                    max_attempts: max_attempts(spec.max_attempts)?,
+                    idempotency_key: spec.idempotency_key.as_deref(),
+                    priority: priority(spec.priority)?,
+                    depends_on,
+                    on_parent_failure: parent_failure_policy(spec.on_parent_failure)?,
+                    definition_id: spec.definition_id.as_deref(),
+                    definition_version,
+                })
+            })
+            .collect::<Result<Vec<_>, tonic::Status>>()?;
+
+        let task_ids = self
+            .task_queue
+            .enqueue_tasks(&request.queue_id, partition_id, tasks)
+            .await
+            .map_err(internal_error)?;
+
+        let response = proto::ScheduleTasksReply {
+            success: true,
+            task_ids: task_ids.iter().map(|task_id| task_id.to_string()).collect(),
         };
 
         Ok(tonic::Response::new(response))
     }
+
+    type LeaseTasksStream = ReceiverStream<Result<proto::LeasedTask, tonic::Status>>;
+
+    async fn lease_tasks(
+        &self,
+        request: tonic::Request<proto::LeaseTasksRequest>,
+    ) -> Result<tonic::Response<Self::LeaseTasksStream>, tonic::Status> {
+        let request = request.into_inner();
+        let partition_id = partition_id(request.partition)?;
+
+        let max_in_flight = usize::try_from(request.max_in_flight)
+            .ok()
+            .filter(|max_in_flight| *max_in_flight > 0)
+            .ok_or_else(|| {
+                tonic::Status::invalid_argument(format!(
+                    "invalid max_in_flight: {}",
+                    request.max_in_flight
+                ))
+            })?;
+
+        let (tx, rx) = mpsc::channel(max_in_flight);
+
+        tokio::spawn(task_lease::run_lease_stream(
+            self.task_queue.clone(),
+            self.leases.clone(),
+            request.queue_id,
+            partition_id,
+            max_in_flight,
+            lease_ms(request.lease_ms),
+            tx,
+        ));
+
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn ack_task(
+        &self,
+        request: tonic::Request<proto::AckTaskRequest>,
+    ) -> Result<tonic::Response<proto::AckTaskReply>, tonic::Status> {
+        let request = request.into_inner();
+        let task_id = task_id(&request.task_id)?;
+
+        let acked = self
+            .task_queue
+            .ack_task(&task_id, &request.lease_token, request.result.as_deref())
+            .await
+            .map_err(internal_error)?;
+
+        self.leases.remove(&task_id, &request.lease_token).await;
+
+        if !acked {
+            return Err(self.lease_not_held(&task_id, &request.lease_token).await);
+        }
+
+        Ok(tonic::Response::new(proto::AckTaskReply { success: true }))
+    }
+
+    async fn nack_task(
+        &self,
+        request: tonic::Request<proto::NackTaskRequest>,
+    ) -> Result<tonic::Response<proto::NackTaskReply>, tonic::Status> {
+        let request = request.into_inner();
+        let task_id = task_id(&request.task_id)?;
+
+        let nacked = self
+            .task_queue
+            .nack_task(&task_id, &request.lease_token, &request.error)
+            .await
+            .map_err(internal_error)?;
+
+        self.leases.remove(&task_id, &request.lease_token).await;
+
+        if !nacked {
+            return Err(self.lease_not_held(&task_id, &request.lease_token).await);
+        }
+
+        Ok(tonic::Response::new(proto::NackTaskReply { success: true }))
+    }
+
+    async fn heartbeat(
+        &self,
+        request: tonic::Request<proto::HeartbeatRequest>,
+    ) -> Result<tonic::Response<proto::HeartbeatReply>, tonic::Status> {
+        let request = request.into_inner();
+        let task_id = task_id(&request.task_id)?;
+        let progress = request.progress.map(TaskProgress::from);
+
+        if let Some(progress) = &progress {
+            progress
+                .validate()
+                .map_err(|err| tonic::Status::invalid_argument(err.to_string()))?;
+        }
+
+        let renewed = self
+            .task_queue
+            .heartbeat_task(
+                &task_id,
+                &request.lease_token,
+                lease_ms(request.lease_ms),
+                progress.as_ref(),
+            )
+            .await
+            .map_err(internal_error)?;
+
+        let Some(lease_expires_at) = renewed else {
+            return Err(self.lease_not_held(&task_id, &request.lease_token).await);
+        };
+
+        self.leases
+            .renew(&task_id, &request.lease_token, lease_expires_at)
+            .await;
+
+        Ok(tonic::Response::new(proto::HeartbeatReply {
+            success: true,
+            lease_expires_at,
+        }))
+    }
+
+    async fn cancel_task(
+        &self,
+        request: tonic::Request<proto::CancelTaskRequest>,
+    ) -> Result<tonic::Response<proto::CancelTaskReply>, tonic::Status> {
+        let request = request.into_inner();
+        let task_id = task_id(&request.task_id)?;
+
+        let cancelled = self
+            .task_queue
+            .cancel_task(&task_id)
+            .await
+            .map_err(internal_error)?;
+
+        if !cancelled {
+            return Err(tonic::Status::failed_precondition(format!(
+                "task is not waiting or running: {}",
+                task_id
+            )));
+        }
+
+        Ok(tonic::Response::new(proto::CancelTaskReply {
+            success: true,
+        }))
+    }
+
+    async fn reschedule_task(
+        &self,
+        request: tonic::Request<proto::RescheduleTaskRequest>,
+    ) -> Result<tonic::Response<proto::RescheduleTaskReply>, tonic::Status> {
+        let request = request.into_inner();
+        let task_id = task_id(&request.task_id)?;
+
+        let rescheduled = self
+            .task_queue
+            .reschedule_task(&task_id, request.scheduled_at)
+            .await
+            .map_err(internal_error)?;
+
+        if !rescheduled {
+            return Err(tonic::Status::failed_precondition(format!(
+                "task is not waiting: {}",
+                task_id
+            )));
+        }
+
+        Ok(tonic::Response::new(proto::RescheduleTaskReply {
+            success: true,
+        }))
+    }
+
+    async fn query_tasks(
+        &self,
+        request: tonic::Request<proto::QueryTasksRequest>,
+    ) -> Result<tonic::Response<proto::QueryTasksReply>, tonic::Status> {
+        let request = request.into_inner();
+        let partition_id = partition_id(request.partition)?;
+
+        let status = proto::TaskStatus::try_from(request.status).map_err(|_| {
+            tonic::Status::invalid_argument(format!("invalid status: {}", request.status))
+        })?;
+
+        let tasks = self
+            .task_queue
+            .query_tasks(
+                &request.queue_id,
+                partition_id,
+                status.into(),
+                query_limit(request.limit),
+            )
+            .await
+            .map_err(internal_error)?;
+
+        let response = proto::QueryTasksReply {
+            tasks: tasks.into_iter().map(proto::TaskInfo::from).collect(),
+        };
+
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn get_task(
+        &self,
+        request: tonic::Request<proto::GetTaskRequest>,
+    ) -> Result<tonic::Response<proto::GetTaskReply>, tonic::Status> {
+        let request = request.into_inner();
+        let task_id = task_id(&request.task_id)?;
+
+        let task = self
+            .task_queue
+            .get_task(&task_id)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| not_found(&task_id))?;
+
+        Ok(tonic::Response::new(proto::GetTaskReply {
+            task: Some(task.into()),
+        }))
+    }
+
+    type WaitForTaskStream = ReceiverStream<Result<proto::TaskInfo, tonic::Status>>;
+
+    async fn wait_for_task(
+        &self,
+        request: tonic::Request<proto::WaitForTaskRequest>,
+    ) -> Result<tonic::Response<Self::WaitForTaskStream>, tonic::Status> {
+        let request = request.into_inner();
+        let task_id = task_id(&request.task_id)?;
+
+        // Unknown tasks fail the call itself rather than the stream.
+        let task = self
+            .task_queue
+            .get_task(&task_id)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| not_found(&task_id))?;
+
+        let (tx, rx) = mpsc::channel(1);
+
+        tokio::spawn(task_wait::run_wait_stream(
+            self.task_queue.clone(),
+            task,
+            tx,
+        ));
+
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_task_graph(
+        &self,
+        request: tonic::Request<proto::GetTaskGraphRequest>,
+    ) -> Result<tonic::Response<proto::GetTaskGraphReply>, tonic::Status> {
+        let request = request.into_inner();
+        let task_id = task_id(&request.task_id)?;
+
+        let graph = self
+            .task_queue
+            .task_graph(&task_id)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| not_found(&task_id))?;
+
+        Ok(tonic::Response::new(proto::GetTaskGraphReply {
+            tasks: graph.tasks.into_iter().map(proto::TaskInfo::from).collect(),
+            dependencies: graph
+                .dependencies
+                .into_iter()
+                .map(proto::TaskDependency::from)
+                .collect(),
+        }))
+    }
+
+    async fn set_retry_policy(
+        &self,
+        request: tonic::Request<proto::SetRetryPolicyRequest>,
+    ) -> Result<tonic::Response<proto::SetRetryPolicyReply>, tonic::Status> {
+        let request = request.into_inner();
+
+        let policy: RetryPolicy = request
+            .policy
+            .ok_or_else(|| tonic::Status::invalid_argument("missing policy"))?
+            .into();
+
+        policy
+            .validate()
+            .map_err(|err| tonic::Status::invalid_argument(err.to_string()))?;
+
+        self.task_queue
+            .set_retry_policy(&request.queue_id, &policy)
+            .await
+            .map_err(internal_error)?;
+
+        Ok(tonic::Response::new(proto::SetRetryPolicyReply {
+            success: true,
+        }))
+    }
+
+    async fn get_retry_policy(
+        &self,
+        request: tonic::Request<proto::GetRetryPolicyRequest>,
+    ) -> Result<tonic::Response<proto::GetRetryPolicyReply>, tonic::Status> {
+        let request = request.into_inner();
+
+        let policy = self
+            .task_queue
+            .retry_policy(&request.queue_id)
+            .await
+            .map_err(internal_error)?;
+
+        Ok(tonic::Response::new(proto::GetRetryPolicyReply {
+            policy: Some(policy.into()),
+        }))
+    }
+
+    async fn register_task_definition(
+        &self,
+        request: tonic::Request<proto::RegisterTaskDefinitionRequest>,
+    ) -> Result<tonic::Response<proto::RegisterTaskDefinitionReply>, tonic::Status> {
+        let request = request.into_inner();
+
+        if request.definition_id.is_empty() {
+            return Err(tonic::Status::invalid_argument("definition_id is required"));
+        }
+
+        let parameters = normalize_parameters(&request.parameters)
+            .map_err(|err| tonic::Status::invalid_argument(format!("{:#}", err)))?;
+
+        let definition = NewTaskDefinition {
+            definition_id: &request.definition_id,
+            name: &request.name,
+            description: &request.description,
+            parameters: &parameters,
+        };
+
+        let registered = self
+            .task_queue
+            .register_task_definition(&definition)
+            .await
+            .map_err(internal_error)?;
+
+        tracing::info!(
+            definition_id = %registered.definition_id,
+            version = registered.version,
+            "task_definition_registered"
+        );
+
+        Ok(tonic::Response::new(proto::RegisterTaskDefinitionReply {
+            definition: Some(registered.into()),
+        }))
+    }
+
+    async fn get_task_definition(
+        &self,
+        request: tonic::Request<proto::GetTaskDefinitionRequest>,
+    ) -> Result<tonic::Response<proto::GetTaskDefinitionReply>, tonic::Status> {
+        let request = request.into_inner();
+
+        let definition = self
+            .task_queue
+            .get_task_definition(&request.definition_id, request.version)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| definition_not_found(&request.definition_id, request.version))?;
+
+        Ok(tonic::Response::new(proto::GetTaskDefinitionReply {
+            definition: Some(definition.into()),
+        }))
+    }
+
+    async fn list_task_definitions(
+        &self,
+        _request: tonic::Request<proto::ListTaskDefinitionsRequest>,
+    ) -> Result<tonic::Response<proto::ListTaskDefinitionsReply>, tonic::Status> {
+        let definitions = self
+            .task_queue
+            .list_task_definitions()
+            .await
+            .map_err(internal_error)?;
+
+        Ok(tonic::Response::new(proto::ListTaskDefinitionsReply {
+            definitions: definitions
+                .into_iter()
+                .map(proto::TaskDefinition::from)
+                .collect(),
+        }))
+    }
+}
+
+pub(super) fn task_id(task_id: &str) -> Result<TaskId, tonic::Status> {
+    task_id
+        .parse()
+        .map_err(|err: anyhow::Error| tonic::Status::invalid_argument(err.to_string()))
+}
+
+pub(super) fn not_found(task_id: &TaskId) -> tonic::Status {
+    tonic::Status::not_found(format!("task not found: {}", task_id))
+}
+
+fn definition_not_found(definition_id: &str, version: Option<i32>) -> tonic::Status {
+    match version {
+        Some(version) => tonic::Status::not_found(format!(
+            "task definition not found: {} version {}",
+            definition_id, version
+        )),
+        None => tonic::Status::not_found(format!("task definition not found: {}", definition_id)),
+    }
+}
+
+fn not_leased(task_id: &TaskId) -> tonic::Status {
+    tonic::Status::failed_precondition(format!("lease is not held: {}", task_id))
+}
+
+pub(super) fn partition_id(partition: i32) -> Result<i16, tonic::Status> {
+    i16::try_from(partition)
+        .map_err(|_| tonic::Status::invalid_argument(format!("invalid partition: {}", partition)))
+}
+
+fn priority(priority: i32) -> Result<i16, tonic::Status> {
+    i16::try_from(priority)
+        .map_err(|_| tonic::Status::invalid_argument(format!("invalid priority: {}", priority)))
+}
+
+fn max_attempts(max_attempts: Option<i32>) -> Result<Option<i32>, tonic::Status> {
+    match max_attempts {
+        Some(max_attempts) if max_attempts < 1 => Err(tonic::Status::invalid_argument(format!(
+            "max_attempts must be at least 1: {}",
+            max_attempts
+        ))),
+        max_attempts => Ok(max_attempts),
+    }
+}
+
+/// A task's parents as indexes into its batch, which have to come before it.
+fn depends_on(index: usize, depends_on: &[u32]) -> Result<Vec<usize>, tonic::Status> {
+    depends_on
+        .iter()
+        .map(|parent| {
+            usize::try_from(*parent)
+                .ok()
+                .filter(|parent| *parent < index)
+                .ok_or_else(|| {
+                    tonic::Status::invalid_argument(format!(
+                        "task {} can only depend on tasks before it: {}",
+                        index, parent
+                    ))
+                })
+        })
+        .collect()
+}
+
+fn parent_failure_policy(policy: i32) -> Result<ParentFailurePolicy, tonic::Status> {
+    proto::ParentFailurePolicy::try_from(policy)
+        .map(Into::into)
+        .map_err(|_| {
+            tonic::Status::invalid_argument(format!("invalid parent failure policy: {}", policy))
+        })
+}
+
+/// Proto3 has no presence for scalars, so a zero timeout means "no timeout".
+fn timeout_ms(timeout_ms: i64) -> Option<i64> {
+    (timeout_ms > 0).then_some(timeout_ms)
+}
+
+fn lease_ms(lease_ms: i64) -> i64 {
+    if lease_ms > 0 {
+        lease_ms
+    } else {
+        task_lease::DEFAULT_LEASE_MS
+    }
+}
+
+pub(super) fn query_limit(limit: i64) -> i64 {
+    if limit > 0 {
+        limit.min(MAX_QUERY_LIMIT)
+    } else {
+        DEFAULT_QUERY_LIMIT
+    }
+}
+
+pub(super) fn internal_error(err: anyhow::Error) -> tonic::Status {
+    tracing::error!(err = ?err, "task_queue_error");
+    tonic::Status::internal(err.to_string())
 }
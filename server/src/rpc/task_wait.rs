@@ -0,0 +1,61 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::mpsc;
+
+use super::{proto, task_service::not_found};
+use crate::persistence::common::{TaskData, TaskQueue, TaskStatus};
+
+/// How often a wait stream checks whether its task has changed.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub type WaitSender = mpsc::Sender<Result<proto::TaskInfo, tonic::Status>>;
+
+/// Sends `task` to `tx`, then again whenever its status or attempts change, until it has
+/// finished or been dead-lettered or the receiver goes away.
+pub async fn run_wait_stream<Q>(task_queue: Arc<Q>, mut task: TaskData, tx: WaitSender)
+where
+    Q: TaskQueue + Send + Sync + 'static,
+{
+    let task_id = task.task_id.clone();
+
+    loop {
+        let done = is_settled(task.status);
+        let seen = (task.status, task.attempts);
+
+        if tx.send(Ok(task.into())).await.is_err() || done {
+            return;
+        }
+
+        task = loop {
+            tokio::select! {
+                _ = tx.closed() => return,
+                _ = tokio::time::sleep(WAIT_POLL_INTERVAL) => {}
+            }
+
+            let current = match task_queue.get_task(&task_id).await {
+                Ok(Some(current)) => current,
+                Ok(None) => {
+                    // Purged while being waited on.
+                    tx.send(Err(not_found(&task_id))).await.ok();
+                    return;
+                }
+                Err(err) => {
+                    tracing::error!(err = ?err, task_id = %task_id, "wait_for_task_failed");
+                    tx.send(Err(tonic::Status::internal(err.to_string())))
+                        .await
+                        .ok();
+                    return;
+                }
+            };
+
+            if (current.status, current.attempts) != seen {
+                break current;
+            }
+        };
+    }
+}
+
+/// Whether a task will stay as it is without someone stepping in.
+fn is_settled(status: TaskStatus) -> bool {
+    status.is_terminal() || status == TaskStatus::DeadLettered
+}
@@ -0,0 +1,236 @@
+use std::sync::Arc;
+
+use super::proto::{self, workflows_server::Workflows};
+use super::task_service::{internal_error, partition_id};
+use crate::persistence::common::{
+    check_workflow_events, NewWorkflow, NewWorkflowEvent, TaskQueue, Workflow, WorkflowState,
+};
+
+pub struct WorkflowService<Q> {
+    task_queue: Arc<Q>,
+}
+
+impl<Q> WorkflowService<Q> {
+    pub fn new(task_queue: Arc<Q>) -> Self {
+        Self { task_queue }
+    }
+}
+
+impl<Q> WorkflowService<Q>
+where
+    Q: TaskQueue + Send + Sync + 'static,
+{
+    async fn workflow(&self, workflow_id: &str) -> Result<Workflow, tonic::Status> {
+        self.task_queue
+            .get_workflow(workflow_id)
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| workflow_not_found(workflow_id))
+    }
+}
+
+#[tonic::async_trait]
+impl<Q> Workflows for WorkflowService<Q>
+where
+    Q: TaskQueue + Send + Sync + 'static,
+{
+    async fn start_workflow(
+        &self,
+        request: tonic::Request<proto::StartWorkflowRequest>,
+    ) -> Result<tonic::Response<proto::StartWorkflowReply>, tonic::Status> {
+        let request = request.into_inner();
+
+        if request.workflow_id.is_empty() {
+            return Err(tonic::Status::invalid_argument("workflow_id is required"));
+        }
+
+        if request.workflow_type.is_empty() {
+            return Err(tonic::Status::invalid_argument("workflow_type is required"));
+        }
+
+        let workflow = NewWorkflow {
+            workflow_id: &request.workflow_id,
+            workflow_type: &request.workflow_type,
+            queue_id: &request.queue_id,
+            partition_id: partition_id(request.partition)?,
+            input: &request.input,
+        };
+
+        let started = self
+            .task_queue
+            .start_workflow(&workflow)
+            .await
+            .map_err(internal_error)?;
+
+        if !started {
+            return Err(tonic::Status::already_exists(format!(
+                "workflow already exists: {}",
+                request.workflow_id
+            )));
+        }
+
+        tracing::info!(workflow_id = %request.workflow_id, "workflow_started");
+
+        Ok(tonic::Response::new(proto::StartWorkflowReply {
+            success: true,
+        }))
+    }
+
+    async fn get_workflow(
+        &self,
+        request: tonic::Request<proto::GetWorkflowRequest>,
+    ) -> Result<tonic::Response<proto::GetWorkflowReply>, tonic::Status> {
+        let request = request.into_inner();
+        let workflow = self.workflow(&request.workflow_id).await?;
+
+        Ok(tonic::Response::new(proto::GetWorkflowReply {
+            workflow: Some(workflow.into()),
+        }))
+    }
+
+    async fn get_workflow_history(
+        &self,
+        request: tonic::Request<proto::GetWorkflowHistoryRequest>,
+    ) -> Result<tonic::Response<proto::GetWorkflowHistoryReply>, tonic::Status> {
+        let request = request.into_inner();
+        let workflow = self.workflow(&request.workflow_id).await?;
+
+        let events = self
+            .task_queue
+            .workflow_history(&request.workflow_id)
+            .await
+            .map_err(internal_error)?;
+
+        Ok(tonic::Response::new(proto::GetWorkflowHistoryReply {
+            workflow: Some(workflow.into()),
+            events: events.into_iter().map(proto::WorkflowEvent::from).collect(),
+        }))
+    }
+
+    async fn append_workflow_events(
+        &self,
+        request: tonic::Request<proto::AppendWorkflowEventsRequest>,
+    ) -> Result<tonic::Response<proto::AppendWorkflowEventsReply>, tonic::Status> {
+        let request = request.into_inner();
+
+        let events = request
+            .events
+            .into_iter()
+            .map(new_workflow_event)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        check_workflow_events(&events)
+            .map_err(|err| tonic::Status::invalid_argument(err.to_string()))?;
+
+        let appended = self
+            .task_queue
+            .append_workflow_events(&request.workflow_id, request.last_event_id, &events)
+            .await
+            .map_err(internal_error)?;
+
+        let Some(last_event_id) = appended else {
+            // Tells a missing workflow apart from one that moved on.
+            self.workflow(&request.workflow_id).await?;
+
+            return Err(tonic::Status::failed_precondition(format!(
+                "workflow history has moved on or the workflow has finished: {}",
+                request.workflow_id
+            )));
+        };
+
+        Ok(tonic::Response::new(proto::AppendWorkflowEventsReply {
+            success: true,
+            last_event_id,
+        }))
+    }
+
+    async fn signal_workflow(
+        &self,
+        request: tonic::Request<proto::SignalWorkflowRequest>,
+    ) -> Result<tonic::Response<proto::SignalWorkflowReply>, tonic::Status> {
+        let request = request.into_inner();
+
+        if request.name.is_empty() {
+            return Err(tonic::Status::invalid_argument("name is required"));
+        }
+
+        let signalled = self
+            .task_queue
+            .signal_workflow(&request.workflow_id, &request.name, &request.payload)
+            .await
+            .map_err(internal_error)?;
+
+        let Some(last_event_id) = signalled else {
+            self.workflow(&request.workflow_id).await?;
+
+            return Err(tonic::Status::failed_precondition(format!(
+                "workflow has finished: {}",
+                request.workflow_id
+            )));
+        };
+
+        tracing::info!(
+            workflow_id = %request.workflow_id,
+            signal = %request.name,
+            "workflow_signalled"
+        );
+
+        Ok(tonic::Response::new(proto::SignalWorkflowReply {
+            success: true,
+            last_event_id,
+        }))
+    }
+
+    async fn query_workflow(
+        &self,
+        request: tonic::Request<proto::QueryWorkflowRequest>,
+    ) -> Result<tonic::Response<proto::QueryWorkflowReply>, tonic::Status> {
+        let request = request.into_inner();
+        let workflow = self.workflow(&request.workflow_id).await?;
+
+        let history = self
+            .task_queue
+            .workflow_history(&request.workflow_id)
+            .await
+            .map_err(internal_error)?;
+
+        let state = WorkflowState::new(workflow, history);
+
+        Ok(tonic::Response::new(proto::QueryWorkflowReply {
+            workflow: Some(state.workflow.into()),
+            steps: state
+                .steps
+                .into_iter()
+                .map(proto::WorkflowEvent::from)
+                .collect(),
+            timers: state
+                .timers
+                .into_iter()
+                .map(proto::WorkflowTimer::from)
+                .collect(),
+            signals: state
+                .signals
+                .into_iter()
+                .map(proto::WorkflowEvent::from)
+                .collect(),
+        }))
+    }
+}
+
+fn new_workflow_event(event: proto::WorkflowEvent) -> Result<NewWorkflowEvent, tonic::Status> {
+    let kind = proto::WorkflowEventKind::try_from(event.kind).map_err(|_| {
+        tonic::Status::invalid_argument(format!("invalid workflow event kind: {}", event.kind))
+    })?;
+
+    Ok(NewWorkflowEvent {
+        kind: kind.into(),
+        name: event.name,
+        payload: event.payload,
+        error: event.error,
+        fire_at: event.fire_at,
+    })
+}
+
+fn workflow_not_found(workflow_id: &str) -> tonic::Status {
+    tonic::Status::not_found(format!("workflow not found: {}", workflow_id))
+}
@@ -0,0 +1,258 @@
+//! Fires recurring task schedules and workflow timers.
+//!
+//! Every node pages through the due schedules but only fires those the [`PartitionResolver`]
+//! places on it. Runs are enqueued with an idempotency key per fire time before the schedule is
+//! advanced with a compare-and-set on `next_fire_at`, so a run is enqueued once even if the
+//! owner changes mid-fire or a node dies in between.
+//!
+//! Workflow timers are placed by workflow id. Firing one removes it in the same transaction
+//! that records it in the workflow's history, so it fires once however many nodes try.
+
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::TimeZone;
+use chrono_tz::Tz;
+use tokio::task::JoinHandle;
+
+use crate::{
+    partition_resolver::PartitionResolver,
+    persistence::common::{now_millis, MisfirePolicy, NewTask, Schedule, TaskQueue},
+};
+
+const SCHEDULER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many due schedules each page of a poll holds, across all nodes.
+const DUE_SCHEDULES_PER_PAGE: i64 = 1_000;
+
+/// How many due workflow timers each page of a poll holds, across all nodes.
+const DUE_TIMERS_PER_PAGE: i64 = 1_000;
+
+/// Caps the runs one fire enqueues, a schedule further behind catches up over later polls.
+pub const MAX_CATCH_UP_RUNS: usize = 100;
+
+/// The `next_fire_at` of a schedule whose expression will not fire again.
+pub const NEVER: i64 = i64::MAX;
+
+/// A schedule's cron expression, evaluated in its time zone.
+pub struct CronSchedule {
+    schedule: cron::Schedule,
+    timezone: Tz,
+}
+
+impl CronSchedule {
+    /// Parses `cron` in the standard five field form, or with a leading seconds field.
+    pub fn parse(cron: &str, timezone: &str) -> Result<Self> {
+        // Five field expressions fire on the minute.
+        let expression = if cron.split_whitespace().count() == 5 {
+            format!("0 {}", cron)
+        } else {
+            cron.to_string()
+        };
+
+        let schedule = cron::Schedule::from_str(&expression)
+            .map_err(|err| anyhow::anyhow!("invalid cron expression {:?}: {}", cron, err))?;
+
+        let timezone = Tz::from_str(timezone)
+            .map_err(|timezone| anyhow::anyhow!("unknown time zone: {}", timezone))?;
+
+        Ok(Self { schedule, timezone })
+    }
+
+    /// The first fire time after `after`, both in unix millis.
+    pub fn next_after(&self, after: i64) -> Option<i64> {
+        let after = self.timezone.timestamp_millis_opt(after).single()?;
+
+        self.schedule
+            .after(&after)
+            .next()
+            .map(|fire_at| fire_at.timestamp_millis())
+    }
+}
+
+/// The runs of `schedule` that are due at `now` under its misfire policy, and when it fires
+/// after them.
+pub fn due_runs(cron: &CronSchedule, schedule: &Schedule, now: i64) -> (Vec<i64>, i64) {
+    let mut runs = vec![schedule.next_fire_at];
+    let mut next = cron.next_after(schedule.next_fire_at);
+
+    while let Some(fire_at) = next.filter(|fire_at| *fire_at <= now) {
+        match schedule.misfire_policy {
+            MisfirePolicy::CatchUp if runs.len() >= MAX_CATCH_UP_RUNS => break,
+            MisfirePolicy::CatchUp => runs.push(fire_at),
+            MisfirePolicy::Skip => runs = vec![fire_at],
+        }
+
+        next = cron.next_after(fire_at);
+    }
+
+    (runs, next.unwrap_or(NEVER))
+}
+
+/// Enqueues the runs of `schedule` due at `now` and advances it past them. Returns how many
+/// runs were enqueued, `0` if the schedule was fired by someone else first.
+pub async fn fire_schedule<Q: TaskQueue + Sync>(
+    task_queue: &Q,
+    schedule: &Schedule,
+    now: i64,
+) -> Result<usize> {
+    let cron = CronSchedule::parse(&schedule.cron, &schedule.timezone)?;
+    let (runs, next_fire_at) = due_runs(&cron, schedule, now);
+
+    let keys: Vec<String> = runs
+        .iter()
+        .map(|fire_at| run_key(&schedule.schedule_id, *fire_at))
+        .collect();
+
+    let tasks = runs
+        .iter()
+        .zip(&keys)
+        .map(|(fire_at, key)| NewTask {
+            content_type: schedule.content_type.as_deref(),
+            scheduled_at: *fire_at,
+            idempotency_key: Some(key),
+            ..NewTask::new(&schedule.payload)
+        })
+        .collect();
+
+    // Enqueueing first means a fire cut short is redone under the same keys, not lost.
+    task_queue
+        .enqueue_tasks(&schedule.queue_id, schedule.partition_id, tasks)
+        .await?;
+
+    let advanced = task_queue
+        .advance_schedule(&schedule.schedule_id, schedule.next_fire_at, next_fire_at)
+        .await?;
+
+    Ok(if advanced { runs.len() } else { 0 })
+}
+
+/// The idempotency key of the run of `schedule_id` at `fire_at`.
+fn run_key(schedule_id: &str, fire_at: i64) -> String {
+    format!("svppl-schedule:{}:{}", schedule_id, fire_at)
+}
+
+/// Fires the schedules due at `now` that are placed on this node. Pages through every due
+/// schedule, so those placed elsewhere can not crowd out local ones.
+pub async fn fire_local_schedules<Q: TaskQueue + Sync>(
+    task_queue: &Q,
+    partition_resolver: &PartitionResolver,
+    now: i64,
+) -> Result<()> {
+    let mut after = None;
+
+    loop {
+        let page = task_queue
+            .due_schedules(now, after.as_ref(), DUE_SCHEDULES_PER_PAGE)
+            .await?;
+
+        for schedule in &page {
+            if !partition_resolver
+                .is_local(schedule.schedule_id.as_bytes())
+                .await
+            {
+                continue;
+            }
+
+            match fire_schedule(task_queue, schedule, now).await {
+                Ok(runs) => {
+                    tracing::info!(schedule_id = %schedule.schedule_id, runs, "schedule_fired");
+                }
+                Err(err) => {
+                    tracing::error!(err = ?err, schedule_id = %schedule.schedule_id, "schedule_fire_failed");
+                }
+            }
+        }
+
+        if (page.len() as i64) < DUE_SCHEDULES_PER_PAGE {
+            return Ok(());
+        }
+
+        after = page.last().cloned();
+    }
+}
+
+/// Fires the workflow timers due at `now` that are placed on this node, paging through every
+/// due timer like [`fire_local_schedules`].
+pub async fn fire_local_timers<Q: TaskQueue + Sync>(
+    task_queue: &Q,
+    partition_resolver: &PartitionResolver,
+    now: i64,
+) -> Result<()> {
+    let mut after = None;
+
+    loop {
+        let page = task_queue
+            .due_workflow_timers(now, after.as_ref(), DUE_TIMERS_PER_PAGE)
+            .await?;
+
+        for timer in &page {
+            if !partition_resolver
+                .is_local(timer.workflow_id.as_bytes())
+                .await
+            {
+                continue;
+            }
+
+            match task_queue.fire_workflow_timer(timer).await {
+                Ok(fired) => {
+                    tracing::info!(workflow_id = %timer.workflow_id, timer = %timer.name, fired, "workflow_timer_fired");
+                }
+                Err(err) => {
+                    tracing::error!(err = ?err, workflow_id = %timer.workflow_id, "workflow_timer_fire_failed");
+                }
+            }
+        }
+
+        if (page.len() as i64) < DUE_TIMERS_PER_PAGE {
+            return Ok(());
+        }
+
+        after = page.last().cloned();
+    }
+}
+
+pub struct SchedulerHandle {
+    shutdown_tx: tokio::sync::oneshot::Sender<()>,
+    join_handle: JoinHandle<()>,
+}
+
+impl SchedulerHandle {
+    pub async fn shutdown(self) -> anyhow::Result<()> {
+        self.shutdown_tx.send(()).ok();
+        self.join_handle.await.ok();
+
+        Ok(())
+    }
+}
+
+pub fn start<Q>(task_queue: Arc<Q>, partition_resolver: PartitionResolver) -> SchedulerHandle
+where
+    Q: TaskQueue + Send + Sync + 'static,
+{
+    let (shutdown_tx, mut shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+
+    let join_handle = tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = &mut shutdown_rx => break,
+                _ = tokio::time::sleep(SCHEDULER_POLL_INTERVAL) => {}
+            }
+
+            let now = now_millis();
+
+            if let Err(err) = fire_local_schedules(&*task_queue, &partition_resolver, now).await {
+                tracing::error!(err = ?err, "schedules_poll_failed");
+            }
+
+            if let Err(err) = fire_local_timers(&*task_queue, &partition_resolver, now).await {
+                tracing::error!(err = ?err, "workflow_timers_poll_failed");
+            }
+        }
+    });
+
+    SchedulerHandle {
+        shutdown_tx,
+        join_handle,
+    }
+}
@@ -0,0 +1,133 @@
+//! Checks task payloads against their registered task definitions.
+//!
+//! A definition's parameters are a JSON Schema, as `js/lib.ts` builds them with TypeBox. Tasks
+//! scheduled against a definition need a JSON payload that matches it, or they are rejected
+//! with every part of the payload that does not.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use anyhow::{Context, Result};
+use jsonschema::JSONSchema;
+use serde_json::Value;
+
+use crate::persistence::common::TaskDefinition;
+
+/// Checks that `parameters` is a JSON Schema and returns it the way it is stored, so the same
+/// schema registered again is recognised however it is formatted.
+pub fn normalize_parameters(parameters: &str) -> Result<String> {
+    let schema: Value = serde_json::from_str(parameters).context("parameters are not JSON")?;
+    compile(&schema)?;
+
+    Ok(schema.to_string())
+}
+
+fn compile(schema: &Value) -> Result<JSONSchema> {
+    JSONSchema::compile(schema)
+        .map_err(|err| anyhow::anyhow!("parameters are not a valid JSON Schema: {}", err))
+}
+
+/// A part of a payload that does not match its task definition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadViolation {
+    /// Where in the payload, as a JSON pointer. Empty for the payload as a whole.
+    pub pointer: String,
+    pub description: String,
+}
+
+impl fmt::Display for PayloadViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "payload{}: {}", self.pointer, self.description)
+    }
+}
+
+/// Why a payload was rejected by its task definition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidPayload {
+    pub definition_id: String,
+    pub version: i32,
+    /// A violation per part of the payload that does not match.
+    pub violations: Vec<PayloadViolation>,
+}
+
+impl fmt::Display for InvalidPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "payload does not match task definition {} version {}",
+            self.definition_id, self.version
+        )?;
+
+        for (i, violation) in self.violations.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { "; " };
+            write!(f, "{}{}", separator, violation)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidPayload {}
+
+/// Checks payloads, compiling the schema of every definition version once. Versions never
+/// change, so their compiled schemas are kept for as long as the validator.
+#[derive(Default)]
+pub struct PayloadValidator {
+    schemas: Mutex<HashMap<(String, i32), Arc<JSONSchema>>>,
+}
+
+impl PayloadValidator {
+    /// Fails with an [`InvalidPayload`] if `payload` is not JSON matching the definition's
+    /// parameters.
+    pub fn check(&self, definition: &TaskDefinition, payload: &[u8]) -> Result<()> {
+        let schema = self.schema(definition)?;
+
+        let violations = match serde_json::from_slice::<Value>(payload) {
+            Ok(instance) => match schema.validate(&instance) {
+                Ok(()) => return Ok(()),
+                Err(errors) => errors
+                    .map(|err| PayloadViolation {
+                        pointer: err.instance_path.to_string(),
+                        description: err.to_string(),
+                    })
+                    .collect(),
+            },
+            Err(err) => vec![PayloadViolation {
+                pointer: String::new(),
+                description: format!("not JSON: {}", err),
+            }],
+        };
+
+        Err(InvalidPayload {
+            definition_id: definition.definition_id.clone(),
+            version: definition.version,
+            violations,
+        }
+        .into())
+    }
+
+    fn schema(&self, definition: &TaskDefinition) -> Result<Arc<JSONSchema>> {
+        let key = (definition.definition_id.clone(), definition.version);
+
+        if let Some(schema) = self.schemas().get(&key) {
+            return Ok(schema.clone());
+        }
+
+        let parameters: Value = serde_json::from_str(&definition.parameters)?;
+        let schema = Arc::new(compile(&parameters)?);
+
+        self.schemas().insert(key, schema.clone());
+
+        Ok(schema)
+    }
+
+    fn schemas(&self) -> MutexGuard<'_, HashMap<(String, i32), Arc<JSONSchema>>> {
+        // Nothing panics while holding the lock, but a poisoned cache is still usable.
+        self.schemas
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,520 @@
+//! Replays durable workflows.
+//!
+//! A workflow is a function that is run again from the top every time a workflow task for it
+//! is leased, against the history recorded so far. Steps that already ran return their
+//! recorded outcome instead of running again; the first step without one runs, its outcome is
+//! appended to the history and the replay stops there. Appending enqueues the next workflow
+//! task, so the workflow carries on from the stored history on whichever node picks it up.
+//!
+//! Sleeping sets a timer instead of holding a worker. Timers are stored with the workflow and
+//! fired by the [`scheduler`](crate::scheduler), which records them in the history and
+//! enqueues the next workflow task. Waiting for a signal works the same way, the signal is
+//! recorded in the history when it is sent and wakes the workflow blocked on it, as does a
+//! child workflow finishing for the parent waiting on it.
+//!
+//! Steps can register a compensation that undoes them. When the workflow fails, the
+//! compensations of the steps it completed run last first, each recorded in the history like
+//! a step, before the failure itself is recorded.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+
+use crate::persistence::common::{
+    child_workflow_id, now_millis, Heartbeat, NewWorkflowEvent, TaskData, TaskPayload,
+    TaskProcessor, TaskQueue, WorkflowEvent, WorkflowEventKind, WorkflowStatus,
+};
+
+/// Returned by [`WorkflowContext::step`] once the replay has to stop, to be passed up
+/// through the workflow function with `?`.
+#[derive(Debug)]
+pub struct Suspended;
+
+impl fmt::Display for Suspended {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "workflow suspended")
+    }
+}
+
+impl std::error::Error for Suspended {}
+
+/// Undoes a completed step while its workflow is failing.
+type Compensation = Box<dyn FnOnce() -> BoxFuture<'static, Result<()>> + Send>;
+
+/// What a workflow function sees of its workflow during a replay.
+pub struct WorkflowContext {
+    workflow_id: String,
+    input: TaskPayload,
+    /// The recorded step outcomes, timers and awaited signals, in the order the workflow
+    /// reached them.
+    steps: Vec<WorkflowEvent>,
+    next_step: usize,
+    /// The names of the timers that have fired.
+    fired: HashSet<String>,
+    /// The signals received and yet to be waited for, by name.
+    signals: HashMap<String, VecDeque<WorkflowEvent>>,
+    /// The events recording how child workflows finished, by child workflow id.
+    children: HashMap<String, WorkflowEvent>,
+    /// The compensations of the steps completed so far, in the order they were registered.
+    compensations: Vec<(String, Compensation)>,
+    /// The recorded compensation outcomes, in the order they ran.
+    compensated: Vec<WorkflowEvent>,
+    /// The event recording the step that ran, the timer that was set or the signal that was
+    /// awaited during this replay.
+    recorded: Option<NewWorkflowEvent>,
+    /// Whether the replay reached a timer or signal that has yet to arrive.
+    asleep: bool,
+    nondeterminism: Option<String>,
+}
+
+impl WorkflowContext {
+    /// Sets up a replay of the history of `workflow_id`, which starts with the workflow's
+    /// [`WorkflowEventKind::WorkflowStarted`] event.
+    pub fn new(workflow_id: &str, history: &[WorkflowEvent]) -> Result<Self> {
+        let input = history
+            .first()
+            .filter(|event| event.kind == WorkflowEventKind::WorkflowStarted)
+            .ok_or_else(|| anyhow::anyhow!("workflow history does not start with its input"))?
+            .payload
+            .clone()
+            .unwrap_or_default();
+
+        let steps = history
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event.kind,
+                    WorkflowEventKind::StepCompleted
+                        | WorkflowEventKind::StepFailed
+                        | WorkflowEventKind::TimerStarted
+                        | WorkflowEventKind::SignalAwaited
+                        | WorkflowEventKind::ChildWorkflowStarted
+                )
+            })
+            .cloned()
+            .collect();
+
+        let fired = history
+            .iter()
+            .filter(|event| event.kind == WorkflowEventKind::TimerFired)
+            .filter_map(|event| event.name.clone())
+            .collect();
+
+        let mut signals: HashMap<String, VecDeque<WorkflowEvent>> = HashMap::new();
+
+        for event in history
+            .iter()
+            .filter(|event| event.kind == WorkflowEventKind::SignalReceived)
+        {
+            let name = event.name.clone().unwrap_or_default();
+            signals.entry(name).or_default().push_back(event.clone());
+        }
+
+        let children = history
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event.kind,
+                    WorkflowEventKind::ChildWorkflowCompleted
+                        | WorkflowEventKind::ChildWorkflowFailed
+                )
+            })
+            .filter_map(|event| Some((event.name.clone()?, event.clone())))
+            .collect();
+
+        let compensated = history
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event.kind,
+                    WorkflowEventKind::CompensationCompleted
+                        | WorkflowEventKind::CompensationFailed
+                )
+            })
+            .cloned()
+            .collect();
+
+        Ok(Self {
+            workflow_id: workflow_id.to_string(),
+            input,
+            steps,
+            next_step: 0,
+            fired,
+            signals,
+            children,
+            compensations: Vec::new(),
+            compensated,
+            recorded: None,
+            asleep: false,
+            nondeterminism: None,
+        })
+    }
+
+    pub fn input(&self) -> &[u8] {
+        &self.input
+    }
+
+    /// Runs the step named `name`, or returns its recorded outcome if it already ran. A step
+    /// that runs is recorded and [`Suspended`] returned, the workflow continues from the next
+    /// replay.
+    pub async fn step<F, Fut>(&mut self, name: &str, run: F) -> Result<TaskPayload>
+    where
+        F: FnOnce() -> Fut + Send,
+        Fut: Future<Output = Result<TaskPayload>> + Send,
+    {
+        if let Some(event) = self.next_recorded(name, WorkflowEventKind::StepCompleted)? {
+            return match event.kind {
+                WorkflowEventKind::StepFailed => {
+                    Err(anyhow::anyhow!(event.error.unwrap_or_default()))
+                }
+                _ => Ok(event.payload.unwrap_or_default()),
+            };
+        }
+
+        self.recorded = Some(match run().await {
+            Ok(result) => NewWorkflowEvent::step_completed(name, result),
+            Err(err) => NewWorkflowEvent::step_failed(name, &err.to_string()),
+        });
+
+        Err(Suspended.into())
+    }
+
+    /// Runs the step like [`step`](Self::step) and, once it has completed, registers
+    /// `compensate` to undo it with its result should the workflow fail later on.
+    pub async fn step_with_compensation<F, Fut, C, CFut>(
+        &mut self,
+        name: &str,
+        run: F,
+        compensate: C,
+    ) -> Result<TaskPayload>
+    where
+        F: FnOnce() -> Fut + Send,
+        Fut: Future<Output = Result<TaskPayload>> + Send,
+        C: FnOnce(TaskPayload) -> CFut + Send + 'static,
+        CFut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let result = self.step(name, run).await?;
+        let completed = result.clone();
+
+        self.compensations.push((
+            name.to_string(),
+            Box::new(move || Box::pin(compensate(completed))),
+        ));
+
+        Ok(result)
+    }
+
+    /// Starts a child workflow of `workflow_type` on this workflow's queue and returns its id,
+    /// to wait on with [`wait_for_child`](Self::wait_for_child).
+    pub async fn start_child(&mut self, workflow_type: &str, input: TaskPayload) -> Result<String> {
+        if let Some(event) =
+            self.next_recorded(workflow_type, WorkflowEventKind::ChildWorkflowStarted)?
+        {
+            return Ok(child_workflow_id(&self.workflow_id, event.event_id));
+        }
+
+        self.recorded = Some(NewWorkflowEvent::child_workflow_started(
+            workflow_type,
+            input,
+        ));
+
+        Err(Suspended.into())
+    }
+
+    /// Waits for the child workflow `workflow_id` to finish and returns its result, or fails
+    /// with its error. Until it finishes the workflow is suspended, blocked on the child.
+    pub async fn wait_for_child(&mut self, workflow_id: &str) -> Result<TaskPayload> {
+        self.check_suspended()?;
+
+        let Some(event) = self.children.get(workflow_id) else {
+            self.asleep = true;
+
+            return Err(Suspended.into());
+        };
+
+        match event.kind {
+            WorkflowEventKind::ChildWorkflowFailed => {
+                Err(anyhow::anyhow!(event.error.clone().unwrap_or_default()))
+            }
+            _ => Ok(event.payload.clone().unwrap_or_default()),
+        }
+    }
+
+    /// Starts a child workflow and waits for its result.
+    pub async fn child_workflow(
+        &mut self,
+        workflow_type: &str,
+        input: TaskPayload,
+    ) -> Result<TaskPayload> {
+        let workflow_id = self.start_child(workflow_type, input).await?;
+
+        self.wait_for_child(&workflow_id).await
+    }
+
+    /// Sleeps for `duration`, measured from the replay that first reaches the sleep.
+    pub async fn sleep(&mut self, duration: Duration) -> Result<()> {
+        self.sleep_until(now_millis() + duration.as_millis() as i64)
+            .await
+    }
+
+    /// Sleeps until `fire_at`, in unix millis. The workflow is suspended with a timer set, and
+    /// replayed past the sleep once the server fires it.
+    pub async fn sleep_until(&mut self, fire_at: i64) -> Result<()> {
+        // Timers are named after their position, which replays reach in the same order.
+        let name = format!("sleep:{}", self.next_step + 1);
+
+        if self
+            .next_recorded(&name, WorkflowEventKind::TimerStarted)?
+            .is_some()
+        {
+            if self.fired.contains(&name) {
+                return Ok(());
+            }
+
+            self.asleep = true;
+
+            return Err(Suspended.into());
+        }
+
+        self.recorded = Some(NewWorkflowEvent::timer_started(&name, fire_at));
+
+        Err(Suspended.into())
+    }
+
+    /// Waits for the next signal named `name` and returns its payload. Signals are taken in
+    /// the order they were sent, and one sent before the workflow waits for it is kept until
+    /// it does. Until it arrives the workflow is suspended, blocked on the signal.
+    pub async fn wait_for_signal(&mut self, name: &str) -> Result<TaskPayload> {
+        self.check_suspended()?;
+
+        let signal = self
+            .signals
+            .get(name)
+            .and_then(|signals| signals.front())
+            .map(|signal| signal.event_id);
+
+        // A replay only blocks on a signal that has yet to be sent, so a recorded wait is this
+        // one unless the signal it would take was sent before the wait was recorded.
+        let awaited = self.steps.get(self.next_step).is_some_and(|event| {
+            event.kind == WorkflowEventKind::SignalAwaited
+                && event.name.as_deref() == Some(name)
+                && signal.map_or(true, |event_id| event_id > event.event_id)
+        });
+
+        if awaited {
+            self.next_step += 1;
+        }
+
+        if let Some(signal) = self.signals.get_mut(name).and_then(VecDeque::pop_front) {
+            return Ok(signal.payload.unwrap_or_default());
+        }
+
+        if awaited {
+            self.asleep = true;
+        } else {
+            self.recorded = Some(NewWorkflowEvent::signal_awaited(name));
+        }
+
+        Err(Suspended.into())
+    }
+
+    /// Fails with [`Suspended`] once the replay has stopped, so nothing past that point runs.
+    fn check_suspended(&self) -> Result<()> {
+        if self.recorded.is_some() || self.asleep || self.nondeterminism.is_some() {
+            return Err(Suspended.into());
+        }
+
+        Ok(())
+    }
+
+    /// The recorded event for the next step or timer, checking that the replay reached the
+    /// same one. `None` if the workflow has not got this far before.
+    fn next_recorded(
+        &mut self,
+        name: &str,
+        kind: WorkflowEventKind,
+    ) -> Result<Option<WorkflowEvent>> {
+        self.check_suspended()?;
+
+        let Some(event) = self.steps.get(self.next_step).cloned() else {
+            return Ok(None);
+        };
+
+        self.next_step += 1;
+
+        let same_kind = match event.kind {
+            WorkflowEventKind::StepFailed => kind == WorkflowEventKind::StepCompleted,
+            recorded => recorded == kind,
+        };
+
+        if event.name.as_deref() != Some(name) || !same_kind {
+            self.nondeterminism = Some(format!(
+                "step {} replayed as {:?} but was recorded as {:?}",
+                self.next_step,
+                name,
+                event.name.as_deref().unwrap_or_default()
+            ));
+
+            return Err(Suspended.into());
+        }
+
+        Ok(Some(event))
+    }
+
+    /// The event recording how the replay ended, given what the workflow function returned,
+    /// `None` while it is blocked. A failed workflow runs the next of its compensations that
+    /// has yet to run, and records its failure once none are left. Fails if the workflow did
+    /// not take the same steps as its history.
+    pub async fn into_event(
+        mut self,
+        outcome: Result<TaskPayload>,
+    ) -> Result<Option<NewWorkflowEvent>> {
+        if let Some(nondeterminism) = self.nondeterminism {
+            return Err(anyhow::anyhow!(
+                "workflow is not deterministic: {}",
+                nondeterminism
+            ));
+        }
+
+        if self.asleep {
+            return Ok(None);
+        }
+
+        if let Some(recorded) = self.recorded {
+            return Ok(Some(recorded));
+        }
+
+        let err = match outcome {
+            Ok(result) => return Ok(Some(NewWorkflowEvent::workflow_completed(result))),
+            Err(err) => err,
+        };
+
+        // Compensations run last registered first, one per replay like steps.
+        let mut compensated = self.compensated.into_iter();
+
+        while let Some((name, compensate)) = self.compensations.pop() {
+            let Some(event) = compensated.next() else {
+                return Ok(Some(match compensate().await {
+                    Ok(()) => NewWorkflowEvent::compensation_completed(&name),
+                    Err(err) => NewWorkflowEvent::compensation_failed(&name, &err.to_string()),
+                }));
+            };
+
+            if event.name.as_deref() != Some(&name) {
+                return Err(anyhow::anyhow!(
+                    "workflow is not deterministic: compensation for {:?} replayed but {:?} \
+                     was recorded",
+                    name,
+                    event.name.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+
+        Ok(Some(NewWorkflowEvent::workflow_failed(&err.to_string())))
+    }
+}
+
+/// A workflow function. It has to take the same steps, in the same order, every time it is
+/// replayed against the same history.
+#[async_trait]
+pub trait WorkflowFn: Send + Sync {
+    async fn run(&self, context: &mut WorkflowContext) -> Result<TaskPayload>;
+}
+
+/// Replays the workflow against its history with `workflow_fn` and appends the outcome.
+/// Returns the new `last_event_id`, or `None` if there was nothing to do because the workflow
+/// has finished, is blocked or its history moved on during the replay.
+pub async fn replay_workflow<Q: TaskQueue + Sync>(
+    task_queue: &Q,
+    workflow_id: &str,
+    workflow_fn: &dyn WorkflowFn,
+) -> Result<Option<i64>> {
+    let history = task_queue.workflow_history(workflow_id).await?;
+
+    let Some(last_event) = history.last() else {
+        return Err(anyhow::anyhow!("workflow not found: {}", workflow_id));
+    };
+
+    if last_event.kind.finished_status().is_some() {
+        return Ok(None);
+    }
+
+    let mut context = WorkflowContext::new(workflow_id, &history)?;
+    let outcome = workflow_fn.run(&mut context).await;
+    let Some(event) = context.into_event(outcome).await? else {
+        return Ok(None);
+    };
+
+    task_queue
+        .append_workflow_events(workflow_id, last_event.event_id, &[event])
+        .await
+}
+
+/// Processes workflow tasks by replaying the workflow functions registered for their types.
+pub struct WorkflowWorker<Q> {
+    task_queue: Arc<Q>,
+    workflows: HashMap<String, Box<dyn WorkflowFn>>,
+}
+
+impl<Q> WorkflowWorker<Q> {
+    pub fn new(task_queue: Arc<Q>) -> Self {
+        Self {
+            task_queue,
+            workflows: HashMap::new(),
+        }
+    }
+
+    /// Replays workflows of `workflow_type` with `workflow_fn`.
+    pub fn register(mut self, workflow_type: &str, workflow_fn: impl WorkflowFn + 'static) -> Self {
+        self.workflows
+            .insert(workflow_type.to_string(), Box::new(workflow_fn));
+        self
+    }
+}
+
+#[async_trait]
+impl<Q: TaskQueue + Send + Sync> TaskProcessor for WorkflowWorker<Q> {
+    async fn process_task(
+        &self,
+        task: TaskData,
+        _heartbeat: &dyn Heartbeat,
+    ) -> Result<Option<TaskPayload>> {
+        let workflow_id = task
+            .workflow_id()
+            .ok_or_else(|| anyhow::anyhow!("not a workflow task: {}", task.task_id))?;
+
+        let workflow = self
+            .task_queue
+            .get_workflow(workflow_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("workflow not found: {}", workflow_id))?;
+
+        if workflow.status != WorkflowStatus::Running {
+            return Ok(None);
+        }
+
+        let workflow_fn = self
+            .workflows
+            .get(&workflow.workflow_type)
+            .ok_or_else(|| anyhow::anyhow!("unknown workflow type: {}", workflow.workflow_type))?;
+
+        match replay_workflow(&*self.task_queue, workflow_id, workflow_fn.as_ref()).await? {
+            Some(last_event_id) => {
+                tracing::debug!(workflow_id, last_event_id, "workflow_replayed");
+            }
+            None => {
+                tracing::debug!(workflow_id, "workflow_replay_superseded");
+            }
+        }
+
+        Ok(None)
+    }
+}
@@ -1,2 +1,6 @@
+pub(crate) mod partition_resolver_tests;
 pub(crate) mod persistence;
+pub(crate) mod scheduler_tests;
 pub(crate) mod simulation;
+pub(crate) mod task_definition_tests;
+pub(crate) mod workflow_tests;
@@ -0,0 +1,198 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use chitchat::ChitchatId;
+use hyper::{body::Bytes, Body};
+use prost::Message;
+use server_lib::{
+    cluster_monitor::{ClusterNode, ClusterNodeId, ClusterStateChange},
+    partition_resolver::PartitionResolver,
+    rpc::{partition_router::PartitionRouter, proto},
+};
+use tower::{Service, ServiceExt};
+
+fn node(node_id: &str, port: u16) -> Result<ClusterNode> {
+    let grpc_endpoint = SocketAddr::from(([127, 0, 0, 1], port));
+    let chitchat_id = ChitchatId::new(node_id.to_string(), 1, grpc_endpoint);
+
+    ClusterNode::new(&chitchat_id, grpc_endpoint)
+}
+
+/// A resolver for `self`, on a ring it shares with `other`.
+async fn resolver_with_peer() -> Result<PartitionResolver> {
+    let mut resolver = PartitionResolver::new(ClusterNodeId("self".to_string()), 50, (0, 0));
+
+    resolver
+        .sync(&vec![
+            ClusterStateChange::Added(node("self", 50051)?),
+            ClusterStateChange::Added(node("other", 50052)?),
+        ])
+        .await;
+
+    Ok(resolver)
+}
+
+#[tokio::test]
+async fn keys_owned_by_this_node_resolve_locally() -> Result<()> {
+    let resolver = resolver_with_peer().await?;
+    let (mut local, mut remote) = (0, 0);
+
+    for key in (0..100).map(|i| format!("key-{}", i)) {
+        let owner = resolver.resolve_node_id(key.as_bytes()).await.unwrap();
+        let channel = resolver.resolve(key.as_bytes()).await;
+
+        if owner.0 == "self" {
+            local += 1;
+            assert!(resolver.is_local(key.as_bytes()).await);
+            assert!(channel.is_none());
+        } else {
+            remote += 1;
+            assert_eq!(owner.0, "other");
+            assert!(!resolver.is_local(key.as_bytes()).await);
+            assert!(channel.is_some());
+        }
+    }
+
+    // Both nodes own a share of the keys.
+    assert!(local > 0 && remote > 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn keys_route_to_the_remaining_node_once_this_one_leaves() -> Result<()> {
+    let mut resolver = resolver_with_peer().await?;
+
+    resolver
+        .sync(&vec![ClusterStateChange::Removed(node("self", 50051)?)])
+        .await;
+
+    for key in (0..100).map(|i| format!("key-{}", i)) {
+        assert!(!resolver.is_local(key.as_bytes()).await);
+        assert!(resolver.resolve(key.as_bytes()).await.is_some());
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn nothing_is_local_before_this_node_joins_the_ring() -> Result<()> {
+    let resolver = PartitionResolver::new(ClusterNodeId("self".to_string()), 50, (0, 0));
+
+    assert!(!resolver.is_local(b"key").await);
+    assert!(resolver.resolve(b"key").await.is_none());
+
+    Ok(())
+}
+
+/// The first of `self`'s keys, or of `other`'s.
+async fn key_placed(resolver: &PartitionResolver, local: bool) -> String {
+    for key in (0..).map(|i| format!("workflow-{}", i)) {
+        if resolver.is_local(key.as_bytes()).await == local {
+            return key;
+        }
+    }
+
+    unreachable!()
+}
+
+/// A `SignalWorkflow` call framed as gRPC sends it.
+fn signal_request(workflow_id: &str, headers: &[(&str, &str)]) -> hyper::Request<Body> {
+    let message = proto::SignalWorkflowRequest {
+        workflow_id: workflow_id.to_string(),
+        name: "approved".to_string(),
+        payload: b"yes".to_vec(),
+    }
+    .encode_to_vec();
+
+    let mut body = vec![0];
+    body.extend((message.len() as u32).to_be_bytes());
+    body.extend(message);
+
+    let mut request = hyper::Request::post("/svppl.v0.Workflows/SignalWorkflow")
+        .header("content-type", "application/grpc");
+
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+
+    request.body(Body::from(body)).unwrap()
+}
+
+/// Routes with `resolver`, recording the bodies of the requests served on this node.
+fn router(
+    resolver: PartitionResolver,
+) -> (
+    PartitionRouter<
+        impl tower::Service<
+                hyper::Request<Body>,
+                Response = hyper::Response<tonic::body::BoxBody>,
+                Error = Infallible,
+                Future = impl Send,
+            > + Clone
+            + Send,
+    >,
+    Arc<Mutex<Vec<Bytes>>>,
+) {
+    let served = Arc::new(Mutex::new(Vec::new()));
+    let recorded = served.clone();
+
+    let inner = tower::service_fn(move |request: hyper::Request<Body>| {
+        let served = served.clone();
+
+        async move {
+            let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+            served.lock().unwrap().push(body);
+
+            Ok::<_, Infallible>(hyper::Response::new(tonic::body::empty_body()))
+        }
+    });
+
+    (PartitionRouter::new(resolver, inner), recorded)
+}
+
+#[tokio::test]
+async fn workflow_requests_are_routed_by_the_workflow_id_they_carry() -> Result<()> {
+    let resolver = resolver_with_peer().await?;
+    let local = key_placed(&resolver, true).await;
+    let remote = key_placed(&resolver, false).await;
+    let (mut router, served) = router(resolver);
+
+    // Served here, with the body read for routing passed on intact.
+    let request = signal_request(&local, &[]);
+    let sent = hyper::body::to_bytes(signal_request(&local, &[]).into_body()).await?;
+    router.ready().await?.call(request).await?;
+    assert_eq!(*served.lock().unwrap(), vec![sent]);
+
+    // Headers naming a local key do not keep another node's workflow here.
+    let request = signal_request(
+        &remote,
+        &[("partition_key", &local), ("workflow_id", &local)],
+    );
+    router.ready().await?.call(request).await?;
+    assert_eq!(served.lock().unwrap().len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn unreadable_workflow_requests_are_rejected() -> Result<()> {
+    let (router, served) = router(resolver_with_peer().await?);
+
+    let request = hyper::Request::post("/svppl.v0.Workflows/QueryWorkflow")
+        .header("content-type", "application/grpc")
+        .body(Body::from(vec![0, 0, 0]))?;
+    let response = router.oneshot(request).await?;
+
+    assert_eq!(
+        response.headers()["grpc-status"],
+        (tonic::Code::InvalidArgument as i32).to_string()
+    );
+    assert!(served.lock().unwrap().is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use anyhow::Result;
+use server_lib::persistence::common::{RetryPolicy, TaskId, TaskStatus};
+
+#[test]
+fn task_id_round_trips_through_string() -> Result<()> {
+    let task_id = TaskId::from_parts("queue:with:colons", 3, 42);
+    let parsed: TaskId = task_id.to_string().parse()?;
+
+    assert_eq!(parsed, task_id);
+    assert_eq!(parsed.queue_id(), "queue:with:colons");
+    assert_eq!(parsed.partition_id(), 3);
+    assert_eq!(parsed.seq_id(), 42);
+
+    Ok(())
+}
+
+#[test]
+fn task_id_rejects_malformed_strings() {
+    assert!("".parse::<TaskId>().is_err());
+    assert!("queue:1".parse::<TaskId>().is_err());
+    assert!(":1:2".parse::<TaskId>().is_err());
+    assert!("queue:x:2".parse::<TaskId>().is_err());
+}
+
+#[test]
+fn task_status_round_trips_through_i16() -> Result<()> {
+    for status in TaskStatus::ALL {
+        assert_eq!(TaskStatus::try_from(status.as_i16())?, status);
+    }
+
+    assert!(TaskStatus::try_from(-1).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn task_status_transitions() {
+    assert!(TaskStatus::Pending.can_transition_to(TaskStatus::Leased));
+    assert!(TaskStatus::Leased.can_transition_to(TaskStatus::Succeeded));
+    assert!(TaskStatus::Leased.can_transition_to(TaskStatus::Pending));
+    assert!(TaskStatus::DeadLettered.can_transition_to(TaskStatus::Pending));
+    assert!(TaskStatus::Blocked.can_transition_to(TaskStatus::Pending));
+
+    assert!(!TaskStatus::Pending.can_transition_to(TaskStatus::Succeeded));
+    assert!(!TaskStatus::Succeeded.can_transition_to(TaskStatus::Pending));
+    assert!(!TaskStatus::Cancelled.can_transition_to(TaskStatus::Leased));
+    assert!(!TaskStatus::Blocked.can_transition_to(TaskStatus::Leased));
+
+    for status in TaskStatus::ALL
+        .into_iter()
+        .filter(|status| status.is_terminal())
+    {
+        assert!(TaskStatus::ALL
+            .into_iter()
+            .all(|next| !status.can_transition_to(next)));
+    }
+
+    assert_eq!(
+        TaskStatus::Leased.sources(),
+        vec![TaskStatus::Pending, TaskStatus::Scheduled]
+    );
+}
+
+#[test]
+fn retry_backoff_grows_exponentially_up_to_the_cap() {
+    let policy = RetryPolicy {
+        initial_backoff_ms: 100,
+        max_backoff_ms: 1_000,
+        backoff_multiplier: 2.0,
+        jitter: 0.0,
+        ..RetryPolicy::default()
+    };
+
+    let delays: Vec<_> = (1..=6)
+        .map(|attempts| policy.backoff_ms(attempts, 0.5))
+        .collect();
+
+    assert_eq!(delays, vec![100, 200, 400, 800, 1_000, 1_000]);
+}
+
+#[test]
+fn retry_backoff_jitter_only_shortens_the_delay() {
+    let policy = RetryPolicy {
+        initial_backoff_ms: 1_000,
+        jitter: 0.5,
+        ..RetryPolicy::default()
+    };
+
+    assert_eq!(policy.backoff_ms(1, 0.0), 1_000);
+    assert_eq!(policy.backoff_ms(1, 0.5), 750);
+    assert!(policy.backoff_ms(1, 0.999) >= 500);
+}
+
+#[test]
+fn retry_policy_validation() {
+    assert!(RetryPolicy::default().validate().is_ok());
+    assert!(RetryPolicy {
+        max_attempts: 0,
+        ..RetryPolicy::default()
+    }
+    .validate()
+    .is_err());
+    assert!(RetryPolicy {
+        jitter: 1.5,
+        ..RetryPolicy::default()
+    }
+    .validate()
+    .is_err());
+}
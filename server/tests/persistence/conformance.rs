@@ -0,0 +1,1875 @@
+//! The contract every `TaskQueue` backend has to meet.
+//!
+//! Each check is a generic function over the queue, and `task_queue_conformance!` turns the
+//! whole set into tests for one backend. Checks use their own random queue ids, so they can
+//! share a database.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use nanoid::nanoid;
+use server_lib::persistence::common::{
+    child_workflow_id, now_millis, Heartbeat, LeaseState, MisfirePolicy, NewTask,
+    NewTaskDefinition, NewWorkflow, NewWorkflowEvent, ParentFailurePolicy, RetryPolicy, Schedule,
+    TaskData, TaskDependency, TaskId, TaskPayload, TaskProcessor, TaskProgress, TaskQueue,
+    TaskStatus, WorkflowEventKind, WorkflowStatus, WorkflowTimer, CANCELLED_REASON,
+    DEPENDENCY_FAILED_REASON,
+};
+
+/// Generates a test per conformance check. `$store` names the queue that the setup
+/// statements bind, so setup can keep guards such as containers alive for the test.
+macro_rules! task_queue_conformance {
+    ($store:ident => { $($setup:tt)* }) => {
+        mod conformance {
+            use super::*;
+
+            task_queue_conformance!(@tests $store { $($setup)* }
+                status_filters_and_limits,
+                fifo_order_per_partition,
+                no_double_delivery_under_concurrent_processing,
+                lease_ack_and_release_tasks,
+                expired_leases_return_to_the_queue,
+                delayed_tasks_are_leased_once_due,
+                tasks_past_their_deadline_are_failed,
+                leases_are_revoked_after_timeout,
+                failed_tasks_are_retried_with_backoff,
+                tasks_are_dead_lettered_once_attempts_are_used_up,
+                dead_lettered_tasks_can_be_redriven_and_purged,
+                purged_tasks_free_their_idempotency_keys,
+                retry_policies_are_per_queue,
+                idempotency_keys_deduplicate_enqueues,
+                concurrent_enqueues_with_one_key_share_a_task,
+                tasks_can_be_cancelled,
+                waiting_tasks_can_be_rescheduled,
+                higher_priority_tasks_are_leased_first,
+                results_are_saved_with_succeeded_tasks,
+                heartbeats_extend_leases_and_save_progress,
+                dependent_tasks_wait_for_their_parents,
+                parent_failures_propagate_by_policy,
+                dependents_move_on_as_soon_as_their_parent_finishes,
+                schedules_are_stored_and_advanced_once,
+                workflow_histories_are_appended_once,
+                workflow_timers_fire_once,
+                workflow_signals_wake_blocked_workflows,
+                child_workflows_report_to_their_parent,
+                task_definitions_are_versioned,
+            );
+        }
+    };
+    (@tests $store:ident { $($setup:tt)* } $check:ident, $($rest:ident,)*) => {
+        #[tokio::test]
+        async fn $check() -> anyhow::Result<()> {
+            $($setup)*
+            crate::persistence::conformance::$check(&$store).await
+        }
+
+        task_queue_conformance!(@tests $store { $($setup)* } $($rest,)*);
+    };
+    (@tests $store:ident { $($setup:tt)* }) => {};
+}
+
+pub(crate) use task_queue_conformance;
+
+/// Records every task it runs and fails those whose payload is `fail`.
+#[derive(Default)]
+struct RecordingProcessor {
+    runs: Mutex<Vec<TaskId>>,
+}
+
+impl RecordingProcessor {
+    fn runs(&self) -> Vec<TaskId> {
+        self.runs.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl TaskProcessor for RecordingProcessor {
+    async fn process_task(
+        &self,
+        task: TaskData,
+        heartbeat: &dyn Heartbeat,
+    ) -> Result<Option<TaskPayload>> {
+        self.runs.lock().unwrap().push(task.task_id.clone());
+
+        // Give concurrent callers a chance to race for the same tasks.
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        match task.payload.as_slice() {
+            b"fail" => Err(anyhow::anyhow!("attempt {} failed", task.attempts)),
+            b"heartbeat" => {
+                let state = heartbeat.beat(Some(half_done())).await?;
+                assert_eq!(state, LeaseState::Held);
+                Ok(None)
+            }
+            b"echo" => Ok(Some(task.payload)),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn half_done() -> TaskProgress {
+    TaskProgress {
+        percent: 50.0,
+        detail: Some("halfway".to_string()),
+    }
+}
+
+fn payloads(count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("payload {}", i)).collect()
+}
+
+fn new_tasks(payloads: &[String]) -> Vec<NewTask<'_>> {
+    payloads
+        .iter()
+        .map(|payload| NewTask::new(payload.as_bytes()))
+        .collect()
+}
+
+fn task_ids(tasks: &[TaskData]) -> Vec<TaskId> {
+    tasks.iter().map(|task| task.task_id.clone()).collect()
+}
+
+async fn set_backoff<Q: TaskQueue>(
+    store: &Q,
+    queue_id: &str,
+    max_attempts: i32,
+    initial_backoff_ms: i64,
+) -> Result<()> {
+    store
+        .set_retry_policy(
+            queue_id,
+            &RetryPolicy {
+                max_attempts,
+                initial_backoff_ms,
+                jitter: 0.0,
+                ..RetryPolicy::default()
+            },
+        )
+        .await
+}
+
+pub async fn status_filters_and_limits<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let payloads = payloads(3);
+
+    let mut tasks = new_tasks(&payloads);
+    tasks[2].scheduled_at = now_millis() + 60_000;
+
+    let ids = store.enqueue_tasks(&queue_id, 0, tasks).await?;
+
+    let pending = store
+        .query_tasks(&queue_id, 0, TaskStatus::Pending, 10)
+        .await?;
+    let scheduled = store
+        .query_tasks(&queue_id, 0, TaskStatus::Scheduled, 10)
+        .await?;
+
+    assert_eq!(task_ids(&pending), ids[..2]);
+    assert_eq!(task_ids(&scheduled), ids[2..]);
+    assert_eq!(pending[0].payload, payloads[0].as_bytes());
+    assert_eq!(
+        store
+            .query_tasks(&queue_id, 0, TaskStatus::Pending, 1)
+            .await?
+            .len(),
+        1
+    );
+
+    let leased = store.lease_tasks(&queue_id, 0, 1, 60_000).await?;
+    assert!(
+        store
+            .ack_task(&ids[0], &leased[0].lease_token, None)
+            .await?
+    );
+
+    let succeeded = store
+        .query_tasks(&queue_id, 0, TaskStatus::Succeeded, 10)
+        .await?;
+    let still_pending = store
+        .query_tasks(&queue_id, 0, TaskStatus::Pending, 10)
+        .await?;
+
+    assert_eq!(task_ids(&succeeded), ids[..1]);
+    assert_eq!(task_ids(&still_pending), ids[1..2]);
+    assert!(store
+        .query_tasks(&queue_id, 0, TaskStatus::Leased, 10)
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+pub async fn fifo_order_per_partition<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let payloads = payloads(10);
+
+    let mut ids = Vec::new();
+
+    // Interleave batches across two partitions, each keeps its own order.
+    for chunk in payloads.chunks(3) {
+        ids.extend(store.enqueue_tasks(&queue_id, 0, new_tasks(chunk)).await?);
+        store.enqueue_tasks(&queue_id, 1, new_tasks(chunk)).await?;
+    }
+
+    let mut leased_ids = Vec::new();
+
+    loop {
+        let leased = store.lease_tasks(&queue_id, 0, 3, 60_000).await?;
+
+        if leased.is_empty() {
+            break;
+        }
+
+        leased_ids.extend(leased.into_iter().map(|leased| leased.task.task_id));
+    }
+
+    assert_eq!(leased_ids, ids);
+    assert_eq!(
+        store
+            .query_tasks(&queue_id, 1, TaskStatus::Pending, 100)
+            .await?
+            .len(),
+        payloads.len()
+    );
+
+    Ok(())
+}
+
+pub async fn no_double_delivery_under_concurrent_processing<Q: TaskQueue + Sync>(
+    store: &Q,
+) -> Result<()> {
+    let queue_id = nanoid!();
+    let payloads = payloads(50);
+    let ids = store
+        .enqueue_tasks(&queue_id, 0, new_tasks(&payloads))
+        .await?;
+
+    let processor = RecordingProcessor::default();
+
+    for _ in 0..10 {
+        let callers = (0..5).map(|_| store.process_tasks(&queue_id, 0, 4, &processor));
+
+        for result in futures::future::join_all(callers).await {
+            result?;
+        }
+    }
+
+    let mut runs: HashMap<TaskId, usize> = HashMap::new();
+
+    for task_id in processor.runs() {
+        *runs.entry(task_id).or_default() += 1;
+    }
+
+    assert_eq!(runs.len(), ids.len());
+    assert!(runs.values().all(|count| *count == 1));
+
+    let succeeded = store
+        .query_tasks(&queue_id, 0, TaskStatus::Succeeded, 100)
+        .await?;
+
+    assert_eq!(succeeded.len(), ids.len());
+
+    Ok(())
+}
+
+pub async fn lease_ack_and_release_tasks<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let payloads = payloads(3);
+    let ids = store
+        .enqueue_tasks(&queue_id, 0, new_tasks(&payloads))
+        .await?;
+
+    let leased = store.lease_tasks(&queue_id, 0, 2, 60_000).await?;
+    let leased_ids: Vec<_> = leased
+        .iter()
+        .map(|leased| leased.task.task_id.clone())
+        .collect();
+
+    assert_eq!(leased_ids, ids[..2]);
+    assert_eq!(leased[0].task.attempts, 1);
+    assert_eq!(store.lease_tasks(&queue_id, 0, 10, 60_000).await?.len(), 1);
+
+    assert!(!store.ack_task(&ids[0], "not-the-token", None).await?);
+    assert!(
+        store
+            .ack_task(&ids[0], &leased[0].lease_token, None)
+            .await?
+    );
+    assert!(
+        !store
+            .ack_task(&ids[0], &leased[0].lease_token, None)
+            .await?
+    );
+    assert!(store.release_task(&ids[1], &leased[1].lease_token).await?);
+
+    let released = store.lease_tasks(&queue_id, 0, 10, 60_000).await?;
+
+    assert_eq!(released.len(), 1);
+    assert_eq!(released[0].task.task_id, ids[1]);
+    // Releasing hands the attempt back.
+    assert_eq!(released[0].task.attempts, 1);
+
+    Ok(())
+}
+
+pub async fn expired_leases_return_to_the_queue<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let ids = store
+        .enqueue_tasks(&queue_id, 0, vec![NewTask::new(b"payload")])
+        .await?;
+
+    let expired = store.lease_tasks(&queue_id, 0, 1, 0).await?;
+    let reclaimed = store.lease_tasks(&queue_id, 0, 1, 60_000).await?;
+
+    assert_eq!(reclaimed.len(), 1);
+    assert_eq!(reclaimed[0].task.task_id, ids[0]);
+    assert_eq!(reclaimed[0].task.attempts, 2);
+    assert_ne!(reclaimed[0].lease_token, expired[0].lease_token);
+
+    // The stale lease can no longer settle the task.
+    assert!(
+        !store
+            .ack_task(&ids[0], &expired[0].lease_token, None)
+            .await?
+    );
+    assert!(
+        store
+            .ack_task(&ids[0], &reclaimed[0].lease_token, None)
+            .await?
+    );
+
+    Ok(())
+}
+
+pub async fn delayed_tasks_are_leased_once_due<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let now = now_millis();
+
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![
+                NewTask {
+                    scheduled_at: now + 60_000,
+                    ..NewTask::new(b"later")
+                },
+                NewTask::new(b"now"),
+                NewTask {
+                    scheduled_at: now - 60_000,
+                    ..NewTask::new(b"overdue")
+                },
+                NewTask {
+                    scheduled_at: now + 50,
+                    ..NewTask::new(b"soon")
+                },
+            ],
+        )
+        .await?;
+
+    let scheduled = store
+        .query_tasks(&queue_id, 0, TaskStatus::Scheduled, 10)
+        .await?;
+
+    assert_eq!(task_ids(&scheduled), vec![ids[3].clone(), ids[0].clone()]);
+
+    let leased = store.lease_tasks(&queue_id, 0, 10, 60_000).await?;
+    let leased_ids: Vec<_> = leased
+        .iter()
+        .map(|leased| leased.task.task_id.clone())
+        .collect();
+
+    // Ordered by due time rather than insertion, and future tasks are held back.
+    assert_eq!(leased_ids, vec![ids[2].clone(), ids[1].clone()]);
+
+    tokio::time::sleep(Duration::from_millis(60)).await;
+
+    let due = store.lease_tasks(&queue_id, 0, 10, 60_000).await?;
+
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].task.task_id, ids[3]);
+
+    Ok(())
+}
+
+pub async fn tasks_past_their_deadline_are_failed<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let now = now_millis();
+
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![
+                NewTask {
+                    deadline_at: Some(now - 1),
+                    ..NewTask::new(b"expired")
+                },
+                NewTask {
+                    deadline_at: Some(now + 60_000),
+                    ..NewTask::new(b"live")
+                },
+            ],
+        )
+        .await?;
+
+    let leased = store.lease_tasks(&queue_id, 0, 10, 60_000).await?;
+
+    assert_eq!(leased.len(), 1);
+    assert_eq!(leased[0].task.task_id, ids[1]);
+
+    let failed = store
+        .query_tasks(&queue_id, 0, TaskStatus::Failed, 10)
+        .await?;
+
+    assert_eq!(task_ids(&failed), ids[..1]);
+    assert_eq!(failed[0].last_error.as_deref(), Some("deadline exceeded"));
+
+    Ok(())
+}
+
+pub async fn leases_are_revoked_after_timeout<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+
+    set_backoff(store, &queue_id, 5, 0).await?;
+
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![NewTask {
+                timeout_ms: Some(10),
+                ..NewTask::new(b"slow")
+            }],
+        )
+        .await?;
+
+    let leased = store.lease_tasks(&queue_id, 0, 1, 60_000).await?;
+
+    // The lease is capped by the task's timeout rather than the requested duration.
+    assert!(leased[0].lease_expires_at <= now_millis() + 10);
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    let released = store.lease_tasks(&queue_id, 0, 1, 60_000).await?;
+
+    assert_eq!(released[0].task.task_id, ids[0]);
+    assert_eq!(
+        released[0].task.last_error.as_deref(),
+        Some("timed out after 10ms")
+    );
+    assert_eq!(released[0].task.attempts, 2);
+    assert!(
+        !store
+            .ack_task(&ids[0], &leased[0].lease_token, None)
+            .await?
+    );
+
+    Ok(())
+}
+
+pub async fn failed_tasks_are_retried_with_backoff<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+
+    set_backoff(store, &queue_id, 5, 60_000).await?;
+
+    let ids = store
+        .enqueue_tasks(&queue_id, 0, vec![NewTask::new(b"payload")])
+        .await?;
+
+    let leased = store.lease_tasks(&queue_id, 0, 1, 60_000).await?;
+    let nacked_at = now_millis();
+
+    assert!(
+        store
+            .nack_task(&ids[0], &leased[0].lease_token, "boom")
+            .await?
+    );
+    assert!(store.lease_tasks(&queue_id, 0, 1, 60_000).await?.is_empty());
+
+    let scheduled = store
+        .query_tasks(&queue_id, 0, TaskStatus::Scheduled, 10)
+        .await?;
+
+    assert_eq!(task_ids(&scheduled), ids);
+    assert_eq!(scheduled[0].attempts, 1);
+    assert_eq!(scheduled[0].max_attempts, 5);
+    assert_eq!(scheduled[0].last_error.as_deref(), Some("boom"));
+    assert!(scheduled[0].scheduled_at >= nacked_at + 60_000);
+
+    Ok(())
+}
+
+pub async fn tasks_are_dead_lettered_once_attempts_are_used_up<Q: TaskQueue + Sync>(
+    store: &Q,
+) -> Result<()> {
+    let queue_id = nanoid!();
+
+    set_backoff(store, &queue_id, 3, 0).await?;
+
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![
+                NewTask::new(b"fail"),
+                NewTask {
+                    max_attempts: Some(1),
+                    ..NewTask::new(b"fail")
+                },
+                NewTask::new(b"ok"),
+            ],
+        )
+        .await?;
+
+    let processor = RecordingProcessor::default();
+
+    // A failing task does not fail the batch, each one is retried on its own.
+    for _ in 0..3 {
+        store.process_tasks(&queue_id, 0, 10, &processor).await?;
+    }
+
+    let mut dead = store.dead_lettered_tasks(&queue_id, 0, 10).await?;
+    dead.sort_by_key(|task| task.task_id.seq_id());
+
+    assert_eq!(task_ids(&dead), ids[..2]);
+    assert_eq!(dead[0].attempts, 3);
+    assert_eq!(dead[0].last_error.as_deref(), Some("attempt 3 failed"));
+    assert_eq!(dead[1].attempts, 1);
+    assert_eq!(dead[1].last_error.as_deref(), Some("attempt 1 failed"));
+
+    let succeeded = store
+        .query_tasks(&queue_id, 0, TaskStatus::Succeeded, 10)
+        .await?;
+
+    assert_eq!(task_ids(&succeeded), ids[2..]);
+    assert_eq!(processor.runs().len(), 5);
+
+    Ok(())
+}
+
+pub async fn dead_lettered_tasks_can_be_redriven_and_purged<Q: TaskQueue + Sync>(
+    store: &Q,
+) -> Result<()> {
+    let queue_id = nanoid!();
+
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            (0..4)
+                .map(|_| NewTask {
+                    max_attempts: Some(1),
+                    ..NewTask::new(b"fail")
+                })
+                .collect(),
+        )
+        .await?;
+
+    let processor = RecordingProcessor::default();
+
+    store.process_tasks(&queue_id, 0, 10, &processor).await?;
+
+    assert_eq!(store.dead_lettered_tasks(&queue_id, 0, 10).await?.len(), 4);
+
+    // Only dead-lettered tasks are redriven, so repeating a redrive is a no-op.
+    assert_eq!(store.redrive_tasks(&ids[..1]).await?, 1);
+    assert_eq!(store.redrive_tasks(&ids[..2]).await?, 1);
+
+    let pending = store
+        .query_tasks(&queue_id, 0, TaskStatus::Pending, 10)
+        .await?;
+
+    assert_eq!(task_ids(&pending), ids[..2]);
+    assert!(pending.iter().all(|task| task.attempts == 0));
+
+    assert_eq!(store.redrive_all_tasks(&queue_id, 0).await?, 2);
+
+    // Redriven tasks get their attempts back, so they run and dead-letter again.
+    store.process_tasks(&queue_id, 0, 10, &processor).await?;
+
+    assert_eq!(store.purge_dead_lettered(&queue_id, 0).await?, 4);
+    assert!(store
+        .dead_lettered_tasks(&queue_id, 0, 10)
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+pub async fn purged_tasks_free_their_idempotency_keys<Q: TaskQueue + Sync>(
+    store: &Q,
+) -> Result<()> {
+    let queue_id = nanoid!();
+    let task = NewTask {
+        idempotency_key: Some("key"),
+        max_attempts: Some(1),
+        ..NewTask::new(b"fail")
+    };
+
+    let ids = store.enqueue_tasks(&queue_id, 0, vec![task]).await?;
+    let leased = store.lease_tasks(&queue_id, 0, 1, 60_000).await?;
+
+    assert!(
+        store
+            .nack_task(&ids[0], &leased[0].lease_token, "boom")
+            .await?
+    );
+    assert_eq!(store.purge_dead_lettered(&queue_id, 0).await?, 1);
+
+    // The key no longer resolves to the purged task, so it writes a live one.
+    let again = store.enqueue_tasks(&queue_id, 0, vec![task]).await?;
+    assert_ne!(again, ids);
+
+    let task = store.get_task(&again[0]).await?.expect("task exists");
+    assert_eq!(task.status, TaskStatus::Pending);
+
+    Ok(())
+}
+
+pub async fn retry_policies_are_per_queue<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let other_queue_id = nanoid!();
+
+    let policy = RetryPolicy {
+        max_attempts: 7,
+        initial_backoff_ms: 250,
+        max_backoff_ms: 10_000,
+        backoff_multiplier: 3.0,
+        jitter: 0.5,
+    };
+
+    store.set_retry_policy(&queue_id, &policy).await?;
+
+    assert_eq!(store.retry_policy(&queue_id).await?, policy);
+    assert_eq!(
+        store.retry_policy(&other_queue_id).await?,
+        RetryPolicy::default()
+    );
+    assert!(store
+        .set_retry_policy(
+            &queue_id,
+            &RetryPolicy {
+                max_attempts: 0,
+                ..RetryPolicy::default()
+            },
+        )
+        .await
+        .is_err());
+
+    let ids = store
+        .enqueue_tasks(&queue_id, 0, vec![NewTask::new(b"payload")])
+        .await?;
+    let tasks = store
+        .query_tasks(&queue_id, 0, TaskStatus::Pending, 10)
+        .await?;
+
+    assert_eq!(task_ids(&tasks), ids);
+    assert_eq!(tasks[0].max_attempts, 7);
+
+    // A task can not override the policy with fewer attempts than it allows either.
+    let no_attempts = NewTask {
+        max_attempts: Some(0),
+        ..NewTask::new(b"payload")
+    };
+    assert!(store
+        .enqueue_tasks(&queue_id, 0, vec![NewTask::new(b"payload"), no_attempts])
+        .await
+        .is_err());
+    assert_eq!(
+        store
+            .query_tasks(&queue_id, 0, TaskStatus::Pending, 10)
+            .await?
+            .len(),
+        1
+    );
+
+    Ok(())
+}
+
+pub async fn idempotency_keys_deduplicate_enqueues<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let keyed = |key| NewTask {
+        idempotency_key: Some(key),
+        ..NewTask::new(b"payload")
+    };
+
+    let first = store
+        .enqueue_tasks(&queue_id, 0, vec![keyed("a"), keyed("b"), keyed("a")])
+        .await?;
+    assert_eq!(first[0], first[2]);
+    assert_ne!(first[0], first[1]);
+
+    // A retried enqueue returns the original id, even from another partition.
+    let retried = store
+        .enqueue_tasks(
+            &queue_id,
+            1,
+            vec![keyed("b"), keyed("c"), NewTask::new(b"x")],
+        )
+        .await?;
+    assert_eq!(retried[0], first[1]);
+    assert_eq!(retried[1].partition_id(), 1);
+
+    let other_queue = store.enqueue_tasks(&nanoid!(), 0, vec![keyed("a")]).await?;
+    assert_ne!(other_queue[0], first[0]);
+
+    let pending = store
+        .query_tasks(&queue_id, 0, TaskStatus::Pending, 10)
+        .await?;
+    assert_eq!(task_ids(&pending), first[..2]);
+
+    let pending = store
+        .query_tasks(&queue_id, 1, TaskStatus::Pending, 10)
+        .await?;
+    assert_eq!(task_ids(&pending), retried[1..]);
+
+    Ok(())
+}
+
+pub async fn concurrent_enqueues_with_one_key_share_a_task<Q: TaskQueue + Sync>(
+    store: &Q,
+) -> Result<()> {
+    let queue_id = nanoid!();
+
+    let enqueues = (0..8).map(|_| {
+        store.enqueue_tasks(
+            &queue_id,
+            0,
+            vec![NewTask {
+                idempotency_key: Some("key"),
+                ..NewTask::new(b"payload")
+            }],
+        )
+    });
+
+    let ids: Vec<TaskId> = futures::future::try_join_all(enqueues)
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    assert!(ids.iter().all(|id| *id == ids[0]));
+
+    let pending = store
+        .query_tasks(&queue_id, 0, TaskStatus::Pending, 10)
+        .await?;
+    assert_eq!(task_ids(&pending), ids[..1]);
+
+    Ok(())
+}
+
+pub async fn tasks_can_be_cancelled<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let payloads = payloads(3);
+
+    let ids = store
+        .enqueue_tasks(&queue_id, 0, new_tasks(&payloads))
+        .await?;
+
+    assert!(store.cancel_task(&ids[0]).await?);
+    assert!(!store.cancel_task(&ids[0]).await?);
+
+    // The cancelled task is skipped, the rest are leased.
+    let leased = store.lease_tasks(&queue_id, 0, 10, 60_000).await?;
+    assert_eq!(leased.len(), 2);
+    assert_eq!(leased[0].task.task_id, ids[1]);
+
+    let token = &leased[0].lease_token;
+    assert_eq!(store.lease_state(&ids[1], token).await?, LeaseState::Held);
+
+    // A running task sees the cancellation through its lease and can no longer settle.
+    assert!(store.cancel_task(&ids[1]).await?);
+    assert_eq!(
+        store.lease_state(&ids[1], token).await?,
+        LeaseState::Cancelled
+    );
+    assert!(!store.ack_task(&ids[1], token, None).await?);
+
+    assert!(
+        store
+            .ack_task(&ids[2], &leased[1].lease_token, None)
+            .await?
+    );
+    assert!(!store.cancel_task(&ids[2]).await?);
+    assert_eq!(
+        store.lease_state(&ids[2], &leased[1].lease_token).await?,
+        LeaseState::Lost
+    );
+
+    let cancelled = store
+        .query_tasks(&queue_id, 0, TaskStatus::Cancelled, 10)
+        .await?;
+    assert_eq!(task_ids(&cancelled), ids[..2]);
+    assert_eq!(cancelled[0].last_error.as_deref(), Some(CANCELLED_REASON));
+
+    Ok(())
+}
+
+pub async fn waiting_tasks_can_be_rescheduled<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let later = now_millis() + 60_000;
+
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![
+                NewTask {
+                    scheduled_at: later,
+                    ..NewTask::new(b"later")
+                },
+                NewTask::new(b"now"),
+            ],
+        )
+        .await?;
+
+    assert!(store.reschedule_task(&ids[0], 0).await?);
+    assert!(store.reschedule_task(&ids[1], later).await?);
+
+    let scheduled = store
+        .query_tasks(&queue_id, 0, TaskStatus::Scheduled, 10)
+        .await?;
+    assert_eq!(task_ids(&scheduled), ids[1..]);
+    assert_eq!(scheduled[0].scheduled_at, later);
+
+    let leased = store.lease_tasks(&queue_id, 0, 10, 60_000).await?;
+    assert_eq!(leased.len(), 1);
+    assert_eq!(leased[0].task.task_id, ids[0]);
+
+    // Only tasks that are still waiting can move.
+    assert!(!store.reschedule_task(&ids[0], later).await?);
+    assert!(
+        store
+            .ack_task(&ids[0], &leased[0].lease_token, None)
+            .await?
+    );
+    assert!(!store.reschedule_task(&ids[0], later).await?);
+
+    Ok(())
+}
+
+pub async fn higher_priority_tasks_are_leased_first<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let now = now_millis();
+    let prioritised = |priority, scheduled_at| NewTask {
+        priority,
+        scheduled_at,
+        ..NewTask::new(b"payload")
+    };
+
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![
+                prioritised(0, now - 2_000),
+                prioritised(-1, now - 3_000),
+                prioritised(5, now - 1_000),
+                prioritised(5, now - 2_000),
+            ],
+        )
+        .await?;
+
+    let leased = store.lease_tasks(&queue_id, 0, 10, 60_000).await?;
+    let leased_ids: Vec<TaskId> = leased
+        .iter()
+        .map(|leased| leased.task.task_id.clone())
+        .collect();
+
+    // Priority first, then the earliest due within a priority.
+    let expected: Vec<TaskId> = [3, 2, 0, 1].iter().map(|i| ids[*i].clone()).collect();
+    assert_eq!(leased_ids, expected);
+    assert_eq!(leased[0].task.priority, 5);
+
+    Ok(())
+}
+
+pub async fn results_are_saved_with_succeeded_tasks<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![NewTask::new(b"acked"), NewTask::new(b"echo")],
+        )
+        .await?;
+
+    let pending = store.get_task(&ids[0]).await?.expect("task exists");
+    assert_eq!(pending.status, TaskStatus::Pending);
+    assert_eq!(pending.result, None);
+
+    let leased = store.lease_tasks(&queue_id, 0, 1, 60_000).await?;
+    assert!(
+        store
+            .ack_task(&ids[0], &leased[0].lease_token, Some(b"done"))
+            .await?
+    );
+
+    let acked = store.get_task(&ids[0]).await?.expect("task exists");
+    assert_eq!(acked.status, TaskStatus::Succeeded);
+    assert_eq!(acked.result.as_deref(), Some(&b"done"[..]));
+
+    // Results returned by a processor are saved the same way.
+    store
+        .process_tasks(&queue_id, 0, 10, &RecordingProcessor::default())
+        .await?;
+
+    let processed = store.get_task(&ids[1]).await?.expect("task exists");
+    assert_eq!(processed.status, TaskStatus::Succeeded);
+    assert_eq!(processed.result.as_deref(), Some(&b"echo"[..]));
+
+    let unknown = TaskId::from_parts(&queue_id, 0, i64::MAX);
+    assert!(store.get_task(&unknown).await?.is_none());
+
+    Ok(())
+}
+
+pub async fn heartbeats_extend_leases_and_save_progress<Q: TaskQueue + Sync>(
+    store: &Q,
+) -> Result<()> {
+    let queue_id = nanoid!();
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![
+                NewTask::new(b"payload"),
+                NewTask {
+                    timeout_ms: Some(10_000),
+                    ..NewTask::new(b"timed")
+                },
+            ],
+        )
+        .await?;
+
+    let leased = store.lease_tasks(&queue_id, 0, 2, 60_000).await?;
+    let token = &leased[0].lease_token;
+    let progress = half_done();
+
+    let renewed = store
+        .heartbeat_task(&ids[0], token, 600_000, Some(&progress))
+        .await?
+        .expect("lease is held");
+    assert!(renewed > leased[0].lease_expires_at);
+
+    let task = store.get_task(&ids[0]).await?.expect("task exists");
+    assert_eq!(task.progress, Some(progress.clone()));
+
+    // A bare heartbeat keeps the progress, a stale token renews nothing.
+    assert!(store
+        .heartbeat_task(&ids[0], token, 600_000, None)
+        .await?
+        .is_some());
+    assert!(store
+        .heartbeat_task(&ids[0], "not-the-token", 600_000, None)
+        .await?
+        .is_none());
+
+    let task = store.get_task(&ids[0]).await?.expect("task exists");
+    assert_eq!(task.progress, Some(progress));
+
+    // Renewals stop at the task's timeout.
+    let capped = store
+        .heartbeat_task(&ids[1], token, 600_000, None)
+        .await?
+        .expect("lease is held");
+    assert!(capped <= leased[1].lease_expires_at);
+
+    // Once a lease has lapsed a heartbeat cannot revive it, and the next attempt starts
+    // without progress.
+    assert!(store.release_task(&ids[0], token).await?);
+    let lapsed = store.lease_tasks(&queue_id, 0, 1, 0).await?;
+    assert_eq!(lapsed[0].task.progress, None);
+    assert!(store
+        .heartbeat_task(&ids[0], &lapsed[0].lease_token, 600_000, None)
+        .await?
+        .is_none());
+
+    let reclaimed = store.lease_tasks(&queue_id, 0, 1, 60_000).await?;
+    assert_eq!(reclaimed[0].task.task_id, ids[0]);
+    assert_ne!(reclaimed[0].lease_token, lapsed[0].lease_token);
+
+    // Processors heartbeat through the handle they are given.
+    let heartbeat_ids = store
+        .enqueue_tasks(&queue_id, 1, vec![NewTask::new(b"heartbeat")])
+        .await?;
+
+    store
+        .process_tasks(&queue_id, 1, 1, &RecordingProcessor::default())
+        .await?;
+
+    let processed = store
+        .get_task(&heartbeat_ids[0])
+        .await?
+        .expect("task exists");
+    assert_eq!(processed.status, TaskStatus::Succeeded);
+    assert_eq!(processed.progress, Some(half_done()));
+
+    Ok(())
+}
+
+pub async fn dependent_tasks_wait_for_their_parents<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+
+    // Fans out from `split` and back in to `join`.
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![
+                NewTask::new(b"split"),
+                NewTask {
+                    depends_on: &[0],
+                    ..NewTask::new(b"left")
+                },
+                NewTask {
+                    depends_on: &[0],
+                    ..NewTask::new(b"right")
+                },
+                NewTask {
+                    depends_on: &[1, 2],
+                    ..NewTask::new(b"join")
+                },
+            ],
+        )
+        .await?;
+
+    let blocked = store
+        .query_tasks(&queue_id, 0, TaskStatus::Blocked, 10)
+        .await?;
+    assert_eq!(task_ids(&blocked), ids[1..]);
+
+    let leased = store.lease_tasks(&queue_id, 0, 10, 60_000).await?;
+    assert_eq!(leased.len(), 1);
+    assert_eq!(leased[0].task.task_id, ids[0]);
+    assert!(
+        store
+            .ack_task(&ids[0], &leased[0].lease_token, None)
+            .await?
+    );
+
+    let leased = store.lease_tasks(&queue_id, 0, 10, 60_000).await?;
+    let leased_ids: Vec<TaskId> = leased.iter().map(|l| l.task.task_id.clone()).collect();
+    assert_eq!(leased_ids, ids[1..3]);
+
+    // The join waits for both branches.
+    assert!(
+        store
+            .ack_task(&ids[1], &leased[0].lease_token, None)
+            .await?
+    );
+    assert!(store
+        .lease_tasks(&queue_id, 0, 10, 60_000)
+        .await?
+        .is_empty());
+    assert!(
+        store
+            .ack_task(&ids[2], &leased[1].lease_token, None)
+            .await?
+    );
+
+    let leased = store.lease_tasks(&queue_id, 0, 10, 60_000).await?;
+    assert_eq!(leased.len(), 1);
+    assert_eq!(leased[0].task.task_id, ids[3]);
+
+    let graph = store.task_graph(&ids[3]).await?.expect("task exists");
+    assert_eq!(task_ids(&graph.tasks), ids);
+
+    let edge = |task: usize, parent: usize| TaskDependency {
+        task_id: ids[task].clone(),
+        parent_id: ids[parent].clone(),
+    };
+    assert_eq!(
+        graph.dependencies,
+        vec![edge(1, 0), edge(2, 0), edge(3, 1), edge(3, 2)]
+    );
+
+    // Only earlier tasks in the batch can be depended on.
+    let cyclic = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![NewTask {
+                depends_on: &[0],
+                ..NewTask::new(b"itself")
+            }],
+        )
+        .await;
+    assert!(cyclic.is_err());
+
+    let unknown = TaskId::from_parts(&queue_id, 0, i64::MAX);
+    assert!(store.task_graph(&unknown).await?.is_none());
+
+    Ok(())
+}
+
+/// Finishing a parent moves its dependents on there and then, without waiting for a lease
+/// call on their partition.
+pub async fn dependents_move_on_as_soon_as_their_parent_finishes<Q: TaskQueue + Sync>(
+    store: &Q,
+) -> Result<()> {
+    let queue_id = nanoid!();
+    let status = |task_id: TaskId| async move {
+        let task = store.get_task(&task_id).await?.expect("task exists");
+
+        Ok::<_, anyhow::Error>(task.status)
+    };
+
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![
+                NewTask {
+                    idempotency_key: Some("parent"),
+                    ..NewTask::new(b"parent")
+                },
+                NewTask {
+                    depends_on: &[0],
+                    ..NewTask::new(b"child")
+                },
+                NewTask {
+                    max_attempts: Some(1),
+                    ..NewTask::new(b"doomed")
+                },
+                NewTask {
+                    depends_on: &[2],
+                    ..NewTask::new(b"orphan")
+                },
+                NewTask {
+                    depends_on: &[3],
+                    ..NewTask::new(b"grandchild")
+                },
+            ],
+        )
+        .await?;
+
+    let leased = store.lease_tasks(&queue_id, 0, 2, 60_000).await?;
+    assert_eq!(leased.len(), 2);
+
+    assert!(
+        store
+            .ack_task(&ids[0], &leased[0].lease_token, None)
+            .await?
+    );
+    assert_eq!(status(ids[1].clone()).await?, TaskStatus::Pending);
+
+    // Dead-lettering fails the dependents, and theirs in turn.
+    assert!(
+        store
+            .nack_task(&ids[2], &leased[1].lease_token, "boom")
+            .await?
+    );
+    assert_eq!(status(ids[3].clone()).await?, TaskStatus::Failed);
+    assert_eq!(status(ids[4].clone()).await?, TaskStatus::Failed);
+
+    // A parent found by idempotency key may already have finished, even in another partition.
+    let late = store
+        .enqueue_tasks(
+            &queue_id,
+            1,
+            vec![
+                NewTask {
+                    idempotency_key: Some("parent"),
+                    ..NewTask::new(b"parent")
+                },
+                NewTask {
+                    depends_on: &[0],
+                    ..NewTask::new(b"latecomer")
+                },
+            ],
+        )
+        .await?;
+    assert_eq!(late[0], ids[0]);
+    assert_eq!(status(late[1].clone()).await?, TaskStatus::Pending);
+
+    Ok(())
+}
+
+pub async fn parent_failures_propagate_by_policy<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let on_failure = |depends_on, on_parent_failure, payload| NewTask {
+        depends_on,
+        on_parent_failure,
+        ..NewTask::new(payload)
+    };
+
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![
+                NewTask::new(b"parent"),
+                on_failure(&[0], ParentFailurePolicy::Fail, b"failed"),
+                on_failure(&[0], ParentFailurePolicy::Cancel, b"cancelled"),
+                on_failure(&[0], ParentFailurePolicy::Run, b"cleanup"),
+                on_failure(&[1], ParentFailurePolicy::Fail, b"grandchild"),
+            ],
+        )
+        .await?;
+
+    assert!(store.cancel_task(&ids[0]).await?);
+
+    // Only the task that runs regardless is leased, the failure carries on past its children.
+    let leased = store.lease_tasks(&queue_id, 0, 10, 60_000).await?;
+    assert_eq!(leased.len(), 1);
+    assert_eq!(leased[0].task.task_id, ids[3]);
+
+    let graph = store.task_graph(&ids[0]).await?.expect("task exists");
+    let statuses: Vec<TaskStatus> = graph.tasks.iter().map(|task| task.status).collect();
+    assert_eq!(
+        statuses,
+        vec![
+            TaskStatus::Cancelled,
+            TaskStatus::Failed,
+            TaskStatus::Cancelled,
+            TaskStatus::Leased,
+            TaskStatus::Failed,
+        ]
+    );
+    assert_eq!(
+        graph.tasks[4].last_error.as_deref(),
+        Some(DEPENDENCY_FAILED_REASON)
+    );
+
+    Ok(())
+}
+
+pub async fn schedules_are_stored_and_advanced_once<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let now = now_millis();
+    let schedule = Schedule {
+        schedule_id: nanoid!(),
+        cron: "*/5 * * * *".to_string(),
+        timezone: "UTC".to_string(),
+        queue_id: nanoid!(),
+        partition_id: 3,
+        payload: b"tick".to_vec(),
+        content_type: Some("text/plain".to_string()),
+        misfire_policy: MisfirePolicy::Skip,
+        next_fire_at: now - 1_000,
+    };
+    let later = Schedule {
+        schedule_id: nanoid!(),
+        next_fire_at: now + 60_000,
+        ..schedule.clone()
+    };
+
+    store.put_schedule(&schedule).await?;
+    store.put_schedule(&later).await?;
+
+    assert_eq!(
+        store.get_schedule(&schedule.schedule_id).await?,
+        Some(schedule.clone())
+    );
+    assert!(store.get_schedule(&nanoid!()).await?.is_none());
+
+    let listed = store.list_schedules().await?;
+    assert!(listed.contains(&schedule));
+    assert!(listed.contains(&later));
+
+    let due = store.due_schedules(now, None, 1_000).await?;
+    assert!(due.contains(&schedule));
+    assert!(!due.contains(&later));
+
+    // The next page starts after the last schedule of this one.
+    let next_page = store.due_schedules(now, Some(&schedule), 1_000).await?;
+    assert!(!next_page.contains(&schedule));
+    assert!(next_page.iter().all(|due| {
+        (due.next_fire_at, &due.schedule_id) > (schedule.next_fire_at, &schedule.schedule_id)
+    }));
+
+    // Only the first advance from a fire time goes through.
+    let next = now + 300_000;
+    assert!(
+        !store
+            .advance_schedule(&schedule.schedule_id, now, next)
+            .await?
+    );
+    assert!(
+        store
+            .advance_schedule(&schedule.schedule_id, schedule.next_fire_at, next)
+            .await?
+    );
+    assert!(
+        !store
+            .advance_schedule(&schedule.schedule_id, schedule.next_fire_at, next)
+            .await?
+    );
+
+    let advanced = store
+        .get_schedule(&schedule.schedule_id)
+        .await?
+        .expect("schedule exists");
+    assert_eq!(advanced.next_fire_at, next);
+    assert!(!store
+        .due_schedules(now, None, 1_000)
+        .await?
+        .contains(&advanced));
+
+    // Putting a schedule again replaces it.
+    let replaced = Schedule {
+        cron: "0 * * * *".to_string(),
+        misfire_policy: MisfirePolicy::CatchUp,
+        ..advanced
+    };
+    store.put_schedule(&replaced).await?;
+    assert_eq!(
+        store.get_schedule(&schedule.schedule_id).await?,
+        Some(replaced)
+    );
+
+    assert!(store.delete_schedule(&schedule.schedule_id).await?);
+    assert!(!store.delete_schedule(&schedule.schedule_id).await?);
+    assert!(store.get_schedule(&schedule.schedule_id).await?.is_none());
+
+    Ok(())
+}
+
+pub async fn workflow_histories_are_appended_once<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let workflow_id = nanoid!();
+    let queue_id = nanoid!();
+    let workflow = NewWorkflow {
+        workflow_id: &workflow_id,
+        workflow_type: "adds",
+        queue_id: &queue_id,
+        partition_id: 2,
+        input: b"5,3",
+    };
+
+    assert!(store.start_workflow(&workflow).await?);
+    assert!(!store.start_workflow(&workflow).await?);
+
+    let started = store
+        .get_workflow(&workflow_id)
+        .await?
+        .expect("workflow exists");
+    assert_eq!(started.workflow_type, "adds");
+    assert_eq!(started.status, WorkflowStatus::Running);
+    assert_eq!(started.last_event_id, 1);
+    assert!(store.get_workflow(&nanoid!()).await?.is_none());
+
+    let history = store.workflow_history(&workflow_id).await?;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].kind, WorkflowEventKind::WorkflowStarted);
+    assert_eq!(history[0].name.as_deref(), Some("adds"));
+    assert_eq!(history[0].payload.as_deref(), Some(&b"5,3"[..]));
+
+    // Starting the workflow enqueued a task to replay it.
+    let workflow_tasks = |tasks: Vec<TaskData>| -> Vec<String> {
+        tasks
+            .iter()
+            .filter_map(|task| task.workflow_id().map(str::to_string))
+            .collect()
+    };
+    let pending = store
+        .query_tasks(&queue_id, 2, TaskStatus::Pending, 10)
+        .await?;
+    assert_eq!(workflow_tasks(pending), vec![workflow_id.clone()]);
+
+    // Only one replay of a history gets to append to it.
+    let step = [NewWorkflowEvent::step_completed("add", b"8".to_vec())];
+    assert_eq!(
+        store.append_workflow_events(&workflow_id, 1, &step).await?,
+        Some(2)
+    );
+    assert_eq!(
+        store.append_workflow_events(&workflow_id, 1, &step).await?,
+        None
+    );
+
+    let pending = store
+        .query_tasks(&queue_id, 2, TaskStatus::Pending, 10)
+        .await?;
+    assert_eq!(pending.len(), 2);
+
+    // Events only workers append, and nothing after the workflow finishes.
+    assert!(store
+        .append_workflow_events(&workflow_id, 2, &[])
+        .await
+        .is_err());
+    assert!(store
+        .append_workflow_events(
+            &workflow_id,
+            2,
+            &[
+                NewWorkflowEvent::workflow_completed(b"8".to_vec()),
+                NewWorkflowEvent::step_failed("add", "overflow"),
+            ],
+        )
+        .await
+        .is_err());
+
+    let completed = [
+        NewWorkflowEvent::step_failed("add", "overflow"),
+        NewWorkflowEvent::workflow_completed(b"8".to_vec()),
+    ];
+    assert_eq!(
+        store
+            .append_workflow_events(&workflow_id, 2, &completed)
+            .await?,
+        Some(4)
+    );
+
+    let finished = store
+        .get_workflow(&workflow_id)
+        .await?
+        .expect("workflow exists");
+    assert_eq!(finished.status, WorkflowStatus::Completed);
+    assert_eq!(finished.result.as_deref(), Some(&b"8"[..]));
+    assert_eq!(finished.last_event_id, 4);
+
+    let history = store.workflow_history(&workflow_id).await?;
+    let kinds: Vec<(i64, WorkflowEventKind)> = history
+        .iter()
+        .map(|event| (event.event_id, event.kind))
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (1, WorkflowEventKind::WorkflowStarted),
+            (2, WorkflowEventKind::StepCompleted),
+            (3, WorkflowEventKind::StepFailed),
+            (4, WorkflowEventKind::WorkflowCompleted),
+        ]
+    );
+    assert_eq!(history[2].error.as_deref(), Some("overflow"));
+
+    // A finished workflow is not replayed again.
+    let pending = store
+        .query_tasks(&queue_id, 2, TaskStatus::Pending, 10)
+        .await?;
+    assert_eq!(pending.len(), 2);
+    assert_eq!(
+        store.append_workflow_events(&workflow_id, 4, &step).await?,
+        None
+    );
+
+    Ok(())
+}
+
+pub async fn workflow_timers_fire_once<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let now = now_millis();
+    let queue_id = nanoid!();
+    let asleep = nanoid!();
+    let finished = nanoid!();
+
+    for workflow_id in [&asleep, &finished] {
+        let workflow = NewWorkflow {
+            workflow_id,
+            workflow_type: "reminder",
+            queue_id: &queue_id,
+            partition_id: 0,
+            input: b"",
+        };
+
+        assert!(store.start_workflow(&workflow).await?);
+    }
+
+    let due = [NewWorkflowEvent::timer_started("sleep:1", now - 1_000)];
+    let later = [NewWorkflowEvent::timer_started("sleep:2", now + 60_000)];
+    assert_eq!(
+        store.append_workflow_events(&asleep, 1, &due).await?,
+        Some(2)
+    );
+    assert_eq!(
+        store.append_workflow_events(&asleep, 2, &later).await?,
+        Some(3)
+    );
+    assert_eq!(
+        store
+            .append_workflow_events(
+                &finished,
+                1,
+                &[
+                    NewWorkflowEvent::timer_started("sleep:1", now - 1_000),
+                    NewWorkflowEvent::workflow_failed("gave up"),
+                ],
+            )
+            .await?,
+        Some(3)
+    );
+
+    // A sleeping workflow has nothing to replay.
+    let pending = store
+        .query_tasks(&queue_id, 0, TaskStatus::Pending, 10)
+        .await?;
+    assert_eq!(pending.len(), 2);
+
+    let mut timers: Vec<WorkflowTimer> = store
+        .due_workflow_timers(now, None, 1_000)
+        .await?
+        .into_iter()
+        .filter(|timer| timer.workflow_id == asleep || timer.workflow_id == finished)
+        .collect();
+    timers.sort_by(|a, b| a.workflow_id.cmp(&b.workflow_id));
+
+    let mut expected: Vec<WorkflowTimer> = [&asleep, &finished]
+        .into_iter()
+        .map(|workflow_id| WorkflowTimer {
+            workflow_id: workflow_id.clone(),
+            timer_id: 2,
+            name: "sleep:1".to_string(),
+            fire_at: now - 1_000,
+        })
+        .collect();
+    expected.sort_by(|a, b| a.workflow_id.cmp(&b.workflow_id));
+
+    assert_eq!(timers, expected);
+
+    let next_page = store
+        .due_workflow_timers(now, Some(&timers[0]), 1_000)
+        .await?;
+    assert!(!next_page.contains(&timers[0]));
+    assert!(next_page.contains(&timers[1]));
+
+    for timer in &timers {
+        assert!(store.fire_workflow_timer(timer).await?);
+        assert!(!store.fire_workflow_timer(timer).await?);
+    }
+
+    // Firing wakes the sleeping workflow, the finished one is left alone.
+    let history = store.workflow_history(&asleep).await?;
+    let fired = history.last().expect("history is not empty");
+    assert_eq!(fired.event_id, 4);
+    assert_eq!(fired.kind, WorkflowEventKind::TimerFired);
+    assert_eq!(fired.name.as_deref(), Some("sleep:1"));
+    assert_eq!(fired.fire_at, Some(now - 1_000));
+    assert_eq!(history[1].fire_at, Some(now - 1_000));
+
+    let history = store.workflow_history(&finished).await?;
+    assert_eq!(history.len(), 3);
+
+    let pending = store
+        .query_tasks(&queue_id, 0, TaskStatus::Pending, 10)
+        .await?;
+    assert_eq!(pending.len(), 3);
+
+    let remaining: Vec<WorkflowTimer> = store
+        .due_workflow_timers(now + 60_000, None, 1_000)
+        .await?
+        .into_iter()
+        .filter(|timer| timer.workflow_id == asleep || timer.workflow_id == finished)
+        .collect();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].timer_id, 3);
+
+    Ok(())
+}
+
+pub async fn workflow_signals_wake_blocked_workflows<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let workflow_id = nanoid!();
+
+    let workflow = NewWorkflow {
+        workflow_id: &workflow_id,
+        workflow_type: "approval",
+        queue_id: &queue_id,
+        partition_id: 0,
+        input: b"",
+    };
+
+    assert!(store.start_workflow(&workflow).await?);
+
+    let pending_tasks = || async {
+        Ok::<_, anyhow::Error>(
+            store
+                .query_tasks(&queue_id, 0, TaskStatus::Pending, 10)
+                .await?
+                .len(),
+        )
+    };
+
+    // A workflow blocked on nothing is woken by any signal.
+    assert_eq!(
+        store
+            .signal_workflow(&workflow_id, "note", b"early")
+            .await?,
+        Some(2)
+    );
+    assert_eq!(pending_tasks().await?, 2);
+
+    let awaited = [NewWorkflowEvent::signal_awaited("approve")];
+    assert_eq!(
+        store
+            .append_workflow_events(&workflow_id, 2, &awaited)
+            .await?,
+        Some(3)
+    );
+
+    let blocked = store
+        .get_workflow(&workflow_id)
+        .await?
+        .expect("workflow exists");
+    assert_eq!(blocked.awaiting_signal.as_deref(), Some("approve"));
+    assert_eq!(pending_tasks().await?, 2);
+
+    // Other signals are recorded without waking it.
+    assert_eq!(
+        store.signal_workflow(&workflow_id, "note", b"late").await?,
+        Some(4)
+    );
+    assert_eq!(pending_tasks().await?, 2);
+
+    assert_eq!(
+        store
+            .signal_workflow(&workflow_id, "approve", b"yes")
+            .await?,
+        Some(5)
+    );
+    assert_eq!(pending_tasks().await?, 3);
+
+    let woken = store
+        .get_workflow(&workflow_id)
+        .await?
+        .expect("workflow exists");
+    assert_eq!(woken.awaiting_signal, None);
+    assert_eq!(woken.last_event_id, 5);
+
+    let history = store.workflow_history(&workflow_id).await?;
+    let signal = history.last().expect("history is not empty");
+    assert_eq!(signal.kind, WorkflowEventKind::SignalReceived);
+    assert_eq!(signal.name.as_deref(), Some("approve"));
+    assert_eq!(signal.payload.as_deref(), Some(&b"yes"[..]));
+
+    // Signals are only recorded by the server, and not once the workflow has finished.
+    let received = [NewWorkflowEvent {
+        kind: WorkflowEventKind::SignalReceived,
+        ..NewWorkflowEvent::signal_awaited("approve")
+    }];
+    assert!(store
+        .append_workflow_events(&workflow_id, 5, &received)
+        .await
+        .is_err());
+
+    let completed = [NewWorkflowEvent::workflow_completed(b"yes".to_vec())];
+    assert_eq!(
+        store
+            .append_workflow_events(&workflow_id, 5, &completed)
+            .await?,
+        Some(6)
+    );
+    assert_eq!(
+        store.signal_workflow(&workflow_id, "approve", b"").await?,
+        None
+    );
+    assert_eq!(
+        store.signal_workflow(&nanoid!(), "approve", b"").await?,
+        None
+    );
+
+    Ok(())
+}
+
+pub async fn child_workflows_report_to_their_parent<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let parent_id = nanoid!();
+
+    let workflow = NewWorkflow {
+        workflow_id: &parent_id,
+        workflow_type: "order",
+        queue_id: &queue_id,
+        partition_id: 0,
+        input: b"",
+    };
+
+    assert!(store.start_workflow(&workflow).await?);
+
+    let charge = [NewWorkflowEvent::child_workflow_started(
+        "charge",
+        b"10".to_vec(),
+    )];
+    assert_eq!(
+        store.append_workflow_events(&parent_id, 1, &charge).await?,
+        Some(2)
+    );
+
+    // The child runs on the parent's queue, named after the event that started it.
+    let charge_id = child_workflow_id(&parent_id, 2);
+    let child = store
+        .get_workflow(&charge_id)
+        .await?
+        .expect("child workflow exists");
+    assert_eq!(child.workflow_type, "charge");
+    assert_eq!(child.queue_id, queue_id);
+    assert_eq!(
+        child.parent_workflow_id.as_deref(),
+        Some(parent_id.as_str())
+    );
+
+    let history = store.workflow_history(&charge_id).await?;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].payload.as_deref(), Some(&b"10"[..]));
+
+    let pending = store
+        .query_tasks(&queue_id, 0, TaskStatus::Pending, 10)
+        .await?;
+    assert_eq!(pending.len(), 3);
+
+    let refund = [NewWorkflowEvent::child_workflow_started(
+        "refund",
+        b"10".to_vec(),
+    )];
+    assert_eq!(
+        store.append_workflow_events(&parent_id, 2, &refund).await?,
+        Some(3)
+    );
+    let refund_id = child_workflow_id(&parent_id, 3);
+
+    // Finishing a child records how in its parent and wakes the parent.
+    let completed = [NewWorkflowEvent::workflow_completed(b"charged".to_vec())];
+    assert_eq!(
+        store
+            .append_workflow_events(&charge_id, 1, &completed)
+            .await?,
+        Some(2)
+    );
+
+    let failed = [NewWorkflowEvent::workflow_failed("no card")];
+    assert_eq!(
+        store.append_workflow_events(&refund_id, 1, &failed).await?,
+        Some(2)
+    );
+
+    let history = store.workflow_history(&parent_id).await?;
+    assert_eq!(history.len(), 5);
+
+    assert_eq!(history[3].kind, WorkflowEventKind::ChildWorkflowCompleted);
+    assert_eq!(history[3].name.as_deref(), Some(charge_id.as_str()));
+    assert_eq!(history[3].payload.as_deref(), Some(&b"charged"[..]));
+
+    assert_eq!(history[4].kind, WorkflowEventKind::ChildWorkflowFailed);
+    assert_eq!(history[4].name.as_deref(), Some(refund_id.as_str()));
+    assert_eq!(history[4].error.as_deref(), Some("no card"));
+
+    let pending = store
+        .query_tasks(&queue_id, 0, TaskStatus::Pending, 10)
+        .await?;
+    assert_eq!(pending.len(), 7);
+
+    let parent = store
+        .get_workflow(&parent_id)
+        .await?
+        .expect("workflow exists");
+    assert_eq!(parent.last_event_id, 5);
+    assert_eq!(parent.parent_workflow_id, None);
+
+    Ok(())
+}
+
+pub async fn task_definitions_are_versioned<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let definition_id = nanoid!();
+
+    let resize = NewTaskDefinition {
+        definition_id: &definition_id,
+        name: "Resize image",
+        description: "Resizes an uploaded image",
+        parameters: r#"{"type":"object","required":["url"]}"#,
+    };
+
+    let first = store.register_task_definition(&resize).await?;
+    assert_eq!(first.definition_id, definition_id);
+    assert_eq!(first.version, 1);
+    assert!(first.matches(&resize));
+
+    // Registering the same definition again, as every deploy does, keeps its version.
+    assert_eq!(store.register_task_definition(&resize).await?, first);
+
+    let with_width = NewTaskDefinition {
+        parameters: r#"{"type":"object","required":["url","width"]}"#,
+        ..resize
+    };
+    let second = store.register_task_definition(&with_width).await?;
+    assert_eq!(second.version, 2);
+
+    assert_eq!(
+        store.get_task_definition(&definition_id, None).await?,
+        Some(second.clone())
+    );
+    assert_eq!(
+        store.get_task_definition(&definition_id, Some(1)).await?,
+        Some(first)
+    );
+    assert_eq!(
+        store.get_task_definition(&definition_id, Some(3)).await?,
+        None
+    );
+    assert_eq!(store.get_task_definition(&nanoid!(), None).await?, None);
+
+    let listed = store.list_task_definitions().await?;
+    let ours = listed
+        .into_iter()
+        .filter(|definition| definition.definition_id == definition_id)
+        .collect::<Vec<_>>();
+    assert_eq!(ours, vec![second]);
+
+    // Tasks keep the definition version their payload was checked against.
+    let queue_id = nanoid!();
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![NewTask {
+                definition_id: Some(&definition_id),
+                definition_version: Some(1),
+                ..NewTask::new(br#"{"url":"a.png"}"#)
+            }],
+        )
+        .await?;
+
+    let task = store.get_task(&ids[0]).await?.expect("task exists");
+    assert_eq!(task.definition_id.as_deref(), Some(definition_id.as_str()));
+    assert_eq!(task.definition_version, Some(1));
+
+    Ok(())
+}
+
+/// Not part of the suite, for backends configured with one second of priority aging. A task
+/// that has waited ten seconds is lifted above a newer, more urgent one.
+pub async fn starved_tasks_are_aged_ahead<Q: TaskQueue + Sync>(store: &Q, now: i64) -> Result<()> {
+    let queue_id = nanoid!();
+
+    let ids = store
+        .enqueue_tasks(
+            &queue_id,
+            0,
+            vec![
+                NewTask {
+                    priority: 5,
+                    scheduled_at: now - 1_000,
+                    ..NewTask::new(b"urgent")
+                },
+                NewTask {
+                    priority: 0,
+                    scheduled_at: now - 10_000,
+                    ..NewTask::new(b"backfill")
+                },
+            ],
+        )
+        .await?;
+
+    let leased = store.lease_tasks(&queue_id, 0, 1, 60_000).await?;
+    assert_eq!(leased[0].task.task_id, ids[1]);
+
+    Ok(())
+}
+
+/// Not part of the suite, for backends sweeping each partition for expired tasks at most once
+/// a minute. A lapsed lease stays put until the next sweep, the claim itself does not look.
+pub async fn lapsed_leases_wait_for_the_next_sweep<Q: TaskQueue + Sync>(store: &Q) -> Result<()> {
+    let queue_id = nanoid!();
+    let ids = store
+        .enqueue_tasks(&queue_id, 0, vec![NewTask::new(b"payload")])
+        .await?;
+
+    assert_eq!(store.lease_tasks(&queue_id, 0, 1, 0).await?.len(), 1);
+    assert!(store.lease_tasks(&queue_id, 0, 1, 60_000).await?.is_empty());
+
+    let task = store.get_task(&ids[0]).await?.unwrap();
+
+    assert_eq!(task.status, TaskStatus::Leased);
+
+    Ok(())
+}
@@ -0,0 +1,269 @@
+use std::time::Duration;
+
+use crate::persistence::conformance::task_queue_conformance;
+use anyhow::Result;
+use server_lib::persistence::{
+    common::{
+        Heartbeat, LeaseHeartbeat, LeaseState, ManualClock, NewTask, RetryPolicy, TaskQueue,
+        TaskStatus,
+    },
+    memory::InMemoryTaskQueue,
+};
+
+const START: i64 = 1_700_000_000_000;
+
+fn start_store() -> (ManualClock, InMemoryTaskQueue<ManualClock>) {
+    let clock = ManualClock::new(START);
+
+    (clock.clone(), InMemoryTaskQueue::with_clock(clock))
+}
+
+#[tokio::test]
+async fn delayed_tasks_wait_for_the_clock() -> Result<()> {
+    let (clock, store) = start_store();
+
+    let task_ids = store
+        .enqueue_tasks(
+            "queue",
+            0,
+            vec![NewTask {
+                scheduled_at: START + 1_000,
+                ..NewTask::new(b"later")
+            }],
+        )
+        .await?;
+
+    assert!(store.lease_tasks("queue", 0, 1, 60_000).await?.is_empty());
+
+    clock.advance(Duration::from_millis(1_000));
+
+    let leased = store.lease_tasks("queue", 0, 1, 60_000).await?;
+
+    assert_eq!(leased[0].task.task_id, task_ids[0]);
+    assert_eq!(leased[0].lease_expires_at, START + 61_000);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn expired_leases_return_to_the_queue() -> Result<()> {
+    let (clock, store) = start_store();
+
+    let task_ids = store
+        .enqueue_tasks("queue", 0, vec![NewTask::new(b"payload")])
+        .await?;
+
+    let expired = store.lease_tasks("queue", 0, 1, 1_000).await?;
+
+    assert!(store.lease_tasks("queue", 0, 1, 1_000).await?.is_empty());
+
+    clock.advance(Duration::from_millis(1_000));
+
+    let reclaimed = store.lease_tasks("queue", 0, 1, 1_000).await?;
+
+    assert_eq!(reclaimed[0].task.task_id, task_ids[0]);
+    assert_eq!(reclaimed[0].task.attempts, 2);
+    assert_eq!(
+        reclaimed[0].task.last_error.as_deref(),
+        Some("lease expired")
+    );
+    assert!(
+        !store
+            .ack_task(&task_ids[0], &expired[0].lease_token, None)
+            .await?
+    );
+    assert!(
+        store
+            .ack_task(&task_ids[0], &reclaimed[0].lease_token, None)
+            .await?
+    );
+
+    let succeeded = store
+        .query_tasks("queue", 0, TaskStatus::Succeeded, 10)
+        .await?;
+
+    assert_eq!(succeeded[0].task_id, task_ids[0]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn nacked_tasks_back_off_then_dead_letter() -> Result<()> {
+    let (clock, store) = start_store();
+
+    store
+        .set_retry_policy(
+            "queue",
+            &RetryPolicy {
+                max_attempts: 2,
+                initial_backoff_ms: 1_000,
+                jitter: 0.0,
+                ..RetryPolicy::default()
+            },
+        )
+        .await?;
+
+    let task_ids = store
+        .enqueue_tasks("queue", 0, vec![NewTask::new(b"payload")])
+        .await?;
+
+    let leased = store.lease_tasks("queue", 0, 1, 60_000).await?;
+    assert!(
+        store
+            .nack_task(&task_ids[0], &leased[0].lease_token, "first")
+            .await?
+    );
+
+    let scheduled = store
+        .query_tasks("queue", 0, TaskStatus::Scheduled, 10)
+        .await?;
+
+    assert_eq!(scheduled[0].scheduled_at, START + 1_000);
+    assert!(store.lease_tasks("queue", 0, 1, 60_000).await?.is_empty());
+
+    clock.advance(Duration::from_millis(1_000));
+
+    let leased = store.lease_tasks("queue", 0, 1, 60_000).await?;
+    assert!(
+        store
+            .nack_task(&task_ids[0], &leased[0].lease_token, "second")
+            .await?
+    );
+
+    let dead = store.dead_lettered_tasks("queue", 0, 10).await?;
+
+    assert_eq!(dead[0].attempts, 2);
+    assert_eq!(dead[0].last_error.as_deref(), Some("second"));
+
+    assert_eq!(store.redrive_all_tasks("queue", 0).await?, 1);
+    assert_eq!(store.lease_tasks("queue", 0, 1, 60_000).await?.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn tasks_past_their_deadline_are_failed() -> Result<()> {
+    let (clock, store) = start_store();
+
+    let task_ids = store
+        .enqueue_tasks(
+            "queue",
+            0,
+            vec![NewTask {
+                scheduled_at: START + 2_000,
+                deadline_at: Some(START + 1_000),
+                ..NewTask::new(b"too late")
+            }],
+        )
+        .await?;
+
+    clock.advance(Duration::from_millis(2_000));
+
+    assert!(store.lease_tasks("queue", 0, 1, 60_000).await?.is_empty());
+
+    let failed = store
+        .query_tasks("queue", 0, TaskStatus::Failed, 10)
+        .await?;
+
+    assert_eq!(failed[0].task_id, task_ids[0]);
+    assert_eq!(failed[0].last_error.as_deref(), Some("deadline exceeded"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn partitions_are_independent() -> Result<()> {
+    let (_clock, store) = start_store();
+
+    store
+        .enqueue_tasks("queue", 0, vec![NewTask::new(b"zero")])
+        .await?;
+    store
+        .enqueue_tasks("queue", 1, vec![NewTask::new(b"one")])
+        .await?;
+
+    let leased = store.lease_tasks("queue", 1, 10, 60_000).await?;
+
+    assert_eq!(leased.len(), 1);
+    assert_eq!(leased[0].task.payload, b"one");
+    assert_eq!(
+        store
+            .query_tasks("queue", 0, TaskStatus::Pending, 10)
+            .await?
+            .len(),
+        1
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn idempotency_keys_expire_after_retention() -> Result<()> {
+    let clock = ManualClock::new(START);
+    let store = InMemoryTaskQueue::with_clock(clock.clone())
+        .with_idempotency_retention(Duration::from_secs(60));
+    let task = NewTask {
+        idempotency_key: Some("key"),
+        ..NewTask::new(b"payload")
+    };
+
+    let first = store.enqueue_tasks("queue", 0, vec![task]).await?;
+
+    clock.advance(Duration::from_secs(59));
+    assert_eq!(store.enqueue_tasks("queue", 0, vec![task]).await?, first);
+
+    clock.advance(Duration::from_secs(1));
+    assert_ne!(store.enqueue_tasks("queue", 0, vec![task]).await?, first);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn starved_tasks_are_aged_ahead() -> Result<()> {
+    let store = InMemoryTaskQueue::with_clock(ManualClock::new(START))
+        .with_priority_aging(Duration::from_secs(1));
+
+    crate::persistence::conformance::starved_tasks_are_aged_ahead(&store, START).await
+}
+
+#[tokio::test]
+async fn missed_heartbeats_let_the_lease_lapse() -> Result<()> {
+    let (clock, store) = start_store();
+
+    let task_ids = store
+        .enqueue_tasks("queue", 0, vec![NewTask::new(b"payload")])
+        .await?;
+
+    let leased = store.lease_tasks("queue", 0, 1, 1_000).await?;
+    let heartbeat = LeaseHeartbeat::new(&store, &leased[0], 1_000);
+
+    clock.advance(Duration::from_millis(900));
+    assert_eq!(heartbeat.beat(None).await?, LeaseState::Held);
+
+    // Renewed until START + 1_900, so the original expiry passes without reclaiming it.
+    clock.advance(Duration::from_millis(900));
+    assert!(store.lease_tasks("queue", 0, 1, 1_000).await?.is_empty());
+
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(heartbeat.beat(None).await?, LeaseState::Lost);
+
+    let reclaimed = store.lease_tasks("queue", 0, 1, 1_000).await?;
+
+    assert_eq!(reclaimed[0].task.task_id, task_ids[0]);
+    assert_eq!(
+        reclaimed[0].task.last_error.as_deref(),
+        Some("lease expired")
+    );
+
+    // Heartbeats are how processors hear about cancellation.
+    let heartbeat = LeaseHeartbeat::new(&store, &reclaimed[0], 1_000);
+
+    assert!(store.cancel_task(&task_ids[0]).await?);
+    assert_eq!(heartbeat.beat(None).await?, LeaseState::Cancelled);
+
+    Ok(())
+}
+
+task_queue_conformance!(store => {
+    let store = InMemoryTaskQueue::new();
+});
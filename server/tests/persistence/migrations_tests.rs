@@ -0,0 +1,54 @@
+use anyhow::Result;
+use server_lib::persistence::{
+    migrations::{self, Migration},
+    postgres, sqlite,
+};
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "first",
+        sql: "",
+    },
+    Migration {
+        version: 2,
+        description: "second",
+        sql: "",
+    },
+];
+
+fn versions(migrations: &[&Migration]) -> Vec<i64> {
+    migrations.iter().map(|m| m.version).collect()
+}
+
+#[test]
+fn pending_skips_applied_migrations() -> Result<()> {
+    assert_eq!(versions(&migrations::pending(MIGRATIONS, &[])?), vec![1, 2]);
+    assert_eq!(versions(&migrations::pending(MIGRATIONS, &[1])?), vec![2]);
+    assert!(migrations::pending(MIGRATIONS, &[1, 2])?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn pending_rejects_newer_schemas() {
+    assert!(migrations::pending(MIGRATIONS, &[1, 2, 3]).is_err());
+}
+
+#[test]
+fn status_reports_when_migrations_were_applied() {
+    let status = migrations::status(MIGRATIONS, &[(1, 1_000)]);
+
+    assert_eq!(status[0].applied_at, Some(1_000));
+    assert_eq!(status[1].applied_at, None);
+    assert_eq!(status[1].description, "second");
+}
+
+#[test]
+fn backend_migrations_are_numbered_consecutively() {
+    for migrations in [postgres::MIGRATIONS, sqlite::MIGRATIONS] {
+        for (i, migration) in migrations.iter().enumerate() {
+            assert_eq!(migration.version, i as i64 + 1);
+        }
+    }
+}
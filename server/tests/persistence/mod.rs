@@ -1,2 +1,7 @@
+pub mod common_tests;
+pub mod conformance;
+pub mod memory_tests;
+pub mod migrations_tests;
 pub mod postgres_image;
 pub mod postgres_tests;
+pub mod sqlite_tests;
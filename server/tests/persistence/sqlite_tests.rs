@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use crate::persistence::conformance::task_queue_conformance;
+use anyhow::Result;
+use nanoid::nanoid;
+use server_lib::persistence::{
+    common::{now_millis, NewTask, TaskQueue, TaskStatus},
+    sqlite::{self, PersistenceSqlite},
+};
+
+async fn start_store() -> Result<PersistenceSqlite> {
+    let pool = sqlite::create_connection_pool("sqlite::memory:").await?;
+    let store = PersistenceSqlite::new(pool);
+
+    store.migrate().await?;
+
+    Ok(store)
+}
+
+#[test]
+fn sqlite_urls_select_the_sqlite_backend() {
+    assert!(sqlite::is_sqlite_url("sqlite::memory:"));
+    assert!(sqlite::is_sqlite_url("sqlite://tasks.db"));
+    assert!(!sqlite::is_sqlite_url("postgres://localhost/svppl"));
+}
+
+#[tokio::test]
+async fn insert_and_query_tasks() -> Result<()> {
+    let store = start_store().await?;
+
+    let queue_id = nanoid!();
+    let payloads: Vec<String> = (0..10).map(|i| format!("payload {}", i)).collect();
+
+    let task_ids = store
+        .enqueue_tasks(
+            queue_id.as_str(),
+            0,
+            payloads
+                .iter()
+                .map(|s| NewTask::new(s.as_bytes()))
+                .collect(),
+        )
+        .await?;
+
+    let tasks = store
+        .query_tasks(queue_id.as_str(), 0, TaskStatus::Pending, 10)
+        .await?;
+    let first = tasks.first().unwrap();
+
+    assert_eq!(tasks.len(), 10);
+    assert_eq!(first.task_id, task_ids[0]);
+    assert_eq!(String::from_utf8(first.payload.clone())?, "payload 0");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn tasks_persist_in_a_database_file() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("svppl-{}.db", nanoid!()));
+    let url = format!("sqlite://{}", path.display());
+    let queue_id = nanoid!();
+
+    {
+        let store = PersistenceSqlite::new(sqlite::create_connection_pool(&url).await?);
+        store.migrate().await?;
+        store
+            .enqueue_tasks(queue_id.as_str(), 0, vec![NewTask::new(b"payload")])
+            .await?;
+    }
+
+    let store = PersistenceSqlite::new(sqlite::create_connection_pool(&url).await?);
+    store.migrate().await?;
+
+    let tasks = store
+        .query_tasks(queue_id.as_str(), 0, TaskStatus::Pending, 10)
+        .await?;
+
+    std::fs::remove_file(&path).ok();
+
+    assert_eq!(tasks.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn migrations_apply_once() -> Result<()> {
+    let store = PersistenceSqlite::new(sqlite::create_connection_pool("sqlite::memory:").await?);
+
+    let status = store.migration_status().await?;
+    assert!(status
+        .iter()
+        .all(|migration| migration.applied_at.is_none()));
+
+    let applied = store.migrate().await?;
+    assert_eq!(applied.len(), sqlite::MIGRATIONS.len());
+    assert!(store.migrate().await?.is_empty());
+
+    let status = store.migration_status().await?;
+    assert!(status
+        .iter()
+        .all(|migration| migration.applied_at.is_some()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn concurrent_migrations_apply_once() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("svppl-{}.db", nanoid!()));
+    let url = format!("sqlite://{}", path.display());
+
+    let stores =
+        futures::future::try_join_all((0..4).map(|_| sqlite::create_connection_pool(&url)))
+            .await?
+            .into_iter()
+            .map(PersistenceSqlite::new)
+            .collect::<Vec<_>>();
+
+    let applied = futures::future::try_join_all(stores.iter().map(|store| store.migrate())).await?;
+
+    std::fs::remove_file(&path).ok();
+
+    let total: usize = applied.iter().map(Vec::len).sum();
+    assert_eq!(total, sqlite::MIGRATIONS.len());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn idempotency_keys_expire_after_retention() -> Result<()> {
+    let store = start_store()
+        .await?
+        .with_idempotency_retention(Duration::ZERO);
+    let task = NewTask {
+        idempotency_key: Some("key"),
+        ..NewTask::new(b"payload")
+    };
+
+    let first = store.enqueue_tasks("queue", 0, vec![task]).await?;
+    let second = store.enqueue_tasks("queue", 0, vec![task]).await?;
+
+    assert_ne!(first, second);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn starved_tasks_are_aged_ahead() -> Result<()> {
+    let store = start_store()
+        .await?
+        .with_priority_aging(Duration::from_secs(1));
+
+    crate::persistence::conformance::starved_tasks_are_aged_ahead(&store, now_millis()).await
+}
+
+#[tokio::test]
+async fn lapsed_leases_wait_for_the_next_sweep() -> Result<()> {
+    let store = start_store()
+        .await?
+        .with_expiry_interval(Duration::from_secs(60));
+
+    crate::persistence::conformance::lapsed_leases_wait_for_the_next_sweep(&store).await
+}
+
+// The checks lease again straight after tasks expire.
+task_queue_conformance!(store => {
+    let store = start_store().await?.with_expiry_interval(Duration::ZERO);
+});
@@ -0,0 +1,238 @@
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use chitchat::ChitchatId;
+use server_lib::{
+    cluster_monitor::{ClusterNode, ClusterNodeId, ClusterStateChange},
+    partition_resolver::PartitionResolver,
+    persistence::{
+        common::{
+            ManualClock, MisfirePolicy, NewWorkflow, NewWorkflowEvent, Schedule, TaskQueue,
+            TaskStatus,
+        },
+        memory::InMemoryTaskQueue,
+    },
+    scheduler::{self, CronSchedule, MAX_CATCH_UP_RUNS},
+};
+
+/// 2024-07-01T00:00:00Z, when London is on summer time.
+const JULY: i64 = 1_719_792_000_000;
+
+const MINUTE: i64 = 60_000;
+const HOUR: i64 = 60 * MINUTE;
+
+fn every_five_minutes(misfire_policy: MisfirePolicy) -> Schedule {
+    Schedule {
+        schedule_id: "every-five-minutes".to_string(),
+        cron: "*/5 * * * *".to_string(),
+        timezone: "UTC".to_string(),
+        queue_id: "queue".to_string(),
+        partition_id: 0,
+        payload: b"tick".to_vec(),
+        content_type: None,
+        misfire_policy,
+        next_fire_at: JULY,
+    }
+}
+
+/// A resolver for `self`, on a ring it shares with `other`.
+async fn resolver_with_peer() -> Result<PartitionResolver> {
+    let mut resolver = PartitionResolver::new(ClusterNodeId("self".to_string()), 50, (0, 0));
+    let mut changes = Vec::new();
+
+    for (node_id, port) in [("self", 50051), ("other", 50052)] {
+        let grpc_endpoint = SocketAddr::from(([127, 0, 0, 1], port));
+        let chitchat_id = ChitchatId::new(node_id.to_string(), 1, grpc_endpoint);
+
+        changes.push(ClusterStateChange::Added(ClusterNode::new(
+            &chitchat_id,
+            grpc_endpoint,
+        )?));
+    }
+
+    resolver.sync(&changes).await;
+
+    Ok(resolver)
+}
+
+/// `count` ids starting with `prefix` that are placed on this node, or on the other one.
+async fn ids_placed(
+    resolver: &PartitionResolver,
+    prefix: &str,
+    local: bool,
+    count: usize,
+) -> Vec<String> {
+    let mut ids = Vec::with_capacity(count);
+
+    for id in (0..).map(|i| format!("{}-{}", prefix, i)) {
+        if ids.len() == count {
+            break;
+        }
+
+        if resolver.is_local(id.as_bytes()).await == local {
+            ids.push(id);
+        }
+    }
+
+    ids
+}
+
+#[test]
+fn cron_expressions_fire_in_their_time_zone() -> Result<()> {
+    let utc = CronSchedule::parse("0 9 * * *", "UTC")?;
+    let london = CronSchedule::parse("0 9 * * *", "Europe/London")?;
+
+    assert_eq!(utc.next_after(JULY), Some(JULY + 9 * HOUR));
+    assert_eq!(london.next_after(JULY), Some(JULY + 8 * HOUR));
+
+    // A leading seconds field is accepted too.
+    let seconds = CronSchedule::parse("30 * * * * *", "UTC")?;
+    assert_eq!(seconds.next_after(JULY), Some(JULY + 30_000));
+
+    assert!(CronSchedule::parse("not a cron", "UTC").is_err());
+    assert!(CronSchedule::parse("0 9 * * *", "Mars/Olympus_Mons").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn missed_runs_follow_the_misfire_policy() -> Result<()> {
+    let cron = CronSchedule::parse("*/5 * * * *", "UTC")?;
+    let now = JULY + 12 * MINUTE;
+
+    let (runs, next) = scheduler::due_runs(&cron, &every_five_minutes(MisfirePolicy::CatchUp), now);
+    assert_eq!(runs, vec![JULY, JULY + 5 * MINUTE, JULY + 10 * MINUTE]);
+    assert_eq!(next, JULY + 15 * MINUTE);
+
+    let (runs, next) = scheduler::due_runs(&cron, &every_five_minutes(MisfirePolicy::Skip), now);
+    assert_eq!(runs, vec![JULY + 10 * MINUTE]);
+    assert_eq!(next, JULY + 15 * MINUTE);
+
+    // Catching up on a long outage is spread over several fires.
+    let cron = CronSchedule::parse("* * * * * *", "UTC")?;
+    let schedule = Schedule {
+        cron: "* * * * * *".to_string(),
+        ..every_five_minutes(MisfirePolicy::CatchUp)
+    };
+
+    let (runs, next) = scheduler::due_runs(&cron, &schedule, JULY + HOUR);
+    assert_eq!(runs.len(), MAX_CATCH_UP_RUNS);
+    assert_eq!(next, JULY + MAX_CATCH_UP_RUNS as i64 * 1_000);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn each_run_is_enqueued_once() -> Result<()> {
+    let now = JULY + 12 * MINUTE;
+    let store = InMemoryTaskQueue::with_clock(ManualClock::new(now));
+    let schedule = every_five_minutes(MisfirePolicy::CatchUp);
+
+    store.put_schedule(&schedule).await?;
+
+    // Two nodes both think they own the schedule and fire the same snapshot of it.
+    assert_eq!(scheduler::fire_schedule(&store, &schedule, now).await?, 3);
+    assert_eq!(scheduler::fire_schedule(&store, &schedule, now).await?, 0);
+
+    let tasks = store
+        .query_tasks("queue", 0, TaskStatus::Pending, 10)
+        .await?;
+    let fired_at: Vec<i64> = tasks.iter().map(|task| task.scheduled_at).collect();
+
+    assert_eq!(fired_at, vec![JULY, JULY + 5 * MINUTE, JULY + 10 * MINUTE]);
+    assert!(tasks.iter().all(|task| task.payload == b"tick"));
+
+    let advanced = store
+        .get_schedule(&schedule.schedule_id)
+        .await?
+        .expect("schedule exists");
+    assert_eq!(advanced.next_fire_at, JULY + 15 * MINUTE);
+    assert!(store.due_schedules(now, None, 10).await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn remote_schedules_do_not_crowd_out_local_ones() -> Result<()> {
+    let now = JULY + 12 * MINUTE;
+    let store = InMemoryTaskQueue::with_clock(ManualClock::new(now));
+    let resolver = resolver_with_peer().await?;
+
+    // More than a page of schedules placed on the other node fall due first.
+    for schedule_id in ids_placed(&resolver, "remote", false, 1_001).await {
+        store
+            .put_schedule(&Schedule {
+                schedule_id,
+                next_fire_at: JULY - HOUR,
+                ..every_five_minutes(MisfirePolicy::Skip)
+            })
+            .await?;
+    }
+
+    let local = Schedule {
+        schedule_id: ids_placed(&resolver, "local", true, 1).await.remove(0),
+        ..every_five_minutes(MisfirePolicy::Skip)
+    };
+    store.put_schedule(&local).await?;
+
+    scheduler::fire_local_schedules(&store, &resolver, now).await?;
+
+    let fired = store
+        .get_schedule(&local.schedule_id)
+        .await?
+        .expect("schedule exists");
+    assert_eq!(fired.next_fire_at, JULY + 15 * MINUTE);
+
+    // The other node's schedules are left to it.
+    assert_eq!(store.due_schedules(now, None, 2_000).await?.len(), 1_001);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn remote_workflow_timers_do_not_crowd_out_local_ones() -> Result<()> {
+    let store = InMemoryTaskQueue::with_clock(ManualClock::new(JULY));
+    let resolver = resolver_with_peer().await?;
+
+    let remote = ids_placed(&resolver, "remote", false, 1_001).await;
+    let local = ids_placed(&resolver, "local", true, 1).await;
+
+    // More than a page of timers on workflows placed on the other node fall due first.
+    for (workflow_id, fire_at) in remote
+        .iter()
+        .map(|workflow_id| (workflow_id, JULY - HOUR))
+        .chain(local.iter().map(|workflow_id| (workflow_id, JULY)))
+    {
+        let workflow = NewWorkflow {
+            workflow_id,
+            workflow_type: "reminder",
+            queue_id: "queue",
+            partition_id: 0,
+            input: b"",
+        };
+
+        assert!(store.start_workflow(&workflow).await?);
+        assert_eq!(
+            store
+                .append_workflow_events(
+                    workflow_id,
+                    1,
+                    &[NewWorkflowEvent::timer_started("sleep", fire_at)],
+                )
+                .await?,
+            Some(2)
+        );
+    }
+
+    scheduler::fire_local_timers(&store, &resolver, JULY).await?;
+
+    let history = store.workflow_history(&local[0]).await?;
+    assert_eq!(history.len(), 3);
+
+    // The other node's timers are left to it.
+    let due = store.due_workflow_timers(JULY, None, 2_000).await?;
+    assert_eq!(due.len(), 1_001);
+    assert!(due.iter().all(|timer| remote.contains(&timer.workflow_id)));
+
+    Ok(())
+}
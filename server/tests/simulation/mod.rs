@@ -1,4 +1,5 @@
 mod cluster_monitor_transport;
+mod task_queue;
 
 #[test]
 fn basic_test() {
@@ -0,0 +1,94 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use server_lib::persistence::{
+    common::{
+        Clock, Heartbeat, NewTask, TaskData, TaskPayload, TaskProcessor, TaskQueue, TaskStatus,
+    },
+    memory::InMemoryTaskQueue,
+};
+
+const EPOCH: i64 = 1_700_000_000_000;
+
+/// Follows tokio's clock, which turmoil drives, so delays elapse in simulated time.
+struct SimClock {
+    start: tokio::time::Instant,
+}
+
+impl Clock for SimClock {
+    fn now_millis(&self) -> i64 {
+        EPOCH + self.start.elapsed().as_millis() as i64
+    }
+}
+
+#[derive(Default)]
+struct CountingProcessor {
+    processed: AtomicUsize,
+}
+
+#[async_trait]
+impl TaskProcessor for CountingProcessor {
+    async fn process_task(
+        &self,
+        _task: TaskData,
+        _heartbeat: &dyn Heartbeat,
+    ) -> anyhow::Result<Option<TaskPayload>> {
+        self.processed.fetch_add(1, Ordering::SeqCst);
+        Ok(None)
+    }
+}
+
+#[test]
+fn delayed_tasks_run_in_simulated_time() -> turmoil::Result {
+    let mut sim = turmoil::Builder::new()
+        .simulation_duration(Duration::from_secs(120))
+        .build();
+
+    sim.client("worker", async {
+        let queue = InMemoryTaskQueue::with_clock(SimClock {
+            start: tokio::time::Instant::now(),
+        });
+        let processor = Arc::new(CountingProcessor::default());
+
+        queue
+            .enqueue_tasks(
+                "queue",
+                0,
+                vec![
+                    NewTask::new(b"now"),
+                    NewTask {
+                        scheduled_at: EPOCH + 30_000,
+                        ..NewTask::new(b"later")
+                    },
+                ],
+            )
+            .await?;
+
+        queue
+            .process_tasks("queue", 0, 10, processor.as_ref())
+            .await?;
+        assert_eq!(processor.processed.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_secs(30)).await;
+
+        queue
+            .process_tasks("queue", 0, 10, processor.as_ref())
+            .await?;
+        assert_eq!(processor.processed.load(Ordering::SeqCst), 2);
+
+        let succeeded = queue
+            .query_tasks("queue", 0, TaskStatus::Succeeded, 10)
+            .await?;
+        assert_eq!(succeeded.len(), 2);
+
+        Ok(())
+    });
+
+    sim.run()
+}
@@ -0,0 +1,100 @@
+use anyhow::Result;
+use server_lib::{
+    persistence::common::TaskDefinition,
+    task_definition::{normalize_parameters, InvalidPayload, PayloadValidator, PayloadViolation},
+};
+
+fn resize_image() -> TaskDefinition {
+    TaskDefinition {
+        definition_id: "resize-image".to_string(),
+        version: 2,
+        name: "Resize image".to_string(),
+        description: "Resizes an uploaded image".to_string(),
+        parameters: r#"{
+            "type": "object",
+            "properties": {
+                "url": { "type": "string" },
+                "width": { "type": "integer", "minimum": 1 }
+            },
+            "required": ["url", "width"]
+        }"#
+        .to_string(),
+        created_at: 0,
+    }
+}
+
+fn violations(result: Result<()>) -> Vec<PayloadViolation> {
+    let err = result.expect_err("payload is rejected");
+    let invalid = err
+        .downcast::<InvalidPayload>()
+        .expect("rejected as an invalid payload");
+
+    assert_eq!(invalid.definition_id, "resize-image");
+    assert_eq!(invalid.version, 2);
+
+    invalid.violations
+}
+
+#[test]
+fn parameters_are_normalized() -> Result<()> {
+    let parameters = normalize_parameters("{ \"type\" : \"object\" }")?;
+    assert_eq!(parameters, r#"{"type":"object"}"#);
+
+    Ok(())
+}
+
+#[test]
+fn parameters_must_be_a_json_schema() {
+    assert!(normalize_parameters("not json").is_err());
+    assert!(normalize_parameters(r#"{"type":"no-such-type"}"#).is_err());
+}
+
+#[test]
+fn matching_payloads_are_accepted() -> Result<()> {
+    let validator = PayloadValidator::default();
+
+    validator.check(
+        &resize_image(),
+        br#"{"url":"https://example.com/a.png","width":640}"#,
+    )?;
+
+    Ok(())
+}
+
+#[test]
+fn every_violation_is_reported_with_its_pointer() {
+    let validator = PayloadValidator::default();
+
+    let err = validator
+        .check(&resize_image(), br#"{"width":0}"#)
+        .expect_err("payload is rejected");
+    let message = err.to_string();
+
+    let violations = violations(Err(err));
+    assert_eq!(violations.len(), 2);
+
+    let missing = violations
+        .iter()
+        .find(|violation| violation.pointer.is_empty())
+        .expect("missing property is reported");
+    assert!(missing.description.contains("\"url\""));
+
+    let width = violations
+        .iter()
+        .find(|violation| violation.pointer == "/width")
+        .expect("minimum is reported");
+
+    assert!(message.starts_with("payload does not match task definition resize-image version 2: "));
+    assert!(message.contains(&format!("payload/width: {}", width.description)));
+}
+
+#[test]
+fn payloads_must_be_json() {
+    let validator = PayloadValidator::default();
+
+    let violations = violations(validator.check(&resize_image(), b"\x00\x01"));
+
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].pointer, "");
+    assert!(violations[0].description.starts_with("not JSON: "));
+}
@@ -0,0 +1,507 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use server_lib::{
+    persistence::{
+        common::{
+            now_millis, NewWorkflow, TaskPayload, TaskQueue, TaskStatus, WorkflowEventKind,
+            WorkflowState, WorkflowStatus,
+        },
+        memory::InMemoryTaskQueue,
+    },
+    workflow::{WorkflowContext, WorkflowFn, WorkflowWorker},
+};
+
+const QUEUE: &str = "workflows";
+
+fn parse(payload: &[u8]) -> Result<i64> {
+    Ok(std::str::from_utf8(payload)?.trim().parse()?)
+}
+
+/// Adds its input up one number at a time, counting every step it actually runs.
+#[derive(Clone, Default)]
+struct Adds {
+    runs: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl WorkflowFn for Adds {
+    async fn run(&self, context: &mut WorkflowContext) -> Result<TaskPayload> {
+        let numbers = std::str::from_utf8(context.input())?
+            .split(',')
+            .map(|number| number.parse::<i64>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut total = 0;
+
+        for number in numbers {
+            let runs = self.runs.clone();
+
+            let sum = context
+                .step("add", || async move {
+                    runs.fetch_add(1, Ordering::SeqCst);
+
+                    if number < 0 {
+                        return Err(anyhow::anyhow!("negative number: {}", number));
+                    }
+
+                    Ok((total + number).to_string().into_bytes())
+                })
+                .await?;
+
+            total = parse(&sum)?;
+        }
+
+        Ok(total.to_string().into_bytes())
+    }
+}
+
+/// Drafts a reminder, sleeps for an hour and sends it.
+#[derive(Clone, Default)]
+struct Reminder {
+    runs: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl WorkflowFn for Reminder {
+    async fn run(&self, context: &mut WorkflowContext) -> Result<TaskPayload> {
+        let runs = self.runs.clone();
+        let draft = context
+            .step("draft", || async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                Ok(b"water the plants".to_vec())
+            })
+            .await?;
+
+        context.sleep(Duration::from_secs(60 * 60)).await?;
+
+        let runs = self.runs.clone();
+        context
+            .step("send", || async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                Ok(draft)
+            })
+            .await
+    }
+}
+
+/// Drafts a post and publishes it once two approvals are signalled.
+#[derive(Clone, Default)]
+struct Approval {
+    runs: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl WorkflowFn for Approval {
+    async fn run(&self, context: &mut WorkflowContext) -> Result<TaskPayload> {
+        let runs = self.runs.clone();
+        let draft = context
+            .step("draft", || async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                Ok(b"draft".to_vec())
+            })
+            .await?;
+
+        let first = context.wait_for_signal("approve").await?;
+        let second = context.wait_for_signal("approve").await?;
+
+        let runs = self.runs.clone();
+        context
+            .step("publish", || async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+
+                let mut post = draft;
+                post.extend_from_slice(b" approved by ");
+                post.extend_from_slice(&first);
+                post.extend_from_slice(b" and ");
+                post.extend_from_slice(&second);
+
+                Ok(post)
+            })
+            .await
+    }
+}
+
+/// Reserves stock and notifies the warehouse, then charges the order in a child workflow.
+#[derive(Clone, Default)]
+struct Order {
+    /// The steps compensated, in the order they were undone.
+    undone: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait]
+impl WorkflowFn for Order {
+    async fn run(&self, context: &mut WorkflowContext) -> Result<TaskPayload> {
+        for name in ["reserve", "notify"] {
+            let undone = self.undone.clone();
+
+            context
+                .step_with_compensation(
+                    name,
+                    || async { Ok(b"done".to_vec()) },
+                    move |_| async move {
+                        undone.lock().unwrap().push(name.to_string());
+                        Ok(())
+                    },
+                )
+                .await?;
+        }
+
+        let amount = context.input().to_vec();
+
+        context.child_workflow("charge", amount).await
+    }
+}
+
+/// Charges up to a hundred.
+struct Charge;
+
+#[async_trait]
+impl WorkflowFn for Charge {
+    async fn run(&self, context: &mut WorkflowContext) -> Result<TaskPayload> {
+        let amount = parse(context.input())?;
+
+        context
+            .step("charge", || async move {
+                if amount > 100 {
+                    return Err(anyhow::anyhow!("card declined"));
+                }
+
+                Ok(format!("charged {}", amount).into_bytes())
+            })
+            .await
+    }
+}
+
+async fn start(store: &InMemoryTaskQueue, workflow_id: &str, input: &[u8]) -> Result<()> {
+    start_workflow(store, workflow_id, "adds", input).await
+}
+
+async fn start_workflow(
+    store: &InMemoryTaskQueue,
+    workflow_id: &str,
+    workflow_type: &str,
+    input: &[u8],
+) -> Result<()> {
+    let started = store
+        .start_workflow(&NewWorkflow {
+            workflow_id,
+            workflow_type,
+            queue_id: QUEUE,
+            partition_id: 0,
+            input,
+        })
+        .await?;
+
+    assert!(started);
+
+    Ok(())
+}
+
+/// Processes workflow tasks until none are left.
+async fn drain<Q: TaskQueue + Send + Sync>(store: &Q, worker: &WorkflowWorker<Q>) -> Result<()> {
+    while !store
+        .query_tasks(QUEUE, 0, TaskStatus::Pending, 1)
+        .await?
+        .is_empty()
+    {
+        store.process_tasks(QUEUE, 0, 10, worker).await?;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn each_step_runs_once_across_replays() -> Result<()> {
+    let store = Arc::new(InMemoryTaskQueue::new());
+    let adds = Adds::default();
+    let worker = WorkflowWorker::new(store.clone()).register("adds", adds.clone());
+
+    start(&store, "sum", b"5,3,3").await?;
+    drain(&*store, &worker).await?;
+
+    let workflow = store.get_workflow("sum").await?.expect("workflow exists");
+    assert_eq!(workflow.status, WorkflowStatus::Completed);
+    assert_eq!(workflow.result.as_deref(), Some(&b"11"[..]));
+
+    // The workflow was replayed from the top for every step, but each step ran once.
+    assert_eq!(adds.runs.load(Ordering::SeqCst), 3);
+
+    let history = store.workflow_history("sum").await?;
+    let kinds: Vec<WorkflowEventKind> = history.iter().map(|event| event.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            WorkflowEventKind::WorkflowStarted,
+            WorkflowEventKind::StepCompleted,
+            WorkflowEventKind::StepCompleted,
+            WorkflowEventKind::StepCompleted,
+            WorkflowEventKind::WorkflowCompleted,
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_steps_are_replayed_as_failures() -> Result<()> {
+    let store = Arc::new(InMemoryTaskQueue::new());
+    let adds = Adds::default();
+    let worker = WorkflowWorker::new(store.clone()).register("adds", adds.clone());
+
+    start(&store, "negative", b"5,-3,3").await?;
+    drain(&*store, &worker).await?;
+
+    let workflow = store
+        .get_workflow("negative")
+        .await?
+        .expect("workflow exists");
+    assert_eq!(workflow.status, WorkflowStatus::Failed);
+    assert_eq!(workflow.error.as_deref(), Some("negative number: -3"));
+    assert_eq!(adds.runs.load(Ordering::SeqCst), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn replays_that_take_other_steps_are_retried() -> Result<()> {
+    /// Takes a differently named step, as a redeploy could under a running workflow.
+    struct Renamed;
+
+    #[async_trait]
+    impl WorkflowFn for Renamed {
+        async fn run(&self, context: &mut WorkflowContext) -> Result<TaskPayload> {
+            context
+                .step("renamed", || async { Ok(b"8".to_vec()) })
+                .await
+        }
+    }
+
+    let store = Arc::new(InMemoryTaskQueue::new());
+    let adds = WorkflowWorker::new(store.clone()).register("adds", Adds::default());
+    let renamed = WorkflowWorker::new(store.clone()).register("adds", Renamed);
+
+    start(&store, "redeployed", b"5,3").await?;
+    store.process_tasks(QUEUE, 0, 10, &adds).await?;
+    store.process_tasks(QUEUE, 0, 10, &renamed).await?;
+
+    // The workflow task failed instead of the workflow.
+    let workflow = store
+        .get_workflow("redeployed")
+        .await?
+        .expect("workflow exists");
+    assert_eq!(workflow.status, WorkflowStatus::Running);
+    assert_eq!(workflow.last_event_id, 2);
+
+    let retried = store
+        .query_tasks(QUEUE, 0, TaskStatus::Scheduled, 10)
+        .await?;
+    assert_eq!(retried.len(), 1);
+    assert!(retried[0]
+        .last_error
+        .as_deref()
+        .is_some_and(|error| error.contains("not deterministic")));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn sleeping_workflows_wait_for_their_timer() -> Result<()> {
+    let store = Arc::new(InMemoryTaskQueue::new());
+    let reminder = Reminder::default();
+    let worker = WorkflowWorker::new(store.clone()).register("reminder", reminder.clone());
+
+    start_workflow(&store, "reminder", "reminder", b"").await?;
+    drain(&*store, &worker).await?;
+
+    // Asleep with no workflow task waiting, so no worker is held.
+    let workflow = store
+        .get_workflow("reminder")
+        .await?
+        .expect("workflow exists");
+    assert_eq!(workflow.status, WorkflowStatus::Running);
+    assert!(store
+        .due_workflow_timers(now_millis(), None, 10)
+        .await?
+        .is_empty());
+
+    // Once the hour has passed the timer fires and the workflow carries on.
+    let timers = store
+        .due_workflow_timers(now_millis() + 2 * 60 * 60 * 1_000, None, 10)
+        .await?;
+    assert_eq!(timers.len(), 1);
+    assert!(store.fire_workflow_timer(&timers[0]).await?);
+
+    drain(&*store, &worker).await?;
+
+    let workflow = store
+        .get_workflow("reminder")
+        .await?
+        .expect("workflow exists");
+    assert_eq!(workflow.status, WorkflowStatus::Completed);
+    assert_eq!(workflow.result.as_deref(), Some(&b"water the plants"[..]));
+    assert_eq!(reminder.runs.load(Ordering::SeqCst), 2);
+
+    let history = store.workflow_history("reminder").await?;
+    let kinds: Vec<WorkflowEventKind> = history.iter().map(|event| event.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            WorkflowEventKind::WorkflowStarted,
+            WorkflowEventKind::StepCompleted,
+            WorkflowEventKind::TimerStarted,
+            WorkflowEventKind::TimerFired,
+            WorkflowEventKind::StepCompleted,
+            WorkflowEventKind::WorkflowCompleted,
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn waiting_workflows_are_woken_by_their_signal() -> Result<()> {
+    let store = Arc::new(InMemoryTaskQueue::new());
+    let approval = Approval::default();
+    let worker = WorkflowWorker::new(store.clone()).register("approval", approval.clone());
+
+    start_workflow(&store, "post", "approval", b"").await?;
+    drain(&*store, &worker).await?;
+
+    // Blocked on the first approval with no workflow task waiting.
+    let workflow = store.get_workflow("post").await?.expect("workflow exists");
+    assert_eq!(workflow.awaiting_signal.as_deref(), Some("approve"));
+
+    let state = WorkflowState::new(workflow, store.workflow_history("post").await?);
+    assert_eq!(state.steps.len(), 1);
+    assert!(state.signals.is_empty());
+
+    store.signal_workflow("post", "approve", b"ann").await?;
+    drain(&*store, &worker).await?;
+
+    let workflow = store.get_workflow("post").await?.expect("workflow exists");
+    assert_eq!(workflow.status, WorkflowStatus::Running);
+    assert_eq!(workflow.awaiting_signal.as_deref(), Some("approve"));
+
+    store.signal_workflow("post", "approve", b"bob").await?;
+    drain(&*store, &worker).await?;
+
+    let workflow = store.get_workflow("post").await?.expect("workflow exists");
+    assert_eq!(workflow.status, WorkflowStatus::Completed);
+    assert_eq!(
+        workflow.result.as_deref(),
+        Some(&b"draft approved by ann and bob"[..])
+    );
+    assert_eq!(approval.runs.load(Ordering::SeqCst), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn signals_sent_before_the_wait_are_kept() -> Result<()> {
+    let store = Arc::new(InMemoryTaskQueue::new());
+    let worker = WorkflowWorker::new(store.clone()).register("approval", Approval::default());
+
+    start_workflow(&store, "post", "approval", b"").await?;
+    store.signal_workflow("post", "approve", b"ann").await?;
+    drain(&*store, &worker).await?;
+
+    // The first approval was waiting, the workflow only blocked on the second.
+    store.signal_workflow("post", "approve", b"bob").await?;
+    drain(&*store, &worker).await?;
+
+    let workflow = store.get_workflow("post").await?.expect("workflow exists");
+    assert_eq!(workflow.status, WorkflowStatus::Completed);
+    assert_eq!(
+        workflow.result.as_deref(),
+        Some(&b"draft approved by ann and bob"[..])
+    );
+
+    let history = store.workflow_history("post").await?;
+    let kinds: Vec<WorkflowEventKind> = history.iter().map(|event| event.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            WorkflowEventKind::WorkflowStarted,
+            WorkflowEventKind::SignalReceived,
+            WorkflowEventKind::StepCompleted,
+            WorkflowEventKind::SignalAwaited,
+            WorkflowEventKind::SignalReceived,
+            WorkflowEventKind::StepCompleted,
+            WorkflowEventKind::WorkflowCompleted,
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn parents_wait_for_their_child_workflows() -> Result<()> {
+    let store = Arc::new(InMemoryTaskQueue::new());
+    let order = Order::default();
+    let worker = WorkflowWorker::new(store.clone())
+        .register("order", order.clone())
+        .register("charge", Charge);
+
+    start_workflow(&store, "order", "order", b"50").await?;
+    drain(&*store, &worker).await?;
+
+    let workflow = store.get_workflow("order").await?.expect("workflow exists");
+    assert_eq!(workflow.status, WorkflowStatus::Completed);
+    assert_eq!(workflow.result.as_deref(), Some(&b"charged 50"[..]));
+    assert!(order.undone.lock().unwrap().is_empty());
+
+    let child = store
+        .get_workflow("order/4")
+        .await?
+        .expect("child workflow exists");
+    assert_eq!(child.status, WorkflowStatus::Completed);
+    assert_eq!(child.parent_workflow_id.as_deref(), Some("order"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_workflows_undo_their_steps_last_first() -> Result<()> {
+    let store = Arc::new(InMemoryTaskQueue::new());
+    let order = Order::default();
+    let worker = WorkflowWorker::new(store.clone())
+        .register("order", order.clone())
+        .register("charge", Charge);
+
+    start_workflow(&store, "order", "order", b"500").await?;
+    drain(&*store, &worker).await?;
+
+    let workflow = store.get_workflow("order").await?.expect("workflow exists");
+    assert_eq!(workflow.status, WorkflowStatus::Failed);
+    assert_eq!(workflow.error.as_deref(), Some("card declined"));
+
+    // Each compensation ran once, across however many replays it took.
+    assert_eq!(*order.undone.lock().unwrap(), vec!["notify", "reserve"]);
+
+    let history = store.workflow_history("order").await?;
+    let compensations: Vec<(WorkflowEventKind, Option<&str>)> = history
+        .iter()
+        .skip(3)
+        .map(|event| (event.kind, event.name.as_deref()))
+        .collect();
+    assert_eq!(
+        compensations,
+        vec![
+            (WorkflowEventKind::ChildWorkflowStarted, Some("charge")),
+            (WorkflowEventKind::ChildWorkflowFailed, Some("order/4")),
+            (WorkflowEventKind::CompensationCompleted, Some("notify")),
+            (WorkflowEventKind::CompensationCompleted, Some("reserve")),
+            (WorkflowEventKind::WorkflowFailed, None),
+        ]
+    );
+
+    Ok(())
+}